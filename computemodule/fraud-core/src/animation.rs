@@ -0,0 +1,92 @@
+use crate::limits::InputLimits;
+use crate::pages::PageImage;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Delay, Frame, Frames, ImageDecoder, ImageOutputFormat, ImageResult, RgbaImage};
+use log::warn;
+use std::io::Cursor;
+
+// Frames past this are not analyzed, every frame costs a full pipeline run
+pub(crate) const MAX_FRAMES: usize = 300;
+
+// With the size of their canvas, frames are only decoded once iterated
+fn frames(data: &[u8]) -> Option<ImageResult<((u32, u32), Frames<'_>)>> {
+    let cursor = Cursor::new(data);
+    Some(if data.starts_with(b"GIF8") {
+        GifDecoder::new(cursor).map(|decoder| (decoder.dimensions(), decoder.into_frames()))
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        match PngDecoder::new(cursor) {
+            Ok(decoder) if decoder.is_apng() => Ok((decoder.dimensions(), decoder.apng().into_frames())),
+            Ok(_) => return None,
+            Err(err) => Err(err),
+        }
+    } else {
+        return None;
+    })
+}
+
+fn encode(frame: Frame, page: usize) -> Result<PageImage, String> {
+    let delay = frame.delay();
+    let mut encoded = Cursor::new(Vec::new());
+    frame.into_buffer().write_to(&mut encoded, ImageOutputFormat::Png).map_err(|err| err.to_string())?;
+    Ok(PageImage { page, data: encoded.into_inner(), delay: Some(delay) })
+}
+
+/// How many frames of `width` by `height`, up to MAX_FRAMES, fit the memory
+/// budget of `limits` together, at least one.
+pub(crate) fn max_frames(limits: &InputLimits, width: u32, height: u32) -> usize {
+    let frame_bytes = (u64::from(width) * u64::from(height) * 4).max(1);
+    limits.max_memory_bytes.map_or(MAX_FRAMES, |budget| {
+        MAX_FRAMES.min(usize::try_from(budget / frame_bytes).unwrap_or(usize::MAX).max(1))
+    })
+}
+
+/// Splits an animated GIF or APNG into its composited frames, re-encoded as
+/// PNG one at a time, or returns None for still images. Canvases `limits`
+/// doesn't allow are refused, and only as many frames are decoded as fit
+/// its memory budget together.
+pub fn animation_frames(data: &[u8], limits: &InputLimits) -> Option<Result<Vec<PageImage>, String>> {
+    let ((width, height), frames) = match frames(data)? {
+        Ok(frames) => frames,
+        Err(err) => return Some(Err(format!("Invalid animation: {}", err))),
+    };
+    if let Err(problem) = limits.check_dimensions(width, height) {
+        return Some(Err(problem));
+    }
+    let max_frames = max_frames(limits, width, height);
+    // Still images are only told apart by their missing second frame, the first is encoded once it comes
+    let (mut first, mut pages) = (None, Vec::new());
+    for (index, frame) in frames.enumerate() {
+        if index == max_frames {
+            warn!("Only analyzing the first {} frames of the animation", max_frames);
+            break;
+        }
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => return Some(Err(format!("Invalid animation: {}", err))),
+        };
+        if index == 0 {
+            first = Some(frame);
+            continue;
+        }
+        for (frame, page) in first.take().map(|first| (first, 1)).into_iter().chain([(frame, index + 1)]) {
+            match encode(frame, page) {
+                Ok(page) => pages.push(page),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+    (!pages.is_empty()).then_some(Ok(pages))
+}
+
+/// Encodes frames as a looping GIF. APNG inputs come back as GIFs as well,
+/// the image crate can't write APNG.
+pub fn encode_gif(frames: Vec<(RgbaImage, Delay)>) -> ImageResult<Vec<u8>> {
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut encoded);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.into_iter().map(|(buffer, delay)| Frame::from_parts(buffer, 0, 0, delay)))?;
+    }
+    Ok(encoded)
+}
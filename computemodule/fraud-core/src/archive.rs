@@ -0,0 +1,80 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::{Cursor, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const MAX_ENTRIES: usize = 500;
+// Bounds how far an entry may inflate, archives are small on the wire but not necessarily once extracted
+const MAX_ENTRY_BYTES: u64 = 100 * 1024 * 1024;
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// The media type of an output image or archive.
+pub fn output_mime_type(data: &[u8]) -> &'static str {
+    if is_zip(data) {
+        return "application/zip";
+    }
+    image::guess_format(data).map_or("application/octet-stream", |format| format.to_mime_type())
+}
+
+// The path of a file within an archive and its contents, or why they could not be read
+type Entry = (String, Result<Vec<u8>, String>);
+
+/// The files of a ZIP archive with their contents, or why an entry could not be read.
+/// Directories and macOS resource forks are skipped.
+pub(crate) fn zip_entries(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|err| format!("Invalid ZIP archive: {}", err))?;
+    if archive.len() > MAX_ENTRIES {
+        return Err(format!("ZIP archive has {} entries, at most {} are supported", archive.len(), MAX_ENTRIES));
+    }
+    let too_large = || format!("Entry is larger than {} bytes", MAX_ENTRY_BYTES);
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let name = archive
+            .name_for_index(index)
+            .and_then(Result::ok)
+            .map_or_else(|| format!("Entry {}", index + 1), Cow::into_owned);
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(err) => {
+                entries.push((name, Err(format!("Unreadable entry: {}", err))));
+                continue;
+            }
+        };
+        if entry.is_dir() || name.starts_with("__MACOSX/") {
+            continue;
+        }
+        // Entry names end up in the output archive, which clients may extract
+        if entry.enclosed_name().is_none() {
+            entries.push((name, Err(String::from("Entry path escapes the archive"))));
+            continue;
+        }
+        if entry.size() > MAX_ENTRY_BYTES {
+            entries.push((name, Err(too_large())));
+            continue;
+        }
+        let mut contents = Vec::new();
+        let read = (&mut entry).take(MAX_ENTRY_BYTES + 1).read_to_end(&mut contents);
+        let contents = match read {
+            Ok(_) if contents.len() as u64 > MAX_ENTRY_BYTES => Err(too_large()),
+            Ok(_) => Ok(contents),
+            Err(err) => Err(format!("Unreadable entry: {}", err)),
+        };
+        entries.push((name, contents));
+    }
+    Ok(entries)
+}
+
+/// Writes files into a new ZIP archive, stored as is since images are compressed already.
+pub(crate) fn write_zip(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, contents) in files {
+        writer.start_file(name, options)?;
+        writer.write_all(&contents)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
@@ -0,0 +1,105 @@
+use crate::blocks::BlockGrid;
+use crate::dct::JpegLuma;
+use crate::detector::{DetectionReport, Detector};
+use image::DynamicImage;
+
+/// Block artifact grid extraction: JPEG compression leaves small luminance
+/// jumps on its 8x8 block boundaries. Each block's grid phase is estimated
+/// from where those jumps peak, and blocks whose confident phase differs from
+/// the dominant one were likely pasted in from another JPEG.
+pub struct BagDetector {
+    pub block_size: u32,
+    /// Minimum peak of the boundary jumps over their median, relative to the
+    /// mean jump, for a block's phase to count.
+    pub min_strength: f64,
+    /// Minimum fraction of all blocks showing the dominant grid, below which
+    /// the image isn't treated as JPEG compressed.
+    pub min_grid_fraction: f64,
+    pub min_blocks: usize,
+}
+
+impl Default for BagDetector {
+    fn default() -> Self {
+        BagDetector { block_size: 32, min_strength: 0.2, min_grid_fraction: 0.3, min_blocks: 5 }
+    }
+}
+
+struct Phase {
+    x: u32,
+    y: u32,
+}
+
+// Strongest of the 8 phases of `jumps` (indexed by position) with its strength
+fn strongest_phase(jumps: &[f64; 8], counts: &[usize; 8]) -> (u32, f64) {
+    let means: Vec<f64> = jumps.iter().zip(counts).map(|(j, &c)| if c > 0 { j / c as f64 } else { 0.0 }).collect();
+    let mut sorted = means.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let (median, mean) = (sorted[4], means.iter().sum::<f64>() / 8.0);
+    let (phase, peak) = means.iter().enumerate().fold((0, 0.0), |best, (p, &m)| if m > best.1 { (p, m) } else { best });
+    (phase as u32, if mean > 0.0 { (peak - median) / mean } else { 0.0 })
+}
+
+impl BagDetector {
+    // Grid phase of the block, or None when it shows no clear grid
+    fn block_phase(&self, luma: &JpegLuma, x0: u32, y0: u32, x1: u32, y1: u32) -> Option<Phase> {
+        let (mut horizontal, mut vertical) = ([0.0; 8], [0.0; 8]);
+        let (mut horizontal_counts, mut vertical_counts) = ([0; 8], [0; 8]);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // A jump between x and x + 1 means a block starts at x + 1
+                if x + 1 < luma.width {
+                    let phase = ((x + 1) % 8) as usize;
+                    horizontal[phase] += (luma.get(x + 1, y) - luma.get(x, y)).abs();
+                    horizontal_counts[phase] += 1;
+                }
+                if y + 1 < luma.height {
+                    let phase = ((y + 1) % 8) as usize;
+                    vertical[phase] += (luma.get(x, y + 1) - luma.get(x, y)).abs();
+                    vertical_counts[phase] += 1;
+                }
+            }
+        }
+        let (x, x_strength) = strongest_phase(&horizontal, &horizontal_counts);
+        let (y, y_strength) = strongest_phase(&vertical, &vertical_counts);
+        (x_strength >= self.min_strength && y_strength >= self.min_strength).then_some(Phase { x, y })
+    }
+}
+
+impl Detector for BagDetector {
+    fn name(&self) -> &'static str {
+        "bag"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "block_grid"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let luma = JpegLuma::from_image(img);
+        // Each block holds its phase as y * 8 + x, NaN without a clear grid
+        let grid = BlockGrid::from_fn(luma.width, luma.height, self.block_size, |x0, y0, x1, y1| {
+            self.block_phase(&luma, x0, y0, x1, y1).map_or(f64::NAN, |phase| (phase.y * 8 + phase.x) as f64)
+        });
+
+        let mut votes = [0usize; 64];
+        for &code in grid.values.iter().filter(|code| !code.is_nan()) {
+            votes[code as usize] += 1;
+        }
+        let (dominant, count) = votes.iter().enumerate().fold((0, 0), |best, (code, &n)| if n > best.1 { (code, n) } else { best });
+        // Without a clear majority there is no grid to compare against
+        let confident: usize = votes.iter().sum();
+        if count * 2 < confident || (count as f64) < self.min_grid_fraction * grid.values.len() as f64 {
+            return DetectionReport::default();
+        }
+
+        let mask: Vec<bool> = grid.values.iter().map(|&code| !code.is_nan() && code as usize != dominant).collect();
+        let mut findings = Vec::new();
+        if dominant != 0 {
+            findings.push(format!("dominant block grid is shifted to ({}, {})", dominant % 8, dominant / 8));
+        }
+        // Blocks off the dominant grid are as trustworthy as the grid itself
+        let agreement = count as f64 / confident as f64;
+        let regions = grid.regions_with(&mask, self.min_blocks, |_| agreement);
+        DetectionReport { regions, findings, ..DetectionReport::default() }
+    }
+}
@@ -0,0 +1,27 @@
+use crate::font::{draw_text, text_width, GLYPH_HEIGHT};
+use crate::report::timestamp;
+use image::{Rgba, RgbaImage};
+use std::time::SystemTime;
+
+const BACKGROUND_ALPHA: f64 = 0.6;
+// The banner text spans at most this fraction of the image width
+const MAX_TEXT_FRACTION: f64 = 0.9;
+
+/// Stamps e.g. `EDITED - 4 REGIONS - 2024-05-01` on a dark strip along the
+/// bottom of the image, so exported images describe themselves.
+pub(crate) fn stamp_banner(image: &mut RgbaImage, result: &str, regions: usize, now: SystemTime) {
+    let noun = if regions == 1 { "region" } else { "regions" };
+    let text = format!("{} - {} {} - {}", result, regions, noun, &timestamp(now)[..10]);
+    let (width, height) = image.dimensions();
+    let fits = (width as f64 * MAX_TEXT_FRACTION / text_width(&text, 1) as f64) as u32;
+    let scale = (width / 400).clamp(1, fits.max(1));
+    let strip = ((GLYPH_HEIGHT + 4) * scale).min(height);
+    for y in height - strip..height {
+        for x in 0..width {
+            let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+            let darken = |channel: u8| (channel as f64 * (1.0 - BACKGROUND_ALPHA)).round() as u8;
+            image.put_pixel(x, y, Rgba([darken(r), darken(g), darken(b), a]));
+        }
+    }
+    draw_text(image, 2 * scale, height - strip + 2 * scale, &text, scale, Rgba([255, 255, 255, 255]));
+}
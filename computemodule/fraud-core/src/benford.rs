@@ -0,0 +1,108 @@
+use crate::dct::{dct_block, estimate_step, JpegLuma, LOW_FREQUENCIES};
+use crate::detector::{DetectionReport, Detector};
+use image::DynamicImage;
+
+const MAX_STEP: u32 = 40;
+
+/// Checks the first digits of the quantized DCT coefficients against the
+/// generalized Benford law they follow after a single JPEG compression.
+/// Recompression and local edits break the law, which is turned into an
+/// image level manipulation probability.
+pub struct BenfordDetector {
+    /// Upper bound on the number of 8x8 blocks sampled.
+    pub max_blocks: usize,
+    /// Minimum number of nonzero coefficients needed for a reliable estimate.
+    pub min_digits: usize,
+    /// Fit error at which the manipulation probability reaches one half.
+    pub divergence_midpoint: f64,
+}
+
+impl Default for BenfordDetector {
+    fn default() -> Self {
+        BenfordDetector { max_blocks: 8000, min_digits: 2000, divergence_midpoint: 0.08 }
+    }
+}
+
+fn first_digit(value: f64) -> Option<usize> {
+    let mut value = value.abs();
+    if value < 1.0 {
+        return None;
+    }
+    while value >= 10.0 {
+        value /= 10.0;
+    }
+    Some(value as usize)
+}
+
+// p(d) = N log10(1 + 1 / (s + d^q)) with N normalizing the distribution
+fn generalized_benford(q: f64, s: f64) -> [f64; 9] {
+    let mut p = [0.0; 9];
+    for (d, p) in p.iter_mut().enumerate() {
+        *p = (1.0 + 1.0 / (s + ((d + 1) as f64).powf(q))).log10();
+    }
+    let total: f64 = p.iter().sum();
+    p.map(|p| p / total)
+}
+
+// Smallest chi-square like distance to the generalized Benford law over a grid of (q, s)
+fn fit_divergence(observed: &[f64; 9]) -> f64 {
+    let mut best = f64::INFINITY;
+    for qi in 0..=60 {
+        for si in 0..=48 {
+            let (q, s) = (0.1 + qi as f64 * 0.05, -0.9 + si as f64 * 0.05);
+            let expected = generalized_benford(q, s);
+            let divergence: f64 = observed.iter().zip(&expected).map(|(o, e)| (o - e).powi(2) / e).sum();
+            best = best.min(divergence);
+        }
+    }
+    best
+}
+
+impl BenfordDetector {
+    fn digit_distribution(&self, luma: &JpegLuma) -> Option<[f64; 9]> {
+        let (cols, rows) = (luma.width / 8, luma.height / 8);
+        let total = (cols * rows) as usize;
+        let stride = total.div_ceil(self.max_blocks.max(1)).max(1);
+        let coefficients: Vec<[f64; 9]> = (0..total)
+            .step_by(stride)
+            .map(|index| {
+                let dct = dct_block(luma, index as u32 % cols * 8, index as u32 / cols * 8);
+                LOW_FREQUENCIES.map(|(v, u)| dct[v][u])
+            })
+            .collect();
+
+        let mut counts = [0usize; 9];
+        for frequency in 0..LOW_FREQUENCIES.len() {
+            let values: Vec<f64> = coefficients.iter().map(|c| c[frequency]).collect();
+            let step = estimate_step(&values, MAX_STEP) as f64;
+            for value in values {
+                if let Some(digit) = first_digit((value / step).round()) {
+                    counts[digit - 1] += 1;
+                }
+            }
+        }
+        let digits: usize = counts.iter().sum();
+        (digits >= self.min_digits).then(|| counts.map(|count| count as f64 / digits as f64))
+    }
+}
+
+impl Detector for BenfordDetector {
+    fn name(&self) -> &'static str {
+        "benford"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let luma = JpegLuma::from_image(img);
+        let Some(observed) = self.digit_distribution(&luma) else {
+            return DetectionReport::default();
+        };
+        let divergence = fit_divergence(&observed);
+        // Logistic in the log divergence, one half at the midpoint
+        let score = 1.0 / (1.0 + (self.divergence_midpoint / divergence.max(f64::EPSILON)).powi(2));
+        let mut findings = Vec::new();
+        if score >= 0.5 {
+            findings.push(format!("DCT coefficient first digits deviate from Benford's law, divergence {:.3}", divergence));
+        }
+        DetectionReport { findings, score: Some(score), ..DetectionReport::default() }
+    }
+}
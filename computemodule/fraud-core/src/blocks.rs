@@ -0,0 +1,145 @@
+use crate::heatmap::suspicion;
+use crate::{Heatmap, Point, Region};
+use rayon::prelude::*;
+
+// Deviation from the median, in robust standard deviations, of a region with 0.5 confidence
+const HALF_CONFIDENCE_DEVIATIONS: f64 = 4.0;
+
+/// A per-block statistic computed over a regular grid of square image blocks.
+/// Blocks without an estimate hold `NaN`, are left out of the statistics and
+/// are never flagged.
+pub(crate) struct BlockGrid {
+    pub cols: u32,
+    pub rows: u32,
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f64>,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+impl BlockGrid {
+    /// Computes `f(x0, y0, x1, y1)` for every full block of the image, where
+    /// `(x1, y1)` is exclusive, spread over the rayon thread pool. Partial blocks at the right and bottom edges
+    /// are skipped.
+    pub fn from_fn(width: u32, height: u32, size: u32, f: impl Fn(u32, u32, u32, u32) -> f64 + Sync) -> BlockGrid {
+        let cols = width / size;
+        let rows = height / size;
+        let values = (0..cols * rows)
+            .into_par_iter()
+            .map(|block| {
+                let (x0, y0) = (block % cols * size, block / cols * size);
+                f(x0, y0, x0 + size, y0 + size)
+            })
+            .collect();
+        BlockGrid { cols, rows, size, width, height, values }
+    }
+
+    /// Median and MAD based standard deviation estimate of the block values.
+    pub fn robust_stats(&self) -> (f64, f64) {
+        let mut values: Vec<f64> = self.values.iter().copied().filter(|v| !v.is_nan()).collect();
+        let center = median(&mut values);
+        let mut deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+        (center, 1.4826 * median(&mut deviations))
+    }
+
+    /// Flags blocks more than `k` robust standard deviations and at least
+    /// `min_delta` above the median.
+    pub fn high_outliers(&self, k: f64, min_delta: f64) -> Vec<bool> {
+        let (center, sigma) = self.robust_stats();
+        let threshold = center + (k * sigma).max(min_delta);
+        self.values.iter().map(|&v| v > threshold).collect()
+    }
+
+    /// Like [`BlockGrid::high_outliers`] but flags deviations in both directions.
+    pub fn outliers(&self, k: f64, min_delta: f64) -> Vec<bool> {
+        let (center, sigma) = self.robust_stats();
+        let threshold = (k * sigma).max(min_delta);
+        self.values.iter().map(|&v| (v - center).abs() > threshold).collect()
+    }
+
+    /// Per-block suspicion of the blocks [`BlockGrid::high_outliers`] flags,
+    /// or [`BlockGrid::outliers`] when `two_sided`, see [`suspicion`].
+    pub fn heatmap(&self, k: f64, min_delta: f64, two_sided: bool) -> Heatmap {
+        let (center, sigma) = self.robust_stats();
+        let threshold = (k * sigma).max(min_delta);
+        self.heatmap_with(|value| {
+            let deviation = if two_sided { (value - center).abs() } else { value - center };
+            suspicion(deviation, threshold)
+        })
+    }
+
+    /// Like [`BlockGrid::heatmap`] with the suspicion computed from each block's
+    /// value, blocks without an estimate get none.
+    pub fn heatmap_with(&self, suspicion: impl Fn(f64) -> f64) -> Heatmap {
+        let values = self.values.iter().map(|&value| if value.is_nan() { 0.0 } else { suspicion(value) }).collect();
+        let size = self.size as f64;
+        Heatmap { cols: self.cols, rows: self.rows, block_width: size, block_height: size, values }
+    }
+
+    /// Bounding boxes of 4-connected groups of flagged blocks with at least
+    /// `min_blocks` blocks. The confidence grows with how many robust standard
+    /// deviations the group's mean lies from the median.
+    pub fn regions(&self, mask: &[bool], min_blocks: usize) -> Vec<Region> {
+        let (center, sigma) = self.robust_stats();
+        self.regions_with(mask, min_blocks, |mean| {
+            let z = (mean - center).abs() / sigma;
+            if z.is_finite() { z / (z + HALF_CONFIDENCE_DEVIATIONS) } else { 1.0 }
+        })
+    }
+
+    /// Like [`BlockGrid::regions`] with the confidence computed from the mean
+    /// value of each group.
+    pub fn regions_with(&self, mask: &[bool], min_blocks: usize, confidence: impl Fn(f64) -> f64) -> Vec<Region> {
+        let mut seen = vec![false; mask.len()];
+        let mut regions = Vec::new();
+        for start in 0..mask.len() {
+            if !mask[start] || seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut stack = vec![start];
+            let (mut count, mut sum) = (0, 0.0);
+            let (mut min_col, mut min_row, mut max_col, mut max_row) = (u32::MAX, u32::MAX, 0, 0);
+            while let Some(index) = stack.pop() {
+                count += 1;
+                sum += self.values[index];
+                let (col, row) = (index as u32 % self.cols, index as u32 / self.cols);
+                min_col = min_col.min(col);
+                min_row = min_row.min(row);
+                max_col = max_col.max(col);
+                max_row = max_row.max(row);
+                let neighbours = [
+                    (col > 0).then(|| index - 1),
+                    (col + 1 < self.cols).then(|| index + 1),
+                    (row > 0).then(|| index - self.cols as usize),
+                    (row + 1 < self.rows).then(|| index + self.cols as usize),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if mask[next] && !seen[next] {
+                        seen[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            if count >= min_blocks {
+                regions.push(Region {
+                    start: Point { x: min_col * self.size, y: min_row * self.size },
+                    end: Point {
+                        x: ((max_col + 1) * self.size).min(self.width) - 1,
+                        y: ((max_row + 1) * self.size).min(self.height) - 1,
+                    },
+                    confidence: confidence(sum / count as f64),
+                });
+            }
+        }
+        regions
+    }
+}
@@ -0,0 +1,91 @@
+use image::{DynamicImage, RgbaImage};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const DEFAULT_BUFFER_POOL_BYTES: usize = 256 * 1024 * 1024;
+
+/// Large byte buffers given back by finished jobs for the next ones to reuse,
+/// instead of allocating decoded inputs, RGBA images and encoded outputs anew
+/// for every job. Holds at most `max_bytes` of capacity, dropping the
+/// smallest buffers past that.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_bytes: AtomicUsize,
+}
+
+/// The pool shared by every job of the process.
+pub static BUFFERS: BufferPool = BufferPool::new(DEFAULT_BUFFER_POOL_BYTES);
+
+impl BufferPool {
+    pub const fn new(max_bytes: usize) -> BufferPool {
+        BufferPool { buffers: Mutex::new(Vec::new()), max_bytes: AtomicUsize::new(max_bytes) }
+    }
+
+    /// 0 disables pooling.
+    pub fn set_max_bytes(&self, max_bytes: usize) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        self.evict(&mut self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    }
+
+    /// An empty buffer, the smallest pooled one holding `capacity` bytes or
+    /// else the largest, grown to it.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut buffers = self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Sorted by capacity, see give
+        let fits = buffers.iter().position(|buffer| buffer.capacity() >= capacity);
+        let mut buffer = match fits.or(buffers.len().checked_sub(1)) {
+            Some(index) => buffers.remove(index),
+            None => Vec::new(),
+        };
+        drop(buffers);
+        buffer.clear();
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    pub fn give(&self, buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        let mut buffers = self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = buffers.partition_point(|pooled| pooled.capacity() < buffer.capacity());
+        buffers.insert(index, buffer);
+        self.evict(&mut buffers);
+    }
+
+    fn evict(&self, buffers: &mut Vec<Vec<u8>>) {
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        let mut total: usize = buffers.iter().map(Vec::capacity).sum();
+        while total > max_bytes {
+            total -= buffers.remove(0).capacity();
+        }
+    }
+}
+
+/// Like [`DynamicImage::to_rgba8`], into a pooled buffer.
+pub(crate) fn to_rgba(image: &DynamicImage) -> RgbaImage {
+    let (width, height) = (image.width(), image.height());
+    let mut buffer = BUFFERS.take(width as usize * height as usize * 4);
+    match image {
+        DynamicImage::ImageRgba8(rgba) => buffer.extend_from_slice(rgba.as_raw()),
+        // Filled opaque first, then copied into whole pixels the compiler vectorizes
+        DynamicImage::ImageRgb8(rgb) => {
+            buffer.resize(rgb.as_raw().len() / 3 * 4, u8::MAX);
+            for (rgba, rgb) in buffer.chunks_exact_mut(4).zip(rgb.as_raw().chunks_exact(3)) {
+                rgba[..3].copy_from_slice(rgb);
+            }
+        }
+        DynamicImage::ImageLuma8(gray) => {
+            buffer.resize(gray.as_raw().len() * 4, u8::MAX);
+            for (rgba, &luma) in buffer.chunks_exact_mut(4).zip(gray.as_raw()) {
+                rgba[..3].fill(luma);
+            }
+        }
+        // Rare enough to convert as usual
+        _ => {
+            BUFFERS.give(buffer);
+            return image.to_rgba8();
+        }
+    }
+    RgbaImage::from_raw(width, height, buffer).expect("The buffer holds every pixel")
+}
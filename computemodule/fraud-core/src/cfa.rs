@@ -0,0 +1,102 @@
+use crate::blocks::BlockGrid;
+use crate::heatmap::suspicion;
+use crate::detector::{DetectionReport, Detector};
+use image::{DynamicImage, RgbImage};
+
+/// Checks the traces left by Bayer color filter array demosaicing.
+///
+/// Cameras only sample green on one checkerboard lattice and interpolate the
+/// other, so the interpolated lattice is predicted from its neighbours much
+/// better than the sampled one. Blocks where that asymmetry is missing or
+/// flipped relative to the rest of the image did not go through the same
+/// demosaicing, a strong indicator of spliced content.
+pub struct CfaDetector {
+    pub block_size: u32,
+    /// Minimum median log variance ratio for the image to count as demosaiced at all.
+    pub min_pattern_strength: f64,
+    /// Fraction of the image's pattern strength below which a block counts as broken.
+    pub broken_fraction: f64,
+    pub min_blocks: usize,
+}
+
+impl Default for CfaDetector {
+    fn default() -> Self {
+        CfaDetector { block_size: 32, min_pattern_strength: 0.3, broken_fraction: 0.2, min_blocks: 4 }
+    }
+}
+
+const ROUNDING_VARIANCE: f64 = 1.0 / 12.0;
+
+fn green_prediction_error(rgb: &RgbImage, x: u32, y: u32) -> f64 {
+    let g = |x: u32, y: u32| rgb.get_pixel(x, y).0[1] as f64;
+    g(x, y) - (g(x - 1, y) + g(x + 1, y) + g(x, y - 1) + g(x, y + 1)) / 4.0
+}
+
+impl CfaDetector {
+    // Log ratio of the prediction error variance on the odd lattice over the even one
+    fn lattice_ratios(&self, rgb: &RgbImage) -> BlockGrid {
+        let (width, height) = rgb.dimensions();
+        BlockGrid::from_fn(width, height, self.block_size, |x0, y0, x1, y1| {
+            let mut energy = [0.0f64; 2];
+            let mut counts = [0usize; 2];
+            for y in y0.max(1)..y1.min(height - 1) {
+                for x in x0.max(1)..x1.min(width - 1) {
+                    let green = rgb.get_pixel(x, y).0[1];
+                    // Clipped pixels break the interpolation model
+                    if green == 0 || green == 255 {
+                        continue;
+                    }
+                    let lattice = ((x + y) % 2) as usize;
+                    energy[lattice] += green_prediction_error(rgb, x, y).powi(2);
+                    counts[lattice] += 1;
+                }
+            }
+            if counts[0] < 64 || counts[1] < 64 {
+                return f64::NAN;
+            }
+            let even = energy[0] / counts[0] as f64;
+            let odd = energy[1] / counts[1] as f64;
+            // Flat blocks carry no demosaicing traces
+            if even.max(odd) < 1.0 {
+                return f64::NAN;
+            }
+            // Perfectly interpolated lattices only keep the rounding error of 8 bit pixels
+            (odd.max(ROUNDING_VARIANCE) / even.max(ROUNDING_VARIANCE)).ln()
+        })
+    }
+}
+
+impl Detector for CfaDetector {
+    fn name(&self) -> &'static str {
+        "cfa"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "demosaicing"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let rgb = img.to_rgb8();
+        let mut report = DetectionReport::default();
+        if rgb.width() < self.block_size || rgb.height() < self.block_size {
+            return report;
+        }
+        let grid = self.lattice_ratios(&rgb);
+        let (pattern, _) = grid.robust_stats();
+        // Resized or heavily compressed images lose the pattern everywhere
+        if pattern.abs() < self.min_pattern_strength {
+            report.findings.push(String::from("no demosaicing pattern found"));
+            return report;
+        }
+        let mask: Vec<bool> = grid
+            .values
+            .iter()
+            .map(|ratio| ratio * pattern.signum() < self.broken_fraction * pattern.abs())
+            .collect();
+        report.regions = grid.regions(&mask, self.min_blocks);
+        // How much of the pattern a block lost, past 1 when it is flipped
+        let lost = |ratio: f64| 1.0 - ratio * pattern.signum() / pattern.abs();
+        report.heatmap = Some(grid.heatmap_with(|ratio| suspicion(lost(ratio), 1.0 - self.broken_fraction)));
+        report
+    }
+}
@@ -0,0 +1,88 @@
+use crate::RegionReport;
+use crate::result::{intern, Name};
+use serde::{Deserialize, Serialize};
+
+/// Detections in the COCO object detection format, with a category per region type.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CocoImage {
+    pub id: u64,
+    /// The id the image was analyzed under, e.g. `<job id> Page 2 image 1`.
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CocoAnnotation {
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: u64,
+    /// `[x, y, width, height]` in pixels from the top left corner.
+    pub bbox: [u32; 4],
+    pub area: u64,
+    /// The region's confidence, as in COCO detection results.
+    pub score: f64,
+    pub iscrowd: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CocoCategory {
+    pub id: u64,
+    #[serde(deserialize_with = "intern")]
+    pub name: Name,
+    /// The detector reporting regions of this type.
+    #[serde(deserialize_with = "intern")]
+    pub supercategory: Name,
+}
+
+impl CocoDataset {
+    /// An empty dataset with a category for every `(region type, detector)`.
+    pub(crate) fn new(categories: impl IntoIterator<Item = (&'static str, &'static str)>) -> Self {
+        let mut dataset = CocoDataset::default();
+        for (name, supercategory) in categories {
+            if dataset.categories.iter().all(|category| category.name != name) {
+                let id = dataset.categories.len() as u64 + 1;
+                dataset.categories.push(CocoCategory { id, name, supercategory });
+            }
+        }
+        dataset
+    }
+
+    pub(crate) fn add_image(&mut self, file_name: &str, width: u32, height: u32, regions: &[RegionReport]) {
+        let image_id = self.images.len() as u64 + 1;
+        self.images.push(CocoImage { id: image_id, file_name: file_name.to_string(), width, height });
+        for report in regions {
+            let Some(category) = self.categories.iter().find(|category| category.name == report.kind) else {
+                continue;
+            };
+            let region = report.region;
+            let (w, h) = (region.end.x - region.start.x + 1, region.end.y - region.start.y + 1);
+            self.annotations.push(CocoAnnotation {
+                id: self.annotations.len() as u64 + 1,
+                image_id,
+                category_id: category.id,
+                bbox: [region.start.x, region.start.y, w, h],
+                area: region.area(),
+                score: region.confidence,
+                iscrowd: 0,
+            });
+        }
+    }
+
+    /// Appends the images and annotations of a dataset with the same categories, renumbering them.
+    pub(crate) fn merge(&mut self, other: CocoDataset) {
+        let offset = self.images.len() as u64;
+        self.images.extend(other.images.into_iter().map(|image| CocoImage { id: image.id + offset, ..image }));
+        for annotation in other.annotations {
+            let id = self.annotations.len() as u64 + 1;
+            self.annotations.push(CocoAnnotation { id, image_id: annotation.image_id + offset, ..annotation });
+        }
+    }
+}
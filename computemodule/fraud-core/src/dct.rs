@@ -0,0 +1,98 @@
+use image::DynamicImage;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+/// Low frequency AC coefficients in zig-zag order as `(row, col)`; they
+/// survive quantization best and carry the clearest quantization traces.
+pub(crate) const LOW_FREQUENCIES: [(usize, usize); 9] = [(0, 1), (1, 0), (2, 0), (1, 1), (0, 2), (0, 3), (1, 2), (2, 1), (3, 0)];
+
+pub(crate) fn cosine_table() -> &'static [[f64; 8]; 8] {
+    static TABLE: OnceLock<[[f64; 8]; 8]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [[0.0; 8]; 8];
+        for (u, row) in table.iter_mut().enumerate() {
+            let scale = if u == 0 { (1.0f64 / 8.0).sqrt() } else { (2.0f64 / 8.0).sqrt() };
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = scale * ((2 * x + 1) as f64 * u as f64 * PI / 16.0).cos();
+            }
+        }
+        table
+    })
+}
+
+/// Unrounded luminance using the BT.601 weights of JPEG's YCbCr conversion, so
+/// DCT coefficients line up with the quantization of the Y channel.
+pub(crate) struct JpegLuma {
+    pub width: u32,
+    pub height: u32,
+    data: Vec<f64>,
+}
+
+impl JpegLuma {
+    pub fn from_image(img: &DynamicImage) -> JpegLuma {
+        let rgb = img.to_rgb8();
+        let data = rgb
+            .pixels()
+            .map(|p| 0.299 * p.0[0] as f64 + 0.587 * p.0[1] as f64 + 0.114 * p.0[2] as f64)
+            .collect();
+        JpegLuma { width: rgb.width(), height: rgb.height(), data }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f64 {
+        self.data[(y * self.width + x) as usize]
+    }
+}
+
+/// Orthonormal 8x8 DCT-II of the level shifted luminance block at `(x0, y0)`,
+/// scaled like the JPEG DCT so coefficients line up with quantization steps.
+pub(crate) fn dct_block(luma: &JpegLuma, x0: u32, y0: u32) -> [[f64; 8]; 8] {
+    let cos = cosine_table();
+    let mut rows = [[0.0; 8]; 8];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..8)
+                .map(|x| (luma.get(x0 + x as u32, y0 + y as u32) - 128.0) * cos[u][x])
+                .sum();
+        }
+    }
+    let mut coefficients = [[0.0; 8]; 8];
+    for (v, row) in coefficients.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..8).map(|y| rows[y][u] * cos[v][y]).sum();
+        }
+    }
+    coefficients
+}
+
+/// How strongly `values` cluster around multiples of `step`, from -1 to 1.
+pub(crate) fn periodicity(values: &[f64], step: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().map(|v| (2.0 * PI * v / step).cos()).sum::<f64>() / values.len() as f64
+}
+
+// Periodicity of the values reaching at least half a step, so large steps
+// don't trivially match small coefficients.
+fn step_score(values: &[f64], step: u32) -> Option<f64> {
+    let large: Vec<f64> = values.iter().copied().filter(|v| v.abs() >= step as f64 / 2.0).collect();
+    (large.len() >= 50).then(|| periodicity(&large, step as f64))
+}
+
+/// Estimates the quantization step used for a set of DCT coefficients as the
+/// step their values cluster around most strongly. Rounding the decoded pixels
+/// blurs steps below ~4, which are reported as 1.
+pub(crate) fn estimate_step(values: &[f64], max_step: u32) -> u32 {
+    match strongest_step(values, max_step) {
+        (step, score) if score > 0.6 => step,
+        _ => 1,
+    }
+}
+
+/// The step from 2 to `max_step` that `values` cluster around most strongly,
+/// with its periodicity.
+pub(crate) fn strongest_step(values: &[f64], max_step: u32) -> (u32, f64) {
+    (2..=max_step)
+        .filter_map(|step| step_score(values, step).map(|score| (step, score)))
+        .fold((1, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+}
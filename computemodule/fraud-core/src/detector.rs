@@ -0,0 +1,96 @@
+use crate::bag::BagDetector;
+use crate::benford::BenfordDetector;
+use crate::cfa::CfaDetector;
+use crate::double_jpeg::DoubleJpegDetector;
+use crate::ela::ElaDetector;
+use crate::ghost::JpegGhostDetector;
+use crate::lighting::LightingDetector;
+use crate::metadata::{MetadataDetector, MetadataFinding};
+use crate::noise::NoiseDetector;
+use crate::result::{intern_keys, Name};
+use crate::thumbnail::ThumbnailDetector;
+use crate::zero::ZeroDetector;
+use crate::{Heatmap, Region};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A single forgery detection algorithm run by the [`Pipeline`](crate::Pipeline).
+pub trait Detector: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// What the detector's regions show, reported as their `type`.
+    fn region_type(&self) -> &'static str {
+        "forged"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport;
+
+    /// Like [`Detector::analyze`] but also given the encoded input, for
+    /// detectors that inspect the file itself rather than its pixels.
+    fn analyze_encoded(&self, img: &DynamicImage, _encoded: &[u8]) -> DetectionReport {
+        self.analyze(img)
+    }
+
+    /// Whether the detector should see the image the way it is displayed, with
+    /// its EXIF orientation applied, its regions are mapped back onto the stored
+    /// image. Left off for detectors of the JPEG grid, sensor or file layout.
+    fn upright(&self) -> bool {
+        false
+    }
+
+    /// Whether the detector needs every pixel of images the pipeline
+    /// otherwise downscales for detection, e.g. to match a sensor fingerprint.
+    fn full_resolution(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DetectionReport {
+    pub regions: Vec<Region>,
+    pub cropped: bool,
+    /// Image level observations that don't localize to a region, e.g. recompression.
+    pub findings: Vec<String>,
+    pub metadata: Vec<MetadataFinding>,
+    /// Image level manipulation probability from 0 to 1, for detectors that can't localize.
+    pub score: Option<f64>,
+    /// Named values backing the findings, e.g. an estimated JPEG quality.
+    pub measurements: Vec<(&'static str, f64)>,
+    /// Per-block suspicion behind the regions, for heatmap overlays.
+    pub heatmap: Option<Heatmap>,
+    /// The statistics behind the regions, for the debug section of results.
+    pub evidence: Vec<RegionEvidence>,
+}
+
+/// What a detector based one of its regions on, e.g. the NFA and grid of
+/// `zero` regions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegionEvidence {
+    /// As the detector reported it, before regions are merged and clipped.
+    #[serde(flatten)]
+    pub region: Region,
+    #[serde(deserialize_with = "intern_keys")]
+    pub values: BTreeMap<Name, f64>,
+}
+
+/// Names accepted by [`detector_by_name`], in their default run order.
+pub const DETECTOR_NAMES: &[&str] =
+    &["zero", "ela", "noise", "double_jpeg", "cfa", "metadata", "thumbnail", "benford", "lighting", "bag", "jpeg_ghost"];
+
+pub fn detector_by_name(name: &str) -> Option<Box<dyn Detector>> {
+    match name {
+        "zero" => Some(Box::new(ZeroDetector)),
+        "ela" => Some(Box::new(ElaDetector::default())),
+        "noise" => Some(Box::new(NoiseDetector::default())),
+        "double_jpeg" => Some(Box::new(DoubleJpegDetector::default())),
+        "cfa" => Some(Box::new(CfaDetector::default())),
+        "metadata" => Some(Box::new(MetadataDetector::default())),
+        "thumbnail" => Some(Box::new(ThumbnailDetector::default())),
+        "benford" => Some(Box::new(BenfordDetector::default())),
+        "lighting" => Some(Box::new(LightingDetector::default())),
+        "bag" => Some(Box::new(BagDetector::default())),
+        "jpeg_ghost" => Some(Box::new(JpegGhostDetector::default())),
+        _ => None,
+    }
+}
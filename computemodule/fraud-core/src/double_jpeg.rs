@@ -0,0 +1,216 @@
+use crate::blocks::BlockGrid;
+use crate::dct::{dct_block, estimate_step, periodicity, strongest_step, JpegLuma, LOW_FREQUENCIES};
+use crate::detector::{DetectionReport, Detector};
+use image::DynamicImage;
+
+/// Looks for double quantization artifacts in DCT coefficient histograms.
+///
+/// When a JPEG is decompressed and saved again, coefficients quantized twice
+/// leave periodic empty bins in their histograms (aligned double JPEG) and the
+/// previous compression grid stays visible at a shifted offset when the image
+/// was cropped in between (non-aligned double JPEG). Blocks that lack those
+/// traces in a recompressed image were most likely pasted in before the last save.
+pub struct DoubleJpegDetector {
+    /// Fraction of histogram bins that must be valleys for a frequency to count as double quantized.
+    pub valley_ratio: f64,
+    /// Periodicity a shifted grid must reach to count as a previous compression.
+    pub shifted_grid_threshold: f64,
+    pub sensitivity: f64,
+    pub min_blocks: usize,
+}
+
+impl Default for DoubleJpegDetector {
+    fn default() -> Self {
+        DoubleJpegDetector { valley_ratio: 0.25, shifted_grid_threshold: 0.3, sensitivity: 4.0, min_blocks: 2 }
+    }
+}
+
+const MAX_STEP: u32 = 32;
+const HISTOGRAM_BINS: usize = 20;
+// Upper bound on the number of blocks sampled per offset when searching for shifted grids
+const SHIFTED_GRID_SAMPLES: usize = 4000;
+const REGION_BLOCK: u32 = 32;
+
+struct Blocks {
+    origin: (u32, u32),
+    cols: u32,
+    rows: u32,
+    coefficients: Vec<[f64; 9]>,
+}
+
+fn block_coefficients(luma: &JpegLuma, origin: (u32, u32), max_blocks: usize) -> Blocks {
+    let cols = luma.width.saturating_sub(origin.0) / 8;
+    let rows = luma.height.saturating_sub(origin.1) / 8;
+    let total = (cols * rows) as usize;
+    let stride = total.div_ceil(max_blocks.max(1)).max(1);
+    let coefficients = (0..total)
+        .step_by(stride)
+        .map(|index| {
+            let (col, row) = (index as u32 % cols, index as u32 / cols);
+            let dct = dct_block(luma, origin.0 + col * 8, origin.1 + row * 8);
+            LOW_FREQUENCIES.map(|(v, u)| dct[v][u])
+        })
+        .collect();
+    Blocks { origin, cols, rows, coefficients }
+}
+
+fn frequency_values(blocks: &Blocks, frequency: usize) -> Vec<f64> {
+    blocks.coefficients.iter().map(|c| c[frequency]).collect()
+}
+
+// Histogram bins (by absolute quantized value) that are much lower than both
+// neighbours, or None when the frequency shows no double quantization.
+fn valley_bins(values: &[f64], step: u32, valley_ratio: f64) -> Option<Vec<bool>> {
+    let mut histogram = [0usize; HISTOGRAM_BINS + 2];
+    for v in values {
+        let k = (v / step as f64).round().abs() as usize;
+        if (1..histogram.len()).contains(&k) {
+            histogram[k] += 1;
+        }
+    }
+    let mut valleys = vec![false; histogram.len()];
+    let mut eligible = 0;
+    for k in 2..=HISTOGRAM_BINS {
+        let neighbours = histogram[k - 1].min(histogram[k + 1]);
+        if neighbours < 10 {
+            continue;
+        }
+        eligible += 1;
+        valleys[k] = (histogram[k] as f64) < 0.4 * neighbours as f64;
+    }
+    let count = valleys.iter().filter(|&&v| v).count();
+    (eligible >= 3 && count as f64 >= valley_ratio * eligible as f64).then_some(valleys)
+}
+
+fn last_saved_as_jpeg(luma: &JpegLuma) -> bool {
+    let blocks = block_coefficients(luma, (0, 0), SHIFTED_GRID_SAMPLES);
+    (0..LOW_FREQUENCIES.len()).any(|frequency| estimate_step(&frequency_values(&blocks, frequency), MAX_STEP) >= 2)
+}
+
+impl DoubleJpegDetector {
+    fn aligned(&self, luma: &JpegLuma, report: &mut DetectionReport) -> bool {
+        let blocks = block_coefficients(luma, (0, 0), usize::MAX);
+        let mut double_quantized = Vec::new();
+        let mut analyzed = 0;
+        for frequency in 0..LOW_FREQUENCIES.len() {
+            let values = frequency_values(&blocks, frequency);
+            let step = estimate_step(&values, MAX_STEP);
+            if step < 2 {
+                continue;
+            }
+            analyzed += 1;
+            if let Some(valleys) = valley_bins(&values, step, self.valley_ratio) {
+                double_quantized.push((frequency, step, valleys));
+            }
+        }
+        if analyzed == 0 || double_quantized.len() * 2 < analyzed {
+            return false;
+        }
+        report.findings.push(String::from("recompressed: aligned double JPEG compression"));
+
+        // Doubly compressed blocks avoid the valleys, singly compressed ones do not
+        let valley_hits: Vec<f64> = blocks.coefficients.iter().map(|c| {
+            let (mut hits, mut total) = (0, 0);
+            for (frequency, step, valleys) in &double_quantized {
+                let k = (c[*frequency] / *step as f64).round().abs() as usize;
+                if (2..=HISTOGRAM_BINS).contains(&k) {
+                    total += 1;
+                    hits += valleys[k] as usize;
+                }
+            }
+            if total == 0 { f64::NAN } else { hits as f64 / total as f64 }
+        }).collect();
+        let grid = self.region_grid(luma, &blocks, &valley_hits);
+        let mask = grid.high_outliers(self.sensitivity, 0.1);
+        report.regions.extend(grid.regions(&mask, self.min_blocks));
+        true
+    }
+
+    fn non_aligned(&self, luma: &JpegLuma, report: &mut DetectionReport) {
+        // Strongest quantization trace (frequency, step, score) of every shifted grid
+        let mut offsets = Vec::new();
+        for dy in 0..8 {
+            for dx in 0..8 {
+                // Half block shifts correlate with the last grid through the DCT's symmetry,
+                // and on smooth images so do shifts of a single pixel
+                let adjacent = |d: u32| d <= 1 || d == 7;
+                if (dx % 4 == 0 && dy % 4 == 0) || (adjacent(dx) && adjacent(dy)) {
+                    continue;
+                }
+                let blocks = block_coefficients(luma, (dx, dy), SHIFTED_GRID_SAMPLES);
+                let strongest = (0..LOW_FREQUENCIES.len())
+                    .map(|frequency| {
+                        let (step, score) = strongest_step(&frequency_values(&blocks, frequency), MAX_STEP);
+                        (frequency, step, score)
+                    })
+                    .max_by(|a, b| a.2.total_cmp(&b.2));
+                offsets.extend(strongest.map(|(frequency, step, score)| ((dx, dy), frequency, step, score)));
+            }
+        }
+        let mut scores: Vec<f64> = offsets.iter().map(|offset| offset.3).collect();
+        scores.sort_by(|a, b| a.total_cmp(b));
+        let Some(&(origin, frequency, step, score)) = offsets.iter().max_by(|a, b| a.3.total_cmp(&b.3)) else {
+            return;
+        };
+        // The previous grid has to stand out, every offset picks up some periodicity by chance
+        let typical = scores[scores.len() / 2];
+        if score < self.shifted_grid_threshold || score < 2.0 * typical {
+            return;
+        }
+        report.findings.push(format!(
+            "recompressed: non-aligned double JPEG compression, previous grid at ({}, {})",
+            origin.0, origin.1
+        ));
+
+        // Blocks without the previous compression's periodicity were not part of the first save
+        let blocks = block_coefficients(luma, origin, usize::MAX);
+        let missing: Vec<f64> = blocks.coefficients.iter().map(|c| {
+            let v = c[frequency];
+            if v.abs() < 1.0 { f64::NAN } else { 1.0 - periodicity(&[v], step as f64) }
+        }).collect();
+        let grid = self.region_grid(luma, &blocks, &missing);
+        let mask = grid.high_outliers(self.sensitivity, 0.5);
+        report.regions.extend(grid.regions(&mask, self.min_blocks));
+    }
+
+    // Averages per 8x8 block scores into REGION_BLOCK sized blocks
+    fn region_grid(&self, luma: &JpegLuma, blocks: &Blocks, scores: &[f64]) -> BlockGrid {
+        let (ox, oy) = blocks.origin;
+        BlockGrid::from_fn(luma.width, luma.height, REGION_BLOCK, |x0, y0, x1, y1| {
+            let (mut sum, mut count) = (0.0, 0);
+            for row in (y0.saturating_sub(oy) / 8)..((y1.saturating_sub(oy) / 8).min(blocks.rows)) {
+                for col in (x0.saturating_sub(ox) / 8)..((x1.saturating_sub(ox) / 8).min(blocks.cols)) {
+                    let score = scores[(row * blocks.cols + col) as usize];
+                    if !score.is_nan() {
+                        sum += score;
+                        count += 1;
+                    }
+                }
+            }
+            if count < 4 { f64::NAN } else { sum / count as f64 }
+        })
+    }
+}
+
+impl Detector for DoubleJpegDetector {
+    fn name(&self) -> &'static str {
+        "double_jpeg"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "double_compression"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let luma = JpegLuma::from_image(img);
+        let mut report = DetectionReport::default();
+        if luma.width < 16 || luma.height < 16 {
+            return report;
+        }
+        // A shifted previous grid only means recompression if the image was last saved as a JPEG
+        if !self.aligned(&luma, &mut report) && last_saved_as_jpeg(&luma) {
+            self.non_aligned(&luma, &mut report);
+        }
+        report
+    }
+}
@@ -0,0 +1,309 @@
+use crate::font::{draw_text, text_width, GLYPH_HEIGHT};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Region {
+    pub start: Point,
+    pub end: Point,
+    /// How likely the region is to be forged, from 0 to 1.
+    pub confidence: f64,
+}
+
+impl Region {
+    pub fn area(&self) -> u64 {
+        (self.end.x - self.start.x + 1) as u64 * (self.end.y - self.start.y + 1) as u64
+    }
+
+    /// The part of the region inside a `width`x`height` image with its corners
+    /// in order, none when it lies entirely outside.
+    pub fn clip(self, width: u32, height: u32) -> Option<Region> {
+        let (x0, x1) = (self.start.x.min(self.end.x), self.start.x.max(self.end.x));
+        let (y0, y1) = (self.start.y.min(self.end.y), self.start.y.max(self.end.y));
+        if x0 >= width || y0 >= height {
+            return None;
+        }
+        Some(Region {
+            start: Point { x: x0, y: y0 },
+            end: Point { x: x1.min(width - 1), y: y1.min(height - 1) },
+            confidence: self.confidence,
+        })
+    }
+
+    /// Whether the regions share a pixel, whatever order their corners are in.
+    pub(crate) fn overlaps(&self, other: &Region) -> bool {
+        let span = |a: u32, b: u32| (a.min(b), a.max(b));
+        let ((ax0, ax1), (ay0, ay1)) = (span(self.start.x, self.end.x), span(self.start.y, self.end.y));
+        let ((bx0, bx1), (by0, by1)) = (span(other.start.x, other.end.x), span(other.start.y, other.end.y));
+        ax0 <= bx1 && bx0 <= ax1 && ay0 <= by1 && by0 <= ay1
+    }
+
+    /// Maps a region found on a resized copy back onto a `width`x`height` image
+    /// `sx` and `sy` times larger.
+    pub(crate) fn scale(self, sx: f64, sy: f64, width: u32, height: u32) -> Region {
+        Region {
+            start: Point { x: (self.start.x as f64 * sx) as u32, y: (self.start.y as f64 * sy) as u32 },
+            end: Point {
+                x: (((self.end.x + 1) as f64 * sx) as u32).min(width) - 1,
+                y: (((self.end.y + 1) as f64 * sy) as u32).min(height) - 1,
+            },
+            confidence: self.confidence,
+        }
+    }
+
+    /// Maps a region found on the `width`x`height` upright copy of an image
+    /// back onto the image as stored.
+    pub(crate) fn unorient(self, orientation: u32, width: u32, height: u32) -> Region {
+        let stored = |Point { x, y }: Point| match orientation {
+            2 => (width - 1 - x, y),
+            3 => (width - 1 - x, height - 1 - y),
+            4 => (x, height - 1 - y),
+            5 => (y, x),
+            6 => (y, width - 1 - x),
+            7 => (height - 1 - y, width - 1 - x),
+            8 => (height - 1 - y, x),
+            _ => (x, y),
+        };
+        let ((x1, y1), (x2, y2)) = (stored(self.start), stored(self.end));
+        Region {
+            start: Point { x: x1.min(x2), y: y1.min(y2) },
+            end: Point { x: x1.max(x2), y: y1.max(y2) },
+            confidence: self.confidence,
+        }
+    }
+}
+
+/// Outlines the part of `region` inside the image, regions outside it are skipped.
+pub fn draw_hollow_rect(image: &mut RgbaImage, region: &Region, color: Rgba<u8>) {
+    let Some(Region { start, end, .. }) = region.clip(image.width(), image.height()) else {
+        return;
+    };
+
+    // Draw top and bottom borders
+    for x in start.x..=end.x {
+        image.put_pixel(x, start.y, color);
+        image.put_pixel(x, end.y, color);
+    }
+
+    // Draw left and right borders
+    for y in start.y..=end.y {
+        image.put_pixel(start.x, y, color);
+        image.put_pixel(end.x, y, color);
+    }
+}
+
+// Blends `color` over pixel `(x, y)` by its alpha, pixels outside the image are skipped
+fn blend(image: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>) {
+    if x < image.width() && y < image.height() {
+        blend_pixels(row(image, y, x, x), color);
+    }
+}
+
+// The RGBA bytes of pixels `x0` to `x1` of row `y`, all inside the image
+fn row(image: &mut RgbaImage, y: u32, x0: u32, x1: u32) -> &mut [u8] {
+    let start = (y as usize * image.width() as usize + x0 as usize) * 4;
+    let raw: &mut [u8] = image;
+    &mut raw[start..start + (x1 - x0 + 1) as usize * 4]
+}
+
+// Blends `color` over RGBA `pixels` by its alpha, rounding like the float math it replaces, in integers the
+// compiler turns into vector instructions 4 pixels at a time
+fn blend_pixels(pixels: &mut [u8], Rgba([r, g, b, a]): Rgba<u8>) {
+    let (a, keep) = (u16::from(a), 255 - u16::from(a));
+    // A blend of the alpha channel with itself, leaving it as it is
+    let (weights, colors) = ([keep, keep, keep, 255], [r, g, b, 0].map(|value| u16::from(value) * a + 128));
+    let mix = |channel: &mut u8, lane: usize| {
+        let value = u16::from(*channel) * weights[lane] + colors[lane];
+        // value / 255 rounded, exact for products of two bytes
+        *channel = ((value + (value >> 8)) >> 8) as u8;
+    };
+    let mut chunks = pixels.chunks_exact_mut(16);
+    for chunk in &mut chunks {
+        chunk.iter_mut().enumerate().for_each(|(i, channel)| mix(channel, i % 4));
+    }
+    chunks.into_remainder().iter_mut().enumerate().for_each(|(i, channel)| mix(channel, i % 4));
+}
+
+/// A color, written `#rrggbb` or with an alpha channel as `#rrggbbaa`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub Rgba<u8>);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let hex = s.trim().trim_start_matches('#');
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        match (hex.len(), channel(0), channel(2), channel(4), channel(6)) {
+            (6, Some(r), Some(g), Some(b), _) => Ok(Color(Rgba([r, g, b, 255]))),
+            (8, Some(r), Some(g), Some(b), Some(a)) => Ok(Color(Rgba([r, g, b, a]))),
+            _ => Err(String::from("expected a #rrggbb or #rrggbbaa color")),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// How the outline of a region is stroked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineStyle {
+    #[default]
+    Solid,
+    Dashed,
+    /// Only the corners of the box, leaving the edited content visible.
+    Corners,
+}
+
+impl FromStr for LineStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "solid" => Ok(LineStyle::Solid),
+            "dashed" => Ok(LineStyle::Dashed),
+            "corners" => Ok(LineStyle::Corners),
+            _ => Err(String::from("expected solid, dashed or corners")),
+        }
+    }
+}
+
+impl LineStyle {
+    // Whether the pixel `pos` along an edge `len` pixels long is stroked
+    fn strokes(self, pos: u32, len: u32, thickness: u32) -> bool {
+        match self {
+            LineStyle::Solid => true,
+            LineStyle::Dashed => (pos / (4 * thickness)).is_multiple_of(2),
+            LineStyle::Corners => {
+                let arm = (len / 4).max(thickness);
+                pos < arm || pos + arm >= len
+            }
+        }
+    }
+}
+
+/// Images up to this many pixels on their longer side are annotated at the configured thickness.
+pub const SCALE_REFERENCE: u32 = 1000;
+
+/// How regions are outlined and filled on annotated images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Annotation {
+    pub color: Color,
+    /// Line width in pixels, the outline grows into the region.
+    pub thickness: u32,
+    pub style: LineStyle,
+    /// Tag every region with its `#index` from the region list.
+    pub labels: bool,
+    /// Blended over the whole region, transparent by default.
+    pub fill: Color,
+    /// Thicken lines and labels on large images, see [`Annotation::scaled`].
+    pub auto_scale: bool,
+}
+
+impl Default for Annotation {
+    fn default() -> Self {
+        Annotation {
+            color: Color(Rgba([255, 0, 0, 255])),
+            thickness: 1,
+            style: LineStyle::default(),
+            labels: false,
+            fill: Color(Rgba([0, 0, 0, 0])),
+            auto_scale: true,
+        }
+    }
+}
+
+impl Annotation {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.thickness == 0 {
+            return Err(String::from("thickness must be at least 1"));
+        }
+        Ok(())
+    }
+
+    /// The annotation for a `width`x`height` image, with the thickness (and so
+    /// the label size) multiplied by every [`SCALE_REFERENCE`] pixels of the
+    /// longer side when auto scaling, so outlines stay visible on large scans.
+    pub fn scaled(&self, width: u32, height: u32) -> Annotation {
+        if !self.auto_scale {
+            return *self;
+        }
+        let factor = (width.max(height) as f64 / SCALE_REFERENCE as f64).round().max(1.0) as u32;
+        Annotation { thickness: self.thickness * factor, ..*self }
+    }
+
+    pub fn draw(&self, image: &mut RgbaImage, region: &Region) {
+        let Some(Region { start, end, .. }) = region.clip(image.width(), image.height()) else {
+            return;
+        };
+        if self.fill.0[3] > 0 {
+            for y in start.y..=end.y {
+                blend_pixels(row(image, y, start.x, end.x), self.fill.0);
+            }
+        }
+        let color = self.color.0;
+        for inset in 0..self.thickness {
+            if 2 * inset > end.x - start.x || 2 * inset > end.y - start.y {
+                break;
+            }
+            let (x0, y0, x1, y1) = (start.x + inset, start.y + inset, end.x - inset, end.y - inset);
+            if self.style == LineStyle::Solid {
+                // Top and bottom edges a row at a time, blended twice where they are the same row like any pixel
+                // two of the edges share
+                blend_pixels(row(image, y0, x0, x1), color);
+                blend_pixels(row(image, y1, x0, x1), color);
+            } else {
+                for pos in 0..=x1 - x0 {
+                    if self.style.strokes(pos, x1 - x0 + 1, self.thickness) {
+                        blend(image, x0 + pos, y0, color);
+                        blend(image, x0 + pos, y1, color);
+                    }
+                }
+            }
+            for pos in 0..=y1 - y0 {
+                if self.style.strokes(pos, y1 - y0 + 1, self.thickness) {
+                    blend(image, x0, y0 + pos, color);
+                    blend(image, x1, y0 + pos, color);
+                }
+            }
+        }
+    }
+
+    /// Draws `#index` on a tag of the outline color above the region's top left
+    /// corner, or just inside it when the region touches the top of the image.
+    pub fn label(&self, image: &mut RgbaImage, region: &Region, index: usize) {
+        let (width, height) = image.dimensions();
+        let Some(region) = region.clip(width, height) else {
+            return;
+        };
+        let text = format!("#{}", index);
+        let scale = self.thickness + 1;
+        let (tag_width, tag_height) = (text_width(&text, scale) + 2 * scale, (GLYPH_HEIGHT + 2) * scale);
+        let (x, y) = (region.start.x, region.start.y.checked_sub(tag_height).unwrap_or(region.start.y));
+        let Color(color) = self.color;
+        for ty in y..(y + tag_height).min(height) {
+            for tx in x..(x + tag_width).min(width) {
+                image.put_pixel(tx, ty, color);
+            }
+        }
+        // Dark text on light tags
+        let Rgba([r, g, b, _]) = color;
+        let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+        let text_color = if luma > 150.0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) };
+        draw_text(image, x + scale, y + scale, &text, scale, text_color);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::blocks::BlockGrid;
+use crate::detector::{DetectionReport, Detector};
+use image::codecs::jpeg::JpegEncoder;
+use image::{load_from_memory, DynamicImage};
+use log::warn;
+
+/// Error Level Analysis: recompresses the image at a known JPEG quality and
+/// flags blocks whose recompression error is unusually high compared to the
+/// rest of the image, which happens when content was pasted in after the
+/// last save.
+pub struct ElaDetector {
+    pub quality: u8,
+    pub block_size: u32,
+    /// How many robust standard deviations above the median error a block must be.
+    pub sensitivity: f64,
+    /// Minimum absolute mean error (in 0-255 levels) above the median for a block to be flagged.
+    pub min_error: f64,
+    pub min_blocks: usize,
+}
+
+impl Default for ElaDetector {
+    fn default() -> Self {
+        ElaDetector { quality: 90, block_size: 16, sensitivity: 6.0, min_error: 2.0, min_blocks: 2 }
+    }
+}
+
+impl ElaDetector {
+    fn error_grid(&self, img: &DynamicImage) -> Option<BlockGrid> {
+        let original = img.to_rgb8();
+        let mut encoded = Vec::new();
+        if let Err(err) = JpegEncoder::new_with_quality(&mut encoded, self.quality).encode_image(&original) {
+            warn!("ELA failed to recompress image: {}", err);
+            return None;
+        }
+        let recompressed = match load_from_memory(&encoded) {
+            Ok(recompressed) => recompressed.to_rgb8(),
+            Err(err) => {
+                warn!("ELA failed to decode recompressed image: {}", err);
+                return None;
+            }
+        };
+
+        let (width, height) = original.dimensions();
+        Some(BlockGrid::from_fn(width, height, self.block_size, |x0, y0, x1, y1| {
+            let mut total = 0u64;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let a = original.get_pixel(x, y).0;
+                    let b = recompressed.get_pixel(x, y).0;
+                    total += (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0) as u64;
+                }
+            }
+            total as f64 / ((x1 - x0) * (y1 - y0)) as f64
+        }))
+    }
+}
+
+impl Detector for ElaDetector {
+    fn name(&self) -> &'static str {
+        "ela"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "error_level"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let Some(grid) = self.error_grid(img) else {
+            return DetectionReport::default();
+        };
+        let mask = grid.high_outliers(self.sensitivity, self.min_error);
+        DetectionReport {
+            regions: grid.regions(&mask, self.min_blocks),
+            heatmap: Some(grid.heatmap(self.sensitivity, self.min_error, false)),
+            ..DetectionReport::default()
+        }
+    }
+}
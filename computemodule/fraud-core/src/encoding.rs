@@ -0,0 +1,118 @@
+use crate::buffers::BUFFERS;
+use crate::simd_base64::Base64Writer;
+use image::buffer::ConvertBuffer;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{self, FilterType};
+use image::{ImageResult, RgbImage, RgbaImage};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+
+pub const DEFAULT_OUTPUT_QUALITY: u8 = 90;
+
+/// The format annotated images are encoded as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// Lossless, the quality is ignored.
+    WebP,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::WebP),
+            _ => Err(String::from("expected png, jpeg or webp")),
+        }
+    }
+}
+
+/// How annotated images are encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputEncoding {
+    pub format: OutputFormat,
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+    /// Skip the output image, only the verdict and regions are returned.
+    pub regions_only: bool,
+    /// Longest side of the output image, larger ones are downscaled. Regions
+    /// are still reported in pixels of the input.
+    pub max_size: Option<u32>,
+}
+
+impl Default for OutputEncoding {
+    fn default() -> Self {
+        OutputEncoding {
+            format: OutputFormat::default(),
+            quality: DEFAULT_OUTPUT_QUALITY,
+            regions_only: false,
+            max_size: None,
+        }
+    }
+}
+
+impl OutputEncoding {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(format!("quality must be between 1 and 100, got {}", self.quality));
+        }
+        if self.max_size == Some(0) {
+            return Err(String::from("max_size must be above 0"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn fits(&self, width: u32, height: u32) -> bool {
+        self.max_size.is_none_or(|size| width.max(height) <= size)
+    }
+
+    /// `image` downscaled to fit `max_size`, keeping its aspect ratio.
+    pub(crate) fn fit<'a>(&self, image: &'a RgbaImage) -> Cow<'a, RgbaImage> {
+        let (width, height) = image.dimensions();
+        match self.max_size {
+            Some(size) if !self.fits(width, height) => {
+                let scale = size as f64 / width.max(height) as f64;
+                let scaled = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, size);
+                Cow::Owned(imageops::resize(image, scaled(width), scaled(height), FilterType::Triangle))
+            }
+            _ => Cow::Borrowed(image),
+        }
+    }
+
+    pub(crate) fn encode(&self, image: &RgbaImage) -> ImageResult<Vec<u8>> {
+        // Encoded images mostly come out at less than half their raw size
+        let mut buf = BUFFERS.take(image.as_raw().len() / 2);
+        self.encode_to(image, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Like [`OutputEncoding::encode`], base64 encoded as it is written
+    /// instead of after, so the raw bytes are never held in full.
+    pub(crate) fn encode_base64(&self, image: &RgbaImage) -> ImageResult<String> {
+        let mut writer = Base64Writer::new();
+        self.encode_to(image, &mut writer)?;
+        Ok(writer.into_string())
+    }
+
+    fn encode_to(&self, image: &RgbaImage, writer: impl Write) -> ImageResult<()> {
+        match self.format {
+            OutputFormat::Png => image.write_with_encoder(PngEncoder::new(writer)),
+            // JPEG has no alpha channel
+            OutputFormat::Jpeg => {
+                let rgb: RgbImage = image.convert();
+                rgb.write_with_encoder(JpegEncoder::new_with_quality(writer, self.quality))
+            }
+            OutputFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(writer)),
+        }
+    }
+}
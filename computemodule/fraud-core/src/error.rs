@@ -0,0 +1,89 @@
+use crate::job::Cancelled;
+use crate::result::{intern, Name};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+
+/// Why a job has no verdict.
+#[derive(Debug)]
+pub enum JobError {
+    /// The job itself is malformed, e.g. invalid base64 or settings.
+    Invalid(String),
+    /// The input could not be fetched, or the output uploaded.
+    Transfer(String),
+    /// The input is not an image, or no supported one.
+    Decode(String),
+    /// The input is over the configured limits.
+    TooLarge(String),
+    /// The output could not be encoded.
+    Encode(String),
+    Cancelled,
+    /// The job ran past its deadline.
+    TimedOut(String),
+    /// A bug, e.g. a detector panicked.
+    Internal(String),
+}
+
+impl JobError {
+    /// Machine readable name of the variant, for [`ErrorReport::kind`].
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobError::Invalid(_) => "invalid",
+            JobError::Transfer(_) => "transfer",
+            JobError::Decode(_) => "decode",
+            JobError::TooLarge(_) => "too_large",
+            JobError::Encode(_) => "encode",
+            JobError::Cancelled => "cancelled",
+            JobError::TimedOut(_) => "timeout",
+            JobError::Internal(_) => "internal",
+        }
+    }
+
+    /// A panic caught while handling a job, with its message when it has one.
+    pub fn panicked(payload: Box<dyn Any + Send>) -> JobError {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or("unknown cause", |message| message).to_string(),
+        };
+        JobError::Internal(format!("Panicked: {}", message))
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Invalid(message)
+            | JobError::Transfer(message)
+            | JobError::Decode(message)
+            | JobError::TooLarge(message)
+            | JobError::Encode(message)
+            | JobError::TimedOut(message)
+            | JobError::Internal(message) => write!(f, "{}", message),
+            JobError::Cancelled => write!(f, "{}", Cancelled),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+impl From<Cancelled> for JobError {
+    fn from(_: Cancelled) -> Self {
+        JobError::Cancelled
+    }
+}
+
+/// A [`JobError`] as reported in a failed result.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorReport {
+    /// See [`JobError::kind`].
+    #[serde(deserialize_with = "intern")]
+    pub kind: Name,
+    pub message: String,
+}
+
+impl From<&JobError> for ErrorReport {
+    fn from(err: &JobError) -> Self {
+        ErrorReport { kind: err.kind(), message: err.to_string() }
+    }
+}
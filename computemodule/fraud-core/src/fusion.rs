@@ -0,0 +1,218 @@
+use crate::{Point, Region};
+use serde::Deserialize;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Combines the evidence of every detector into one confidence. A detector's
+/// evidence is its score, or full confidence when it localized regions, scaled
+/// by its weight. The evidence is combined as independent
+/// signals (noisy OR), so agreeing detectors reinforce each other.
+#[derive(Clone, Default)]
+pub struct Fusion {
+    /// Per detector weight from 0 (ignored) to 1, detectors not listed get 1.
+    pub weights: HashMap<String, f64>,
+}
+
+/// Trades false positives against false negatives when turning detector
+/// output into a verdict.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Sensitivity {
+    /// Regions with a lower confidence are dropped. Zero's regions have a
+    /// confidence of `1 / (1 + NFA)`, so 0.5 keeps those with an NFA below 1.
+    pub min_region_confidence: f64,
+    /// Smaller regions are dropped, so single block noise doesn't flip the verdict.
+    pub min_region_area: RegionArea,
+    /// Minimum fused confidence for a suspicious verdict.
+    pub suspicious_threshold: f64,
+    /// Minimum fused confidence for an edited verdict.
+    pub edited_threshold: f64,
+}
+
+impl Default for Sensitivity {
+    fn default() -> Self {
+        Sensitivity {
+            min_region_confidence: 0.0,
+            min_region_area: RegionArea::Pixels(0),
+            suspicious_threshold: 0.4,
+            edited_threshold: 0.75,
+        }
+    }
+}
+
+/// An area in pixels or as a percentage of the image, written as `1024` or `0.5%`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "RawArea")]
+pub enum RegionArea {
+    Pixels(u64),
+    Percent(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawArea {
+    Pixels(u64),
+    Text(String),
+}
+
+impl TryFrom<RawArea> for RegionArea {
+    type Error = String;
+
+    fn try_from(raw: RawArea) -> Result<Self, String> {
+        match raw {
+            RawArea::Pixels(pixels) => Ok(RegionArea::Pixels(pixels)),
+            RawArea::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl FromStr for RegionArea {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse().map(RegionArea::Percent).map_err(|err| err.to_string()),
+            None => s.parse().map(RegionArea::Pixels).map_err(|_| String::from("expected pixels or a percentage")),
+        }
+    }
+}
+
+impl RegionArea {
+    pub fn pixels(&self, width: u32, height: u32) -> u64 {
+        match *self {
+            RegionArea::Pixels(pixels) => pixels,
+            RegionArea::Percent(percent) => (percent / 100.0 * width as f64 * height as f64).ceil() as u64,
+        }
+    }
+}
+
+impl Sensitivity {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.min_region_confidence) {
+            return Err(String::from("min_region_confidence must be between 0 and 1"));
+        }
+        if let RegionArea::Percent(percent) = self.min_region_area {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(String::from("min_region_area must be between 0% and 100%"));
+            }
+        }
+        let ordered = 0.0 < self.suspicious_threshold
+            && self.suspicious_threshold <= self.edited_threshold
+            && self.edited_threshold <= 1.0;
+        if !ordered {
+            return Err(String::from("thresholds must satisfy 0 < suspicious_threshold <= edited_threshold <= 1"));
+        }
+        Ok(())
+    }
+
+    /// Whether the region is confident and large enough to count.
+    pub fn keeps(&self, region: &Region, width: u32, height: u32) -> bool {
+        region.confidence >= self.min_region_confidence && region.area() >= self.min_region_area.pixels(width, height)
+    }
+
+    pub fn verdict(&self, confidence: f64) -> Verdict {
+        if confidence >= self.edited_threshold {
+            Verdict::Edited
+        } else if confidence >= self.suspicious_threshold {
+            Verdict::Suspicious
+        } else {
+            Verdict::Clean
+        }
+    }
+}
+
+/// The fused verdict before cropping is taken into account.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Clean,
+    Suspicious,
+    Edited,
+}
+
+impl Fusion {
+    pub fn weight(&self, detector: &str) -> f64 {
+        self.weights.get(detector).copied().unwrap_or(1.0)
+    }
+
+    /// Fuses `(detector, evidence)` pairs into a confidence from 0 to 1.
+    pub fn confidence<'a>(&self, evidence: impl IntoIterator<Item = (&'a str, f64)>) -> f64 {
+        let unexplained: f64 = evidence
+            .into_iter()
+            .map(|(detector, evidence)| 1.0 - (self.weight(detector) * evidence).clamp(0.0, 1.0))
+            .product();
+        1.0 - unexplained
+    }
+}
+
+// Caps the severity of evidence no region localizes, such as metadata or image level scores
+const UNLOCALIZED_SEVERITY: f64 = 0.5;
+
+/// Ranks how much of the image was manipulated from 0 to 100. Every region
+/// counts with its confidence, from half for a tiny region to all of it for
+/// one covering the image, and regions are combined as independent evidence
+/// so more of them raise the score. The fused `confidence` counts at most
+/// half, so edits found without regions still rank above clean images.
+pub fn severity_score<R: Borrow<Region>>(regions: &[R], width: u32, height: u32, confidence: f64) -> u8 {
+    let pixels = (width as f64 * height as f64).max(1.0);
+    let unexplained: f64 = regions
+        .iter()
+        .map(|region| {
+            let region = region.borrow();
+            let coverage = (region.area() as f64 / pixels).sqrt().min(1.0);
+            1.0 - (region.confidence * (0.5 + 0.5 * coverage)).clamp(0.0, 1.0)
+        })
+        .product();
+    let severity = (1.0 - unexplained).max(UNLOCALIZED_SEVERITY * confidence.clamp(0.0, 1.0));
+    (severity * 100.0).round() as u8
+}
+
+// Whether the regions overlap or are at most `gap` pixels apart
+fn near(a: &Region, b: &Region, gap: u32) -> bool {
+    a.start.x <= b.end.x + gap && b.start.x <= a.end.x + gap && a.start.y <= b.end.y + gap && b.start.y <= a.end.y + gap
+}
+
+/// Merges regions that overlap or are at most `gap` pixels apart into their
+/// bounding boxes, so one forgery split into adjacent blocks is reported once.
+pub fn merge_regions(regions: Vec<Region>, gap: u32) -> Vec<Region> {
+    let mut merged: Vec<Region> = Vec::new();
+    for region in regions {
+        let mut current = region;
+        // Growing a box can make it overlap boxes merged earlier
+        while let Some(index) = merged.iter().position(|other| near(other, &current, gap)) {
+            let other = merged.swap_remove(index);
+            current = Region {
+                start: Point { x: current.start.x.min(other.start.x), y: current.start.y.min(other.start.y) },
+                end: Point { x: current.end.x.max(other.end.x), y: current.end.y.max(other.end.y) },
+                confidence: current.confidence.max(other.confidence),
+            };
+        }
+        merged.push(current);
+    }
+    merged
+}
+
+fn intersection_over_union(a: &Region, b: &Region) -> f64 {
+    let (x0, y0) = (a.start.x.max(b.start.x), a.start.y.max(b.start.y));
+    let (x1, y1) = (a.end.x.min(b.end.x), a.end.y.min(b.end.y));
+    if x0 > x1 || y0 > y1 {
+        return 0.0;
+    }
+    let intersection = (x1 - x0 + 1) as f64 * (y1 - y0 + 1) as f64;
+    intersection / ((a.area() + b.area()) as f64 - intersection)
+}
+
+/// Non-maximum suppression: drops every region overlapping a more confident
+/// one by an intersection over union of at least `min_iou`, so detectors
+/// reporting the same finding don't each add a box.
+pub fn suppress_duplicates<R: Borrow<Region>>(mut regions: Vec<R>, min_iou: f64) -> Vec<R> {
+    regions.sort_by(|a, b| b.borrow().confidence.total_cmp(&a.borrow().confidence));
+    let mut kept: Vec<R> = Vec::new();
+    for region in regions {
+        if kept.iter().all(|other| intersection_over_union(other.borrow(), region.borrow()) < min_iou) {
+            kept.push(region);
+        }
+    }
+    kept
+}
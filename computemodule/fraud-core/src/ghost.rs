@@ -0,0 +1,145 @@
+use crate::blocks::BlockGrid;
+use crate::detector::{DetectionReport, Detector};
+use crate::Region;
+use image::codecs::jpeg::JpegEncoder;
+use image::{load_from_memory, DynamicImage, GrayImage};
+use log::warn;
+use rayon::prelude::*;
+
+/// JPEG ghosts: recompressing at the quality a region was previously saved
+/// with barely changes it, so sweeping the quality shows a dip in that
+/// region's difference map. A dip at a lower quality than the rest of the
+/// image means the region came from another, more compressed JPEG.
+pub struct JpegGhostDetector {
+    /// Recompression qualities in ascending order.
+    pub qualities: Vec<u8>,
+    pub block_size: u32,
+    /// How many robust standard deviations below the median difference a block must be.
+    pub sensitivity: f64,
+    /// Minimum normalized difference below the median for a block to be a ghost.
+    pub min_depth: f64,
+    /// Minimum spread of a block's mean squared difference over the sweep,
+    /// flat blocks barely change at any quality.
+    pub min_range: f64,
+    /// Ghosts covering more of the image than this are the image's own history.
+    pub max_ghost_fraction: f64,
+    /// How many quality points below the image's last quality a ghost must be.
+    pub min_quality_gap: u8,
+    pub min_blocks: usize,
+}
+
+impl Default for JpegGhostDetector {
+    fn default() -> Self {
+        JpegGhostDetector {
+            qualities: (30..=95).step_by(5).collect(),
+            block_size: 16,
+            sensitivity: 4.0,
+            min_depth: 0.3,
+            min_range: 4.0,
+            max_ghost_fraction: 0.5,
+            min_quality_gap: 10,
+            min_blocks: 4,
+        }
+    }
+}
+
+impl JpegGhostDetector {
+    // Mean squared luma difference per block after recompressing at `quality`
+    fn difference(&self, img: &DynamicImage, original: &GrayImage, quality: u8) -> Option<BlockGrid> {
+        let mut encoded = Vec::new();
+        if let Err(err) = JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&img.to_rgb8()) {
+            warn!("JPEG ghost failed to recompress image at quality {}: {}", quality, err);
+            return None;
+        }
+        let recompressed = match load_from_memory(&encoded) {
+            Ok(recompressed) => recompressed.to_luma8(),
+            Err(err) => {
+                warn!("JPEG ghost failed to decode recompressed image: {}", err);
+                return None;
+            }
+        };
+        let (width, height) = original.dimensions();
+        Some(BlockGrid::from_fn(width, height, self.block_size, |x0, y0, x1, y1| {
+            let mut total = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let d = original.get_pixel(x, y).0[0] as f64 - recompressed.get_pixel(x, y).0[0] as f64;
+                    total += d * d;
+                }
+            }
+            total / ((x1 - x0) * (y1 - y0)) as f64
+        }))
+    }
+
+    // Each block's difference rescaled to 0-1 over the sweep, so textured and
+    // flat blocks can be compared at the same quality
+    fn normalize(&self, sweep: &mut [BlockGrid]) {
+        for block in 0..sweep[0].values.len() {
+            let curve = sweep.iter().map(|grid| grid.values[block]);
+            let (min, max) = curve.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+            for grid in sweep.iter_mut() {
+                let value = &mut grid.values[block];
+                *value = if max - min >= self.min_range { (*value - min) / (max - min) } else { f64::NAN };
+            }
+        }
+    }
+}
+
+impl Detector for JpegGhostDetector {
+    fn name(&self) -> &'static str {
+        "jpeg_ghost"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "jpeg_ghost"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let original = img.to_luma8();
+        // Every quality is recompressed on its own thread
+        let sweep = self.qualities.par_iter().map(|&q| self.difference(img, &original, q));
+        let Some(mut sweep) = sweep.collect::<Option<Vec<_>>>() else {
+            return DetectionReport::default();
+        };
+        if sweep.len() < 3 {
+            return DetectionReport::default();
+        }
+        self.normalize(&mut sweep);
+
+        // The quality the image was last saved with leaves the smallest
+        // differences, ignoring the top quality which barely changes anything
+        let medians: Vec<f64> = sweep.iter().map(|grid| grid.robust_stats().0).collect();
+        let last = (0..sweep.len() - 1).fold(0, |best, q| if medians[q] < medians[best] { q } else { best });
+        let last_quality = self.qualities[last];
+
+        // Ghosts show over a range of qualities, keep the one where they are deepest
+        let mut best: Option<(f64, u8, Vec<Region>)> = None;
+        for (q, grid) in sweep.iter().enumerate() {
+            let quality = self.qualities[q];
+            if quality + self.min_quality_gap > last_quality {
+                continue;
+            }
+            let (median, sigma) = grid.robust_stats();
+            let threshold = median - (self.sensitivity * sigma).max(self.min_depth);
+            let mask: Vec<bool> = grid.values.iter().map(|&v| v < threshold).collect();
+            let regions = grid.regions(&mask, self.min_blocks);
+            let flagged = mask.iter().filter(|&&m| m).count();
+            if regions.is_empty() || flagged as f64 > self.max_ghost_fraction * grid.values.len() as f64 {
+                continue;
+            }
+            let depth: f64 = grid.values.iter().zip(&mask).filter(|(_, &m)| m).map(|(v, _)| median - v).sum();
+            if best.as_ref().is_none_or(|(max, _, _)| depth > *max) {
+                best = Some((depth, quality, regions));
+            }
+        }
+        let Some((_, quality, regions)) = best else {
+            return DetectionReport::default();
+        };
+        DetectionReport {
+            regions,
+            findings: vec![format!("JPEG ghost at quality {}, the image was last saved at {}", quality, last_quality)],
+            measurements: vec![("quality", quality as f64), ("last_quality", last_quality as f64)],
+            ..DetectionReport::default()
+        }
+    }
+}
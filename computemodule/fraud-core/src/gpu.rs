@@ -0,0 +1,139 @@
+use crate::onnx::session_builder;
+use image::GrayImage;
+use log::{info, warn};
+use ort::execution_providers::{CUDAExecutionProvider, ExecutionProviderDispatch};
+use ort::session::Session;
+use ort::value::Tensor;
+use std::sync::{Mutex, OnceLock};
+
+// Immerkær's noise estimation kernel, see noise::residual
+const NOISE_KERNEL: [f32; 9] = [1.0, -2.0, 1.0, -2.0, 4.0, -2.0, 1.0, -2.0, 1.0];
+
+static DEVICE: OnceLock<i32> = OnceLock::new();
+
+// None once it failed to start on the device, the CPU filters instead
+static NOISE_FILTER: OnceLock<Option<Mutex<Session>>> = OnceLock::new();
+
+/// Runs the ONNX model and the noise detector's filter on CUDA device
+/// `device_id` through ONNX Runtime, which needs a libonnxruntime built with
+/// CUDA. Without a usable device the model runs on the CPU in ONNX Runtime
+/// and the filter in the detector instead. Only the first call counts, call
+/// it before loading the model.
+pub fn use_gpu(device_id: i32) {
+    let _ = DEVICE.set(device_id);
+}
+
+/// The CUDA provider of the configured device, if any. Sessions fall back to
+/// the CPU when it can't start unless `required`, which fails them instead.
+pub(crate) fn cuda(required: bool) -> Option<ExecutionProviderDispatch> {
+    let device_id = *DEVICE.get()?;
+    // TF32 would round the 0-255 levels of the filter input
+    let provider = CUDAExecutionProvider::default().with_device_id(device_id).with_tf32(false).build();
+    Some(if required { provider.error_on_failure() } else { provider })
+}
+
+/// The noise residual of every pixel but the outermost ones, row by row, the
+/// same as the detector's, none without a usable device.
+pub(crate) fn noise_residuals(luma: &GrayImage) -> Option<Vec<f32>> {
+    let device_id = *DEVICE.get()?;
+    let filter = NOISE_FILTER.get_or_init(|| match noise_filter() {
+        Ok(session) => {
+            info!("Running the noise filter on CUDA device {}", device_id);
+            Some(Mutex::new(session))
+        }
+        Err(err) => {
+            warn!("Running the noise filter on the CPU, CUDA device {} is unusable: {}", device_id, err);
+            None
+        }
+    });
+    let mut session = filter.as_ref()?.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (width, height) = luma.dimensions();
+    let levels: Vec<f32> = luma.as_raw().iter().map(|&level| f32::from(level)).collect();
+    let filtered = Tensor::from_array(([1usize, 1, height as usize, width as usize], levels))
+        .and_then(|input| session.run(ort::inputs![input]))
+        .and_then(|outputs| Ok(outputs[0].try_extract_tensor::<f32>()?.1.to_vec()));
+    filtered.map_err(|err| warn!("Noise filter failed on CUDA device {}: {}", device_id, err)).ok()
+}
+
+fn noise_filter() -> Result<Session, ort::Error> {
+    let cuda = cuda(true).expect("Only built with a device");
+    session_builder()?.with_execution_providers([cuda])?.commit_from_memory(&conv_model(NOISE_KERNEL))
+}
+
+// ONNX protobuf, written out by hand for the only model built here
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_int(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buf, u64::from(field) << 3);
+    put_varint(buf, value);
+}
+
+fn put_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(buf, u64::from(field) << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+// A float tensor of shape `[1, 1, height, width]`, or of any shape for outputs ONNX Runtime infers the shape of
+fn value_info(name: &str, shaped: bool) -> Vec<u8> {
+    let mut tensor = Vec::new();
+    put_int(&mut tensor, 1, 1); // elem_type FLOAT
+    if shaped {
+        let mut shape = Vec::new();
+        for param in [None, None, Some("height"), Some("width")] {
+            let mut dim = Vec::new();
+            match param {
+                Some(param) => put_field(&mut dim, 2, param.as_bytes()),
+                None => put_int(&mut dim, 1, 1),
+            }
+            put_field(&mut shape, 1, &dim);
+        }
+        put_field(&mut tensor, 2, &shape);
+    }
+    let mut kind = Vec::new();
+    put_field(&mut kind, 1, &tensor); // tensor_type
+    let mut info = Vec::new();
+    put_field(&mut info, 1, name.as_bytes());
+    put_field(&mut info, 2, &kind);
+    info
+}
+
+// A model convolving a `[1, 1, height, width]` `image` with the 3x3 `kernel` into `filtered`, without padding
+fn conv_model(kernel: [f32; 9]) -> Vec<u8> {
+    let mut node = Vec::new();
+    put_field(&mut node, 1, b"image");
+    put_field(&mut node, 1, b"kernel");
+    put_field(&mut node, 2, b"filtered");
+    put_field(&mut node, 4, b"Conv");
+
+    let mut weights = Vec::new();
+    for dim in [1, 1, 3, 3] {
+        put_int(&mut weights, 1, dim);
+    }
+    put_int(&mut weights, 2, 1); // data_type FLOAT
+    put_field(&mut weights, 8, b"kernel");
+    let raw: Vec<u8> = kernel.iter().flat_map(|weight| weight.to_le_bytes()).collect();
+    put_field(&mut weights, 9, &raw);
+
+    let mut graph = Vec::new();
+    put_field(&mut graph, 1, &node);
+    put_field(&mut graph, 2, b"filter");
+    put_field(&mut graph, 5, &weights);
+    put_field(&mut graph, 11, &value_info("image", true));
+    put_field(&mut graph, 12, &value_info("filtered", false));
+
+    let mut opset = Vec::new();
+    put_int(&mut opset, 2, 13);
+    let mut model = Vec::new();
+    put_int(&mut model, 1, 7); // ir_version
+    put_field(&mut model, 7, &graph);
+    put_field(&mut model, 8, &opset);
+    model
+}
@@ -0,0 +1,117 @@
+use crate::orientation::upright_point;
+use crate::RegionReport;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::str::FromStr;
+
+// Blocks below their detector's threshold are left untinted, see suspicion
+const MIN_SUSPICION: f64 = 0.5;
+const MAX_ALPHA: f64 = 0.6;
+
+/// Per-block suspicion from 0 to 1 over a grid of `block_width` by
+/// `block_height` pixel blocks starting at the top left corner.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    pub cols: u32,
+    pub rows: u32,
+    pub block_width: f64,
+    pub block_height: f64,
+    pub values: Vec<f64>,
+}
+
+impl Heatmap {
+    /// The suspicion of the block covering pixel `(x, y)`, 0 outside the grid.
+    pub fn at(&self, x: u32, y: u32) -> f64 {
+        let (col, row) = ((x as f64 / self.block_width) as u32, (y as f64 / self.block_height) as u32);
+        if col < self.cols && row < self.rows {
+            self.values[(row * self.cols + col) as usize]
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Maps how far a block's statistic is past normal onto 0 to 1, reaching 0.5
+/// at the `threshold` its detector flags blocks at.
+pub(crate) fn suspicion(excess: f64, threshold: f64) -> f64 {
+    let ratio = excess / threshold;
+    if excess <= 0.0 {
+        0.0
+    } else if ratio.is_finite() {
+        ratio / (ratio + 1.0)
+    } else {
+        1.0
+    }
+}
+
+/// How forged regions are drawn onto the annotated image.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Overlay {
+    /// A hollow rectangle around every region.
+    #[default]
+    Boxes,
+    /// Per-block suspicion blended over the image, from yellow to red.
+    Heatmap,
+    /// Regions blurred beyond recognition, for sharing images without the tampered content.
+    Blur,
+    /// Regions filled black.
+    Blackout,
+}
+
+impl FromStr for Overlay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "boxes" => Ok(Overlay::Boxes),
+            "heatmap" => Ok(Overlay::Heatmap),
+            "blur" => Ok(Overlay::Blur),
+            "blackout" => Ok(Overlay::Blackout),
+            _ => Err(String::from("expected boxes, heatmap, blur or blackout")),
+        }
+    }
+}
+
+/// Blends the highest suspicion of any detector over every pixel. Heatmaps
+/// come with the name of their detector and the orientation of the image it
+/// saw, regions of detectors without a heatmap are filled at their confidence.
+pub(crate) fn render_heatmap(
+    image: &DynamicImage,
+    heatmaps: &[(&str, Heatmap, u32)],
+    regions: &[RegionReport],
+) -> RgbaImage {
+    let mut image_buffer = image.to_rgba8();
+    let (width, height) = image_buffer.dimensions();
+    let mut suspicion = vec![0.0f64; width as usize * height as usize];
+    for (_, heatmap, orientation) in heatmaps {
+        for y in 0..height {
+            for x in 0..width {
+                let (hx, hy) = upright_point(x, y, *orientation, width, height);
+                let value = &mut suspicion[(y * width + x) as usize];
+                *value = value.max(heatmap.at(hx, hy));
+            }
+        }
+    }
+    for report in regions.iter().filter(|report| heatmaps.iter().all(|(name, ..)| *name != report.detector)) {
+        let Some(region) = report.region.clip(width, height) else {
+            continue;
+        };
+        for y in region.start.y..=region.end.y {
+            for x in region.start.x..=region.end.x {
+                let value = &mut suspicion[(y * width + x) as usize];
+                *value = value.max(region.confidence);
+            }
+        }
+    }
+    for (x, y, pixel) in image_buffer.enumerate_pixels_mut() {
+        let value = suspicion[(y * width + x) as usize].clamp(0.0, 1.0);
+        if value < MIN_SUSPICION {
+            continue;
+        }
+        let alpha = MAX_ALPHA * value;
+        let color = [255.0, 255.0 * (1.0 - value), 0.0];
+        let Rgba([r, g, b, a]) = *pixel;
+        let blend = |channel: u8, color: f64| (channel as f64 * (1.0 - alpha) + color * alpha).round() as u8;
+        *pixel = Rgba([blend(r, color[0]), blend(g, color[1]), blend(b, color[2]), a]);
+    }
+    image_buffer
+}
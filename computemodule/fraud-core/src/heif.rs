@@ -0,0 +1,57 @@
+use image::{load_from_memory, DynamicImage};
+use std::error::Error;
+
+const HEIC_BRANDS: [&[u8; 4]; 7] = [b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1"];
+const AVIF_BRANDS: [&[u8; 4]; 2] = [b"avif", b"avis"];
+
+// The major and compatible brands of an ISO-BMFF ftyp box
+pub(crate) fn ftyp_brands(data: &[u8]) -> Vec<&[u8]> {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return Vec::new();
+    }
+    let size = (u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize).clamp(16, data.len());
+    let compatible = data[16..size].chunks_exact(4);
+    std::iter::once(&data[8..12]).chain(compatible).collect()
+}
+
+/// Names the HEIF based format of the input, AVIF taking precedence as both share the `mif1` brand.
+pub fn heif_format(data: &[u8]) -> Option<&'static str> {
+    let brands = ftyp_brands(data);
+    if brands.iter().any(|brand| AVIF_BRANDS.iter().any(|avif| brand == avif)) {
+        Some("avif")
+    } else if brands.iter().any(|brand| HEIC_BRANDS.iter().any(|heic| brand == heic)) {
+        Some("heic")
+    } else {
+        None
+    }
+}
+
+#[cfg(any(feature = "heic", feature = "avif"))]
+fn decode_heif(data: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(data)?;
+    let handle = context.primary_image_handle()?;
+    let decoded = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+    let plane = decoded.planes().interleaved.ok_or("HEIF image has no interleaved plane")?;
+    let row = plane.width as usize * 4;
+    let pixels = plane.data.chunks(plane.stride).flat_map(|line| &line[..row]).copied().collect();
+    let image = RgbaImage::from_raw(plane.width, plane.height, pixels).ok_or("HEIF image is truncated")?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+#[cfg(not(any(feature = "heic", feature = "avif")))]
+fn decode_heif(_data: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    unreachable!("HEIF decoding is only reached with the heic or avif feature")
+}
+
+/// Decodes an encoded image, HEIC and AVIF included when built with their features.
+pub fn decode_image(data: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    match heif_format(data) {
+        Some("heic") if cfg!(feature = "heic") => decode_heif(data),
+        Some("avif") if cfg!(feature = "avif") => decode_heif(data),
+        Some(format) => Err(format!("{} input requires the {} feature", format.to_uppercase(), format).into()),
+        None => Ok(load_from_memory(data)?),
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{Annotation, DetectorSummary, Heatmap, Locale, OutputEncoding, Sensitivity};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// How a single job is analyzed and its results written, see
+/// [`Pipeline::job_settings`](crate::Pipeline::job_settings) for the configured ones.
+#[derive(Debug, Clone)]
+pub struct JobSettings {
+    pub sensitivity: Sensitivity,
+    pub encoding: OutputEncoding,
+    pub annotation: Annotation,
+    pub locale: Locale,
+    pub cancel: CancelToken,
+    pub progress: Progress,
+    pub artifacts: Artifacts,
+    /// Add the statistics behind every region to the result.
+    pub debug: bool,
+}
+
+/// Set from another thread to stop a job before its next detector, page or file.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Where a running job got to, updated by the pipeline as it goes.
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Arc<Mutex<ProgressUpdate>>);
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ProgressUpdate {
+    /// What the job is doing, `decoding`, the name of a detector, `annotating` or `encoding` as set by the
+    /// pipeline, or whatever its caller sets before.
+    pub stage: String,
+    /// Share of the image's detectors that finished.
+    pub percent: u8,
+    /// Detectors that finished so far, the partial evidence of jobs stopped before their verdict.
+    #[serde(skip)]
+    pub detectors: Vec<DetectorSummary>,
+    #[serde(skip)]
+    pub scores: BTreeMap<String, f64>,
+    /// Every stage so far and when it started.
+    #[serde(skip)]
+    pub stages: Vec<(String, SystemTime)>,
+}
+
+impl Progress {
+    pub fn set(&self, stage: &str, percent: u8) {
+        let mut update = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if update.stage != stage {
+            update.stages.push((stage.to_string(), SystemTime::now()));
+        }
+        update.stage = stage.to_string();
+        update.percent = percent;
+    }
+
+    pub fn finished(&self, summary: DetectorSummary, score: Option<f64>) {
+        let mut update = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(score) = score {
+            update.scores.insert(summary.name.clone(), score);
+        }
+        update.detectors.push(summary);
+    }
+
+    pub fn current(&self) -> ProgressUpdate {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// Intermediate results of a job kept for diagnosing its verdict, only
+/// collected by the pipeline when enabled.
+#[derive(Debug, Clone, Default)]
+pub struct Artifacts(Option<Arc<Mutex<Vec<DetectorMap>>>>);
+
+/// The heatmap one detector reported for one image of a job.
+#[derive(Debug, Clone)]
+pub struct DetectorMap {
+    /// Of the image, e.g. `<job ID> page 2` for the pages of a multi-page input.
+    pub job_id: String,
+    pub detector: &'static str,
+    pub heatmap: Heatmap,
+}
+
+impl Artifacts {
+    pub fn enabled() -> Artifacts {
+        Artifacts(Some(Arc::default()))
+    }
+
+    pub(crate) fn add_map(&self, job_id: &str, detector: &'static str, heatmap: &Heatmap) {
+        if let Some(maps) = &self.0 {
+            let map = DetectorMap { job_id: job_id.to_string(), detector, heatmap: heatmap.clone() };
+            maps.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(map);
+        }
+    }
+
+    pub fn maps(&self) -> Vec<DetectorMap> {
+        match &self.0 {
+            Some(maps) => maps.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The error of jobs stopped by their [`CancelToken`].
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Job was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
@@ -0,0 +1,93 @@
+mod animation;
+mod archive;
+mod bag;
+mod banner;
+mod benford;
+mod blocks;
+mod buffers;
+mod cfa;
+mod coco;
+mod dct;
+mod detector;
+mod double_jpeg;
+mod draw;
+mod encoding;
+mod error;
+mod ela;
+mod font;
+mod fusion;
+mod ghost;
+#[cfg(feature = "cuda")]
+mod gpu;
+mod heatmap;
+mod heif;
+mod job;
+mod lighting;
+mod limits;
+mod messages;
+mod metadata;
+mod noise;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod orientation;
+mod pages;
+mod pdf;
+mod pipeline;
+mod prescreen;
+mod prnu;
+mod redact;
+mod report;
+mod result;
+mod simd_base64;
+mod svg;
+mod thumbnail;
+mod tiff;
+mod video;
+mod zero;
+
+pub use archive::{is_zip, output_mime_type};
+pub use bag::BagDetector;
+pub use benford::BenfordDetector;
+pub use buffers::{BufferPool, BUFFERS, DEFAULT_BUFFER_POOL_BYTES};
+pub use cfa::CfaDetector;
+pub use coco::{CocoAnnotation, CocoCategory, CocoDataset, CocoImage};
+pub use detector::{detector_by_name, DetectionReport, Detector, RegionEvidence, DETECTOR_NAMES};
+pub use double_jpeg::DoubleJpegDetector;
+pub use draw::{draw_hollow_rect, Annotation, Color, LineStyle, Point, Region, SCALE_REFERENCE};
+pub use ela::ElaDetector;
+pub use encoding::{OutputEncoding, OutputFormat, DEFAULT_OUTPUT_QUALITY};
+pub use error::{ErrorReport, JobError};
+pub use fusion::{merge_regions, severity_score, suppress_duplicates, Fusion, RegionArea, Sensitivity, Verdict};
+pub use ghost::JpegGhostDetector;
+#[cfg(feature = "cuda")]
+pub use gpu::use_gpu;
+pub use heatmap::{Heatmap, Overlay};
+pub use heif::{decode_image, heif_format};
+pub use job::{Artifacts, CancelToken, Cancelled, DetectorMap, JobSettings, Progress, ProgressUpdate};
+pub use lighting::LightingDetector;
+pub use limits::{estimated_memory, InputLimits, DEFAULT_MAX_DIMENSION, DEFAULT_MAX_INPUT_BYTES, DEFAULT_MAX_PIXELS};
+pub use messages::Locale;
+pub use metadata::{MetadataDetector, MetadataFinding};
+pub use noise::NoiseDetector;
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxDetector;
+pub use pages::{split_pages, PageImage};
+pub use pipeline::{
+    batch_result, Analysis, Pipeline, DEFAULT_MERGE_GAP, DEFAULT_NMS_IOU, DEFAULT_THUMBNAIL_SIZE, DEFAULT_VIDEO_SAMPLE_RATE,
+};
+pub use prnu::{camera_key, claimed_camera, Fingerprint, PrnuDetector};
+pub use report::{hex, timestamp};
+pub use result::{
+    DetectorSummary, FileResult, PageResult, QueryResult, RegionCrop, RegionDebug, RegionReport, ResultSignature,
+    TimeRange,
+};
+pub use simd_base64::{decode_base64_into, encode_base64, encode_base64_into};
+pub use svg::SvgOverlay;
+pub use thumbnail::ThumbnailDetector;
+pub use zero::ZeroDetector;
+
+/// Runs the default detection pipeline over an encoded image and returns the
+/// verdict, a description of every forged region and the annotated image.
+pub fn detect(job_id: &str, image_data: &[u8]) -> Result<QueryResult, JobError> {
+    Pipeline::default().detect(job_id, image_data)
+}
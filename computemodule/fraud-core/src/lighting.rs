@@ -0,0 +1,226 @@
+use crate::detector::{DetectionReport, Detector};
+use crate::Point;
+use image::{imageops, DynamicImage};
+use std::fmt;
+
+/// Estimates the in-plane light direction of each segmented object from the
+/// shading along its occluding contour, where the surface normal lies in the
+/// image plane and intensity is `albedo * (normal · light) + ambient`.
+/// Strongly disagreeing directions suggest objects were composited from
+/// different photos. Segmentation and the Lambertian model are crude, so
+/// the score is capped well below the verdict threshold.
+pub struct LightingDetector {
+    /// Minimum object area as a fraction of the image.
+    pub min_area: f64,
+    /// Minimum contour pixels an estimate is fitted to.
+    pub min_contour: usize,
+    /// Minimum fitted light strength (in 0-255 levels) for an estimate to count.
+    pub min_strength: f64,
+    /// Smallest angle in degrees between two objects' light directions that is flagged.
+    pub max_angle: f64,
+    pub max_score: f64,
+}
+
+impl Default for LightingDetector {
+    fn default() -> Self {
+        LightingDetector { min_area: 0.005, min_contour: 50, min_strength: 10.0, max_angle: 90.0, max_score: 0.4 }
+    }
+}
+
+struct Estimate {
+    start: Point,
+    end: Point,
+    /// Degrees counterclockwise from the right, 90 is light from above.
+    angle: f64,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = (self.start, self.end);
+        write!(f, "{:.0} for the object at ({}, {})-({}, {})", self.angle, start.x, start.y, end.x, end.y)
+    }
+}
+
+fn otsu(values: &[f32]) -> f32 {
+    let mut histogram = [0usize; 256];
+    for &v in values {
+        histogram[v.clamp(0.0, 255.0) as usize] += 1;
+    }
+    let total = values.len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(i, &c)| i as f64 * c as f64).sum();
+    let (mut below, mut below_sum, mut best, mut threshold) = (0.0, 0.0, 0.0, 0);
+    for (i, &count) in histogram.iter().enumerate() {
+        below += count as f64;
+        below_sum += i as f64 * count as f64;
+        if below == 0.0 || below == total {
+            continue;
+        }
+        let (mean_below, mean_above) = (below_sum / below, (sum - below_sum) / (total - below));
+        let variance = below * (total - below) * (mean_below - mean_above).powi(2);
+        if variance > best {
+            best = variance;
+            threshold = i;
+        }
+    }
+    threshold as f32 + 0.5
+}
+
+// Solves the 3x3 normal equations by Cramer's rule
+fn solve(a: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(a);
+    if d.abs() < 1e-9 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (i, x) in x.iter_mut().enumerate() {
+        let mut m = a;
+        for row in 0..3 {
+            m[row][i] = b[row];
+        }
+        *x = det(m) / d;
+    }
+    Some(x)
+}
+
+fn angle_between(a: f64, b: f64) -> f64 {
+    let difference = (a - b).rem_euclid(360.0);
+    difference.min(360.0 - difference)
+}
+
+impl LightingDetector {
+    // Connected components of one Otsu class that don't touch the image border
+    fn objects(&self, width: usize, height: usize, foreground: &[bool]) -> Vec<Vec<usize>> {
+        let min_area = (self.min_area * (width * height) as f64) as usize;
+        let mut seen = vec![false; foreground.len()];
+        let mut objects = Vec::new();
+        for start in 0..foreground.len() {
+            if !foreground[start] || seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let (mut stack, mut pixels, mut touches_border) = (vec![start], Vec::new(), false);
+            while let Some(index) = stack.pop() {
+                pixels.push(index);
+                let (x, y) = (index % width, index / width);
+                touches_border |= x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+                let neighbours = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < width).then(|| index + 1),
+                    (y > 0).then(|| index - width),
+                    (y + 1 < height).then(|| index + width),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if foreground[next] && !seen[next] {
+                        seen[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            if !touches_border && pixels.len() >= min_area {
+                objects.push(pixels);
+            }
+        }
+        objects
+    }
+
+    fn estimate(&self, width: usize, luma: &[f32], inside: &[bool], pixels: &[usize]) -> Option<Estimate> {
+        let (mut a, mut b, mut count) = ([[0.0; 3]; 3], [0.0; 3], 0);
+        for &index in pixels {
+            let (x, y) = ((index % width) as i64, (index / width) as i64);
+            let at = |dx: i64, dy: i64| inside[((y + dy) * width as i64 + x + dx) as usize];
+            if at(-1, 0) && at(1, 0) && at(0, -1) && at(0, 1) {
+                continue;
+            }
+            // Outward normal from the surrounding object pixels, objects never touch the border
+            let (mut nx, mut ny) = (0.0, 0.0);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if at(dx, dy) {
+                        nx -= dx as f64;
+                        ny += dy as f64;
+                    }
+                }
+            }
+            let length = (nx * nx + ny * ny).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+            let (nx, ny) = (nx / length, ny / length);
+            let row = [nx, ny, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    a[i][j] += row[i] * row[j];
+                }
+                b[i] += row[i] * luma[index] as f64;
+            }
+            count += 1;
+        }
+        if count < self.min_contour {
+            return None;
+        }
+        let [lx, ly, _] = solve(a, b)?;
+        if (lx * lx + ly * ly).sqrt() < self.min_strength {
+            return None;
+        }
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        for &index in pixels {
+            let (x, y) = (index % width, index / width);
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        }
+        let (start, end) = (Point { x: min_x as u32, y: min_y as u32 }, Point { x: max_x as u32, y: max_y as u32 });
+        Some(Estimate {
+            start,
+            end,
+            angle: ly.atan2(lx).to_degrees().rem_euclid(360.0),
+        })
+    }
+}
+
+impl Detector for LightingDetector {
+    fn name(&self) -> &'static str {
+        "lighting"
+    }
+
+    fn upright(&self) -> bool {
+        true
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        // Blurring works on 0-1 floats, scaled to 0-255 afterwards
+        let smoothed = imageops::blur(&img.to_luma32f(), 1.5);
+        let (width, height) = (smoothed.width() as usize, smoothed.height() as usize);
+        let luma: Vec<f32> = smoothed.pixels().map(|p| p.0[0] * 255.0).collect();
+        let threshold = otsu(&luma);
+
+        let mut estimates = Vec::new();
+        for bright in [true, false] {
+            let inside: Vec<bool> = luma.iter().map(|&v| (v > threshold) == bright).collect();
+            for pixels in self.objects(width, height, &inside) {
+                estimates.extend(self.estimate(width, &luma, &inside, &pixels));
+            }
+        }
+
+        let mut worst: Option<(f64, &Estimate, &Estimate)> = None;
+        for (i, a) in estimates.iter().enumerate() {
+            for b in &estimates[i + 1..] {
+                let angle = angle_between(a.angle, b.angle);
+                if worst.is_none_or(|(max, _, _)| angle > max) {
+                    worst = Some((angle, a, b));
+                }
+            }
+        }
+        let Some((angle, a, b)) = worst else {
+            return DetectionReport::default();
+        };
+        let mut findings = Vec::new();
+        if angle >= self.max_angle {
+            findings.push(format!("light directions differ by {:.0} degrees, {} and {}", angle, a, b));
+        }
+        DetectionReport { findings, score: Some(self.max_score * angle / 180.0), ..DetectionReport::default() }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::JobError;
+use image::io::Reader as ImageReader;
+use std::io::Cursor;
+
+pub const DEFAULT_MAX_INPUT_BYTES: usize = 100 * 1024 * 1024;
+pub const DEFAULT_MAX_DIMENSION: u32 = 32768;
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+// Rough bytes per pixel a job holds at full resolution for the decoded image, its annotated copy and their encoding
+const IMAGE_BYTES_PER_PIXEL: u64 = 12;
+// And for the working copies of each detector running on it, luma planes of floats and recompressed copies
+const DETECTOR_BYTES_PER_PIXEL: u64 = 16;
+
+/// Bounds on inputs, checked before they are decoded so a single huge image
+/// cannot exhaust memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputLimits {
+    pub max_bytes: usize,
+    /// Widest or tallest an image may be.
+    pub max_dimension: u32,
+    /// Most pixels, width times height, an image may have.
+    pub max_pixels: u64,
+    /// Most memory analyzing a single image may take by [`estimated_memory`],
+    /// unbounded when unset.
+    pub max_memory_bytes: Option<u64>,
+}
+
+/// Roughly how much memory analyzing an image of `pixels` takes, when its
+/// detectors run on as many pixels as `detector_pixels` lists, all at once
+/// when `parallel` or else one after another.
+pub fn estimated_memory(pixels: u64, detector_pixels: &[u64], parallel: bool) -> u64 {
+    let working = if parallel {
+        detector_pixels.iter().sum()
+    } else {
+        detector_pixels.iter().copied().max().unwrap_or(0)
+    };
+    pixels * IMAGE_BYTES_PER_PIXEL + working * DETECTOR_BYTES_PER_PIXEL
+}
+
+// From the header, where the format has them there
+pub(crate) fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let reader = ImageReader::new(Cursor::new(data)).with_guessed_format();
+    reader.ok().map(ImageReader::into_dimensions)?.ok()
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        InputLimits {
+            max_bytes: DEFAULT_MAX_INPUT_BYTES,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            max_memory_bytes: None,
+        }
+    }
+}
+
+impl InputLimits {
+    /// Fails inputs of more than `max_bytes`, and images whose header gives them
+    /// more pixels than allowed. Documents, archives, videos and HEIF images have
+    /// no dimensions to read up front, their images are checked once extracted.
+    pub fn check(&self, data: &[u8]) -> Result<(), JobError> {
+        if data.len() > self.max_bytes {
+            let problem = format!("Input is {} bytes, at most {} are allowed", data.len(), self.max_bytes);
+            return Err(JobError::TooLarge(problem));
+        }
+        let Some((width, height)) = dimensions(data) else {
+            return Ok(());
+        };
+        self.check_dimensions(width, height).map_err(JobError::TooLarge)
+    }
+
+    /// Why an image of `width` by `height` is too large, if it is.
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), String> {
+        if width.max(height) > self.max_dimension {
+            let max = self.max_dimension;
+            return Err(format!("Image is {}x{}, at most {} pixels wide and tall are allowed", width, height, max));
+        }
+        if u64::from(width) * u64::from(height) > self.max_pixels {
+            return Err(format!("Image is {}x{}, at most {} pixels are allowed", width, height, self.max_pixels));
+        }
+        Ok(())
+    }
+}
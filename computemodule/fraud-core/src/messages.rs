@@ -0,0 +1,171 @@
+use crate::{RegionReport, TimeRange};
+use serde::Deserialize;
+use std::fmt::{Display, Write as _};
+use std::str::FromStr;
+
+/// The language the `text` of results is written in. Findings of detectors
+/// are always English.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "en" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            "fr" => Ok(Locale::Fr),
+            "es" => Ok(Locale::Es),
+            _ => Err(String::from("expected en, de, fr or es")),
+        }
+    }
+}
+
+// Templates with named placeholders in braces
+struct Messages {
+    region: &'static str,
+    metadata: &'static str,
+    page: &'static str,
+    frame: &'static str,
+    image: &'static str,
+    tampering: &'static str,
+    verdict: &'static str,
+    // Names of the verdict codes in VERDICTS order
+    verdicts: [&'static str; 6],
+    decimal: char,
+}
+
+const VERDICTS: [&str; 6] = ["clean", "cropped", "suspicious", "edited", "editcrop", "Failed"];
+
+const EN: Messages = Messages {
+    region: "Forged region #{index}: from ({x0}, {y0}) to ({x1}, {y1}), confidence {confidence}",
+    metadata: "metadata: {detail}",
+    page: "Page {page} image {image}",
+    frame: "Frame {frame}",
+    image: "Image {index}",
+    tampering: "Tampering from {start}s to {end}s",
+    verdict: "{label}: {result}",
+    verdicts: VERDICTS,
+    decimal: '.',
+};
+
+const DE: Messages = Messages {
+    region: "Gefälschte Region #{index}: von ({x0}, {y0}) bis ({x1}, {y1}), Konfidenz {confidence}",
+    metadata: "Metadaten: {detail}",
+    page: "Seite {page} Bild {image}",
+    frame: "Einzelbild {frame}",
+    image: "Bild {index}",
+    tampering: "Manipulation von {start} s bis {end} s",
+    verdict: "{label}: {result}",
+    verdicts: ["unverändert", "zugeschnitten", "verdächtig", "bearbeitet", "bearbeitet und zugeschnitten", "fehlgeschlagen"],
+    decimal: ',',
+};
+
+const FR: Messages = Messages {
+    region: "Région falsifiée n°{index} : de ({x0}, {y0}) à ({x1}, {y1}), confiance {confidence}",
+    metadata: "métadonnées : {detail}",
+    page: "Page {page} image {image}",
+    frame: "Trame {frame}",
+    image: "Image {index}",
+    tampering: "Manipulation de {start} s à {end} s",
+    verdict: "{label} : {result}",
+    verdicts: ["intacte", "recadrée", "suspecte", "modifiée", "modifiée et recadrée", "échec"],
+    decimal: ',',
+};
+
+const ES: Messages = Messages {
+    region: "Región falsificada n.º {index}: de ({x0}, {y0}) a ({x1}, {y1}), confianza {confidence}",
+    metadata: "metadatos: {detail}",
+    page: "Página {page} imagen {image}",
+    frame: "Fotograma {frame}",
+    image: "Imagen {index}",
+    tampering: "Manipulación de {start} s a {end} s",
+    verdict: "{label}: {result}",
+    verdicts: ["limpia", "recortada", "sospechosa", "editada", "editada y recortada", "fallida"],
+    decimal: ',',
+};
+
+// In one pass, so values are never taken for placeholders
+fn fill(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some((open, close)) = rest.find('{').and_then(|open| Some((open, open + rest[open..].find('}')?))) {
+        text.push_str(&rest[..open]);
+        match args.iter().find(|(name, _)| *name == &rest[open + 1..close]) {
+            Some((_, value)) => {
+                let _ = write!(text, "{}", value);
+            }
+            None => text.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+impl Locale {
+    fn messages(self) -> &'static Messages {
+        match self {
+            Locale::En => &EN,
+            Locale::De => &DE,
+            Locale::Fr => &FR,
+            Locale::Es => &ES,
+        }
+    }
+
+    fn number(self, value: f64) -> String {
+        format!("{:.2}", value).replace('.', &self.messages().decimal.to_string())
+    }
+
+    pub(crate) fn region(self, report: &RegionReport) -> String {
+        let (start, end) = (report.region.start, report.region.end);
+        let confidence = self.number(report.region.confidence);
+        fill(
+            self.messages().region,
+            &[
+                ("index", &report.index),
+                ("x0", &start.x),
+                ("y0", &start.y),
+                ("x1", &end.x),
+                ("y1", &end.y),
+                ("confidence", &confidence),
+            ],
+        )
+    }
+
+    pub(crate) fn metadata(self, detail: &str) -> String {
+        fill(self.messages().metadata, &[("detail", &detail)])
+    }
+
+    pub(crate) fn page(self, page: usize, image: usize) -> String {
+        fill(self.messages().page, &[("page", &page), ("image", &image)])
+    }
+
+    pub(crate) fn frame(self, frame: usize) -> String {
+        fill(self.messages().frame, &[("frame", &frame)])
+    }
+
+    pub(crate) fn image(self, index: usize) -> String {
+        fill(self.messages().image, &[("index", &index)])
+    }
+
+    pub(crate) fn tampering(self, range: &TimeRange) -> String {
+        let (start, end) = (self.number(range.start), self.number(range.end));
+        fill(self.messages().tampering, &[("start", &start), ("end", &end)])
+    }
+
+    /// `label` followed by the name of the verdict code `result`.
+    pub(crate) fn verdict(self, label: &str, result: &str) -> String {
+        let messages = self.messages();
+        let name = VERDICTS.iter().position(|code| *code == result).map_or(result, |i| messages.verdicts[i]);
+        fill(messages.verdict, &[("label", &label), ("result", &name)])
+    }
+}
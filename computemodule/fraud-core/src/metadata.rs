@@ -0,0 +1,256 @@
+use crate::detector::{DetectionReport, Detector};
+use crate::report::days_from_civil;
+use exif::{Exif, In, Tag, Value};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// A metadata inconsistency, e.g. `editing_software` or `timestamp_mismatch`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataFinding {
+    pub kind: String,
+    pub detail: String,
+}
+
+impl MetadataFinding {
+    fn new(kind: &str, detail: String) -> Self {
+        MetadataFinding { kind: String::from(kind), detail }
+    }
+}
+
+/// Cross checks EXIF, XMP and JFIF metadata against each other and the
+/// decoded image. Metadata is easy to strip or forge, so these findings are
+/// supporting evidence and never localize to a region.
+pub struct MetadataDetector {
+    /// Lowercase substrings identifying editing software in software tags.
+    pub editors: Vec<String>,
+    /// Largest tolerated gap between capture and modification timestamps.
+    pub max_timestamp_drift_secs: i64,
+}
+
+const EDITORS: &[&str] = &[
+    "photoshop",
+    "lightroom",
+    "gimp",
+    "affinity",
+    "paint.net",
+    "pixelmator",
+    "snapseed",
+    "picsart",
+    "canva",
+    "illustrator",
+    "coreldraw",
+    "paintshop",
+    "facetune",
+    "photopea",
+];
+
+impl Default for MetadataDetector {
+    fn default() -> Self {
+        MetadataDetector { editors: EDITORS.iter().map(|e| e.to_string()).collect(), max_timestamp_drift_secs: 60 }
+    }
+}
+
+pub(crate) fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|value| value.to_f64()),
+        _ => None,
+    }
+}
+
+// Seconds since the epoch for "YYYY:MM:DD HH:MM:SS" (EXIF) or "YYYY-MM-DDTHH:MM:SS" (XMP) timestamps
+fn timestamp_secs(value: &str) -> Option<i64> {
+    let digits: Vec<i64> = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .take(6)
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [year, month, day, hour, minute, second] = digits[..] else {
+        return None;
+    };
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn xmp_packet(encoded: &[u8]) -> Option<String> {
+    let start = find(encoded, b"<x:xmpmeta")?;
+    let end = find(&encoded[start..], b"</x:xmpmeta>")? + start;
+    Some(String::from_utf8_lossy(&encoded[start..end]).into_owned())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// Values of an XMP property written either as an attribute or as an element
+fn xmp_values(xmp: &str, name: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    for (open, close) in [(format!("{}=\"", name), "\""), (format!("<{}>", name), "<")] {
+        let mut rest = xmp;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            if let Some(end) = rest.find(close) {
+                values.push(rest[..end].trim().to_string());
+            }
+        }
+    }
+    values
+}
+
+struct Jfif {
+    units: u8,
+    x_density: u16,
+    y_density: u16,
+}
+
+fn jfif(encoded: &[u8]) -> Option<Jfif> {
+    if !encoded.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut offset = 2;
+    while offset + 4 <= encoded.len() && encoded[offset] == 0xFF {
+        let marker = encoded[offset + 1];
+        let length = u16::from_be_bytes([encoded[offset + 2], encoded[offset + 3]]) as usize;
+        let segment = encoded.get(offset + 4..offset + 2 + length)?;
+        if marker == 0xE0 && segment.starts_with(b"JFIF\0") && segment.len() >= 12 {
+            return Some(Jfif {
+                units: segment[7],
+                x_density: u16::from_be_bytes([segment[8], segment[9]]),
+                y_density: u16::from_be_bytes([segment[10], segment[11]]),
+            });
+        }
+        // Start of scan, no more metadata segments follow
+        if marker == 0xDA {
+            return None;
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+impl MetadataDetector {
+    fn editor<'a>(&self, software: &'a str) -> Option<&'a str> {
+        let lower = software.to_lowercase();
+        self.editors.iter().any(|editor| lower.contains(editor.as_str())).then_some(software)
+    }
+
+    fn check_timestamps(
+        &self,
+        label: &str,
+        captured: Option<String>,
+        modified: Option<String>,
+        findings: &mut Vec<MetadataFinding>,
+    ) {
+        let (Some(captured), Some(modified)) = (captured, modified) else {
+            return;
+        };
+        if let (Some(c), Some(m)) = (timestamp_secs(&captured), timestamp_secs(&modified)) {
+            if (m - c).abs() > self.max_timestamp_drift_secs {
+                findings.push(MetadataFinding::new(
+                    "timestamp_mismatch",
+                    format!("{} captured {} but modified {}", label, captured, modified),
+                ));
+            }
+        }
+    }
+
+    fn check_exif(&self, img: &DynamicImage, exif: &Exif, findings: &mut Vec<MetadataFinding>) {
+        if let Some(software) = ascii(exif, Tag::Software).as_deref().and_then(|s| self.editor(s)) {
+            findings.push(MetadataFinding::new("editing_software", format!("EXIF Software is {}", software)));
+        }
+        self.check_timestamps("EXIF", ascii(exif, Tag::DateTimeOriginal), ascii(exif, Tag::DateTime), findings);
+        self.check_timestamps(
+            "EXIF",
+            ascii(exif, Tag::DateTimeOriginal),
+            ascii(exif, Tag::DateTimeDigitized),
+            findings,
+        );
+
+        if let Some(make) = ascii(exif, Tag::Make) {
+            if exif.get_field(Tag::MakerNote, In::PRIMARY).is_none() {
+                findings.push(MetadataFinding::new(
+                    "missing_maker_note",
+                    format!("camera make {} is set but the maker notes were stripped", make),
+                ));
+            }
+        }
+        if let (Some(width), Some(height)) = (uint(exif, Tag::PixelXDimension), uint(exif, Tag::PixelYDimension)) {
+            if (width, height) != (img.width(), img.height()) {
+                findings.push(MetadataFinding::new(
+                    "dimension_mismatch",
+                    format!("EXIF reports {}x{} but the image is {}x{}", width, height, img.width(), img.height()),
+                ));
+            }
+        }
+    }
+
+    fn check_xmp(&self, xmp: &str, findings: &mut Vec<MetadataFinding>) {
+        for tag in ["xmp:CreatorTool", "stEvt:softwareAgent"] {
+            for software in xmp_values(xmp, tag) {
+                if self.editor(&software).is_some() {
+                    findings.push(MetadataFinding::new("editing_software", format!("XMP {} is {}", tag, software)));
+                }
+            }
+        }
+        let first = |name| xmp_values(xmp, name).into_iter().next();
+        self.check_timestamps("XMP", first("xmp:CreateDate"), first("xmp:ModifyDate"), findings);
+    }
+
+    fn check_resolution(&self, exif: &Exif, jfif: &Jfif, findings: &mut Vec<MetadataFinding>) {
+        // JFIF units 1 and 2 are dots per inch and per cm, EXIF ResolutionUnit 2 and 3
+        let (Some(x), Some(y)) = (rational(exif, Tag::XResolution), rational(exif, Tag::YResolution)) else {
+            return;
+        };
+        let exif_unit = uint(exif, Tag::ResolutionUnit).unwrap_or(2);
+        if jfif.units == 0 || jfif.units as u32 + 1 != exif_unit {
+            return;
+        }
+        let matches = |exif: f64, jfif: u16| (exif - jfif as f64).abs() < 1.0;
+        if !matches(x, jfif.x_density) || !matches(y, jfif.y_density) {
+            findings.push(MetadataFinding::new(
+                "resolution_mismatch",
+                format!("EXIF resolution {}x{} differs from JFIF density {}x{}", x, y, jfif.x_density, jfif.y_density),
+            ));
+        }
+    }
+}
+
+impl Detector for MetadataDetector {
+    fn name(&self) -> &'static str {
+        "metadata"
+    }
+
+    // Metadata only exists in the encoded file
+    fn analyze(&self, _img: &DynamicImage) -> DetectionReport {
+        DetectionReport::default()
+    }
+
+    fn analyze_encoded(&self, img: &DynamicImage, encoded: &[u8]) -> DetectionReport {
+        let mut findings = Vec::new();
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(encoded)).ok();
+        if let Some(exif) = &exif {
+            self.check_exif(img, exif, &mut findings);
+        }
+        if let Some(xmp) = xmp_packet(encoded) {
+            self.check_xmp(&xmp, &mut findings);
+        }
+        if let (Some(exif), Some(jfif)) = (&exif, jfif(encoded)) {
+            self.check_resolution(exif, &jfif, &mut findings);
+        }
+        DetectionReport { metadata: findings, ..DetectionReport::default() }
+    }
+}
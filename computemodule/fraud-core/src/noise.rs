@@ -0,0 +1,89 @@
+use crate::blocks::BlockGrid;
+use crate::detector::{DetectionReport, Detector};
+use image::{DynamicImage, GrayImage};
+
+/// Estimates the sensor noise level of every block from a high-pass residual
+/// and flags blocks whose noise differs strongly from the rest of the image,
+/// which is typical for content spliced in from another source.
+pub struct NoiseDetector {
+    pub block_size: u32,
+    /// How many robust standard deviations a block's noise level must deviate by.
+    pub sensitivity: f64,
+    /// Minimum absolute deviation of the noise level (in 0-255 levels).
+    pub min_deviation: f64,
+    pub min_blocks: usize,
+}
+
+impl Default for NoiseDetector {
+    fn default() -> Self {
+        NoiseDetector { block_size: 32, sensitivity: 5.0, min_deviation: 1.0, min_blocks: 4 }
+    }
+}
+
+// Response of the Laplacian-like noise estimation kernel from Immerkær's
+// "Fast Noise Variance Estimation", which cancels out most image structure.
+fn residual(luma: &GrayImage, x: u32, y: u32) -> f64 {
+    let p = |dx: i32, dy: i32| luma.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32).0[0] as f64;
+    p(-1, -1) - 2.0 * p(0, -1) + p(1, -1)
+        - 2.0 * p(-1, 0) + 4.0 * p(0, 0) - 2.0 * p(1, 0)
+        + p(-1, 1) - 2.0 * p(0, 1) + p(1, 1)
+}
+
+impl NoiseDetector {
+    fn noise_grid(&self, luma: &GrayImage) -> BlockGrid {
+        let (width, height) = luma.dimensions();
+        #[cfg(feature = "cuda")]
+        let filtered = crate::gpu::noise_residuals(luma);
+        #[cfg(not(feature = "cuda"))]
+        let filtered: Option<Vec<f32>> = None;
+        let residual = |x: u32, y: u32| match &filtered {
+            Some(filtered) => f64::from(filtered[((y - 1) * (width - 2) + x - 1) as usize]),
+            None => residual(luma, x, y),
+        };
+        BlockGrid::from_fn(width, height, self.block_size, |x0, y0, x1, y1| {
+            let mut residuals = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+            let mut brightness = 0.0;
+            for y in y0.max(1)..y1.min(height - 1) {
+                for x in x0.max(1)..x1.min(width - 1) {
+                    residuals.push(residual(x, y).abs());
+                    brightness += luma.get_pixel(x, y).0[0] as f64;
+                }
+            }
+            if residuals.is_empty() {
+                return f64::NAN;
+            }
+            // Clipped highlights and shadows carry no noise, keep them out of the statistics
+            let brightness = brightness / residuals.len() as f64;
+            if !(8.0..=247.0).contains(&brightness) {
+                return f64::NAN;
+            }
+            residuals.sort_by(|a, b| a.total_cmp(b));
+            // Median absolute residual of Gaussian noise, normalized by the kernel gain (sqrt(36) = 6)
+            residuals[residuals.len() / 2] / (0.6745 * 6.0)
+        })
+    }
+}
+
+impl Detector for NoiseDetector {
+    fn name(&self) -> &'static str {
+        "noise"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "noise_level"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let luma = img.to_luma8();
+        if luma.width() < 3 || luma.height() < 3 {
+            return DetectionReport::default();
+        }
+        let grid = self.noise_grid(&luma);
+        let mask = grid.outliers(self.sensitivity, self.min_deviation);
+        DetectionReport {
+            regions: grid.regions(&mask, self.min_blocks),
+            heatmap: Some(grid.heatmap(self.sensitivity, self.min_deviation, true)),
+            ..DetectionReport::default()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::blocks::BlockGrid;
+use crate::heatmap::suspicion;
+use crate::detector::{DetectionReport, Detector};
+use crate::Heatmap;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use log::warn;
+use ort::session::builder::SessionBuilder;
+use ort::session::Session;
+use ort::value::Tensor;
+use std::panic;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Runs a user supplied tamper localization model. The model takes a
+/// `[1, 3, size, size]` RGB tensor scaled to `0..1` and returns a tamper
+/// probability mask of any resolution as its first output, which is
+/// thresholded and mapped back onto the image.
+pub struct OnnxDetector {
+    // Running a session needs exclusive access
+    session: Mutex<Session>,
+    pub input_size: u32,
+    pub threshold: f32,
+    /// Minimum number of flagged mask pixels for a region to be reported.
+    pub min_pixels: usize,
+}
+
+pub(crate) fn session_builder() -> Result<SessionBuilder, ort::Error> {
+    // ort panics instead of failing when libonnxruntime can't be loaded
+    let builder = panic::catch_unwind(Session::builder);
+    builder.map_err(|_| ort::Error::new("failed to load ONNX Runtime, check ORT_DYLIB_PATH"))?
+}
+
+impl OnnxDetector {
+    /// On the GPU when one is in use and ONNX Runtime can run the model on it, see [`use_gpu`](crate::use_gpu).
+    pub fn load(model_path: &Path) -> Result<OnnxDetector, ort::Error> {
+        let builder = session_builder()?;
+        #[cfg(feature = "cuda")]
+        let builder = match crate::gpu::cuda(false) {
+            Some(cuda) => builder.with_execution_providers([cuda])?,
+            None => builder,
+        };
+        let session = builder.commit_from_file(model_path)?;
+        Ok(OnnxDetector { session: Mutex::new(session), input_size: 512, threshold: 0.5, min_pixels: 16 })
+    }
+
+    fn mask(&self, img: &DynamicImage) -> Result<BlockGrid, ort::Error> {
+        let size = self.input_size;
+        let resized = img.resize_exact(size, size, FilterType::Triangle).to_rgb8();
+        let plane = (size * size) as usize;
+        let mut input = vec![0f32; 3 * plane];
+        for (index, pixel) in resized.pixels().enumerate() {
+            for c in 0..3 {
+                input[c * plane + index] = pixel.0[c] as f32 / 255.0;
+            }
+        }
+        let input = Tensor::from_array(([1usize, 3, size as usize, size as usize], input))?;
+
+        let mut session = self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let outputs = session.run(ort::inputs![input])?;
+        let (shape, mask) = outputs[0].try_extract_tensor::<f32>()?;
+        // The mask is the last two dimensions, e.g. [1, 1, h, w] or [1, h, w]
+        let dims: Vec<u32> = shape.iter().rev().take(2).map(|&d| d as u32).collect();
+        let (width, height) = match dims[..] {
+            [width, height] if (width * height) as usize == mask.len() => (width, height),
+            _ => return Err(ort::Error::new(format!("unexpected mask shape {:?}", shape))),
+        };
+        Ok(BlockGrid::from_fn(width, height, 1, |x, y, _, _| mask[(y * width + x) as usize] as f64))
+    }
+}
+
+impl Detector for OnnxDetector {
+    fn name(&self) -> &'static str {
+        "onnx"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "model_mask"
+    }
+
+    fn upright(&self) -> bool {
+        true
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let grid = match self.mask(img) {
+            Ok(grid) => grid,
+            Err(err) => {
+                warn!("ONNX model failed: {}", err);
+                return DetectionReport::default();
+            }
+        };
+        let flagged: Vec<bool> = grid.values.iter().map(|&p| p > self.threshold as f64).collect();
+        let (sx, sy) = (img.width() as f64 / grid.width as f64, img.height() as f64 / grid.height as f64);
+        let regions = grid
+            .regions_with(&flagged, self.min_pixels, |probability| probability)
+            .into_iter()
+            .map(|region| region.scale(sx, sy, img.width(), img.height()))
+            .collect();
+        let heatmap = grid.heatmap_with(|probability| suspicion(probability, self.threshold as f64));
+        let heatmap = Heatmap { block_width: sx, block_height: sy, ..heatmap };
+        DetectionReport { regions, heatmap: Some(heatmap), ..DetectionReport::default() }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::heif::heif_format;
+use exif::{In, Tag};
+use image::DynamicImage;
+use std::io::Cursor;
+
+/// The EXIF orientation of an encoded image, 1 (stored upright) when absent.
+/// HEIF decoders apply the container's own rotation, so it is ignored there.
+pub(crate) fn exif_orientation(encoded: &[u8]) -> u32 {
+    if heif_format(encoded).is_some() {
+        return 1;
+    }
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(encoded))
+        .ok()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0))
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+/// Rotates and flips a stored image the way viewers display it.
+pub(crate) fn orient(image: &DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image.clone(),
+    }
+}
+
+/// Where pixel `(x, y)` of a `width`x`height` stored image ends up once oriented.
+pub(crate) fn upright_point(x: u32, y: u32, orientation: u32, width: u32, height: u32) -> (u32, u32) {
+    match orientation {
+        2 => (width - 1 - x, y),
+        3 => (width - 1 - x, height - 1 - y),
+        4 => (x, height - 1 - y),
+        5 => (y, x),
+        6 => (height - 1 - y, x),
+        7 => (height - 1 - y, width - 1 - x),
+        8 => (y, width - 1 - x),
+        _ => (x, y),
+    }
+}
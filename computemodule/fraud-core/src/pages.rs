@@ -0,0 +1,31 @@
+use crate::animation::animation_frames;
+use crate::limits::InputLimits;
+use crate::pdf::{extract_images, is_pdf};
+use crate::tiff::{is_tiff, tiff_pages};
+use crate::video::{is_video, video_frames};
+use image::Delay;
+
+/// One image of a multi-page input, encoded so the pipeline can load it.
+pub struct PageImage {
+    /// 1-based page or animation frame number.
+    pub page: usize,
+    pub data: Vec<u8>,
+    /// How long an animation or video frame is shown, None for document pages.
+    pub delay: Option<Delay>,
+}
+
+/// Splits multi-page inputs (PDFs, multi-frame TIFFs, animations and videos
+/// sampled at `video_sample_rate` frames per second) into their page images,
+/// or returns None for inputs holding a single image. Frames are checked
+/// against `limits` before they are decoded.
+pub fn split_pages(data: &[u8], video_sample_rate: f64, limits: &InputLimits) -> Option<Result<Vec<PageImage>, String>> {
+    if is_pdf(data) {
+        Some(extract_images(data, limits))
+    } else if is_video(data) {
+        Some(video_frames(data, video_sample_rate, limits))
+    } else if is_tiff(data) {
+        tiff_pages(data, limits)
+    } else {
+        animation_frames(data, limits)
+    }
+}
@@ -0,0 +1,534 @@
+use crate::animation::MAX_FRAMES;
+use crate::limits::InputLimits;
+use crate::pages::PageImage;
+use flate2::read::ZlibDecoder;
+use image::{DynamicImage, GrayImage, ImageOutputFormat, RgbImage};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+
+pub fn is_pdf(data: &[u8]) -> bool {
+    data.starts_with(b"%PDF-")
+}
+
+// Nesting limit for page trees and form XObjects, malformed files can contain cycles
+const MAX_DEPTH: usize = 32;
+// Largest stream inflated, like archive entries, so small compressed streams can't exhaust memory
+const MAX_STREAM_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+enum Object {
+    // Also stands in for booleans, nothing here needs their value
+    Null,
+    Number(f64),
+    Name(String),
+    String,
+    Array(Vec<Object>),
+    Dict(HashMap<String, Object>),
+    Ref(u32),
+    Stream(HashMap<String, Object>, Vec<u8>),
+}
+
+impl Object {
+    fn dict(&self) -> Option<&HashMap<String, Object>> {
+        match self {
+            Object::Dict(dict) | Object::Stream(dict, _) => Some(dict),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        match self {
+            Object::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Object::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' | b'\0')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(b, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'%' => {
+                    while self.pos < self.data.len() && !matches!(self.data[self.pos], b'\r' | b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                b if is_whitespace(b) => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn word(&mut self) -> &[u8] {
+        let start = self.pos;
+        while self.pos < self.data.len() && !is_whitespace(self.data[self.pos]) && !is_delimiter(self.data[self.pos]) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn peek_keyword(&mut self, keyword: &[u8]) -> bool {
+        self.skip_whitespace();
+        let end = self.pos + keyword.len();
+        let matches = self.data.get(self.pos..end) == Some(keyword);
+        matches && self.data.get(end).is_none_or(|&b| is_whitespace(b) || is_delimiter(b))
+    }
+
+    // An integer followed by `G R`, tried after every number inside containers
+    fn reference(&mut self, number: f64) -> Option<Object> {
+        let saved = self.pos;
+        self.skip_whitespace();
+        let generation = std::str::from_utf8(self.word()).ok().and_then(|w| w.parse::<u32>().ok());
+        if generation.is_some() && self.peek_keyword(b"R") && number.fract() == 0.0 && number >= 0.0 {
+            self.pos += 1;
+            return Some(Object::Ref(number as u32));
+        }
+        self.pos = saved;
+        None
+    }
+
+    fn object(&mut self, depth: usize) -> Option<Object> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        let first = *self.data.get(self.pos)?;
+        match first {
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut dict = HashMap::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.data.get(self.pos..self.pos + 2)? == b">>" {
+                        self.pos += 2;
+                        return Some(Object::Dict(dict));
+                    }
+                    let key = self.object(depth + 1)?.name()?.to_string();
+                    let value = self.object(depth + 1)?;
+                    dict.insert(key, value);
+                }
+            }
+            b'<' => {
+                let end = self.data[self.pos..].iter().position(|&b| b == b'>')?;
+                self.pos += end + 1;
+                Some(Object::String)
+            }
+            b'(' => {
+                let mut nesting = 0;
+                while let Some(&b) = self.data.get(self.pos) {
+                    self.pos += 1;
+                    match b {
+                        b'\\' => self.pos += 1,
+                        b'(' => nesting += 1,
+                        b')' if nesting == 1 => return Some(Object::String),
+                        b')' => nesting -= 1,
+                        _ => {}
+                    }
+                }
+                None
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.data.get(self.pos) == Some(&b']') {
+                        self.pos += 1;
+                        return Some(Object::Array(items));
+                    }
+                    items.push(self.object(depth + 1)?);
+                }
+            }
+            b'/' => {
+                self.pos += 1;
+                Some(Object::Name(String::from_utf8_lossy(self.word()).into_owned()))
+            }
+            _ => {
+                let word = self.word();
+                match word {
+                    b"true" | b"false" | b"null" => Some(Object::Null),
+                    _ => {
+                        let number = std::str::from_utf8(word).ok()?.parse::<f64>().ok()?;
+                        Some(self.reference(number).unwrap_or(Object::Number(number)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Document {
+    objects: HashMap<u32, Object>,
+}
+
+// Offsets just past every `N G obj` header, later definitions (incremental updates) win
+fn object_offsets(data: &[u8]) -> HashMap<u32, usize> {
+    // Start and end of the digits before `end`, skipping whitespace in between
+    let digits_before = |mut end: usize| {
+        while end > 0 && is_whitespace(data[end - 1]) {
+            end -= 1;
+        }
+        let digits_end = end;
+        while end > 0 && data[end - 1].is_ascii_digit() {
+            end -= 1;
+        }
+        (end < digits_end).then_some((end, digits_end))
+    };
+    let mut offsets = HashMap::new();
+    for (at, window) in data.windows(3).enumerate() {
+        let delimited = at > 0 && is_whitespace(data[at - 1]);
+        let ends = data.get(at + 3).is_none_or(|&b| is_whitespace(b) || is_delimiter(b));
+        if window != b"obj" || !delimited || !ends {
+            continue;
+        }
+        let Some((generation_start, _)) = digits_before(at) else { continue };
+        if generation_start == 0 || !is_whitespace(data[generation_start - 1]) {
+            continue;
+        }
+        let Some((id_start, id_end)) = digits_before(generation_start) else { continue };
+        if let Some(id) = std::str::from_utf8(&data[id_start..id_end]).ok().and_then(|id| id.parse().ok()) {
+            offsets.insert(id, at + 3);
+        }
+    }
+    offsets
+}
+
+fn parse_object(data: &[u8], offset: usize, offsets: &HashMap<u32, usize>) -> Option<Object> {
+    let mut lexer = Lexer { data, pos: offset };
+    let object = lexer.object(0)?;
+    if !lexer.peek_keyword(b"stream") {
+        return Some(object);
+    }
+    lexer.pos += b"stream".len();
+    if data.get(lexer.pos) == Some(&b'\r') {
+        lexer.pos += 1;
+    }
+    if data.get(lexer.pos) == Some(&b'\n') {
+        lexer.pos += 1;
+    }
+    let start = lexer.pos;
+    let dict = object.dict()?.clone();
+    let length = match dict.get("Length") {
+        Some(Object::Number(n)) => Some(*n as usize),
+        // The length object has to be a plain number, it can't be a stream itself
+        Some(Object::Ref(id)) => {
+            offsets.get(id).and_then(|&at| Lexer { data, pos: at }.object(0)?.number()).map(|n| n as usize)
+        }
+        _ => None,
+    };
+    // Trust the length only if `endstream` follows it, lengths past the end of the file are bogus
+    let followed = |end: usize| data.get(end..).is_some_and(|rest| rest.windows(9).take(16).any(|w| w == b"endstream"));
+    let end = match length.and_then(|length| start.checked_add(length)) {
+        Some(end) if followed(end) => end,
+        _ => {
+            let end = start + data[start..].windows(9).position(|w| w == b"endstream")?;
+            // The end of line before `endstream` is not part of the data
+            let end = if data[..end].ends_with(b"\n") { end - 1 } else { end };
+            if data[..end].ends_with(b"\r") { end - 1 } else { end }
+        }
+    };
+    Some(Object::Stream(dict, data[start..end].to_vec()))
+}
+
+fn filters(dict: &HashMap<String, Object>) -> Vec<String> {
+    match dict.get("Filter") {
+        Some(Object::Name(name)) => vec![name.clone()],
+        Some(Object::Array(items)) => items.iter().filter_map(|item| item.name().map(String::from)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Reverses the PNG row filters selected by `/Predictor 10` and up, rows can't be longer than the data or `max_row`
+fn unpredict(data: &[u8], colors: usize, columns: usize, max_row: usize) -> Option<Vec<u8>> {
+    let stride = colors.checked_mul(columns).filter(|&stride| stride <= data.len() && stride <= max_row)?;
+    let mut out = Vec::with_capacity(data.len());
+    let mut previous = vec![0u8; stride];
+    for row in data.chunks(stride + 1) {
+        let (&filter, row) = row.split_first()?;
+        let mut current = row.to_vec();
+        current.resize(stride, 0);
+        for i in 0..stride {
+            let left = if i >= colors { current[i - colors] } else { 0 };
+            let up_left = if i >= colors { previous[i - colors] } else { 0 };
+            current[i] = match filter {
+                0 => current[i],
+                1 => current[i].wrapping_add(left),
+                2 => current[i].wrapping_add(previous[i]),
+                3 => current[i].wrapping_add(((left as u16 + previous[i] as u16) / 2) as u8),
+                4 => current[i].wrapping_add(paeth(left, previous[i], up_left)),
+                _ => return None,
+            };
+        }
+        out.extend_from_slice(&current);
+        previous = current;
+    }
+    Some(out)
+}
+
+// None past MAX_STREAM_BYTES
+fn zlib(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data).take(MAX_STREAM_BYTES + 1).read_to_end(&mut out).ok()?;
+    (out.len() as u64 <= MAX_STREAM_BYTES).then_some(out)
+}
+
+// Predicted rows are at most `max_row` bytes
+fn inflate(dict: &HashMap<String, Object>, data: &[u8], max_row: usize) -> Option<Vec<u8>> {
+    let out = zlib(data)?;
+    let params = dict.get("DecodeParms").and_then(|params| match params {
+        Object::Array(items) => items.first().cloned(),
+        other => Some(other.clone()),
+    });
+    let param = |key: &str, default: f64| {
+        params.as_ref().and_then(|p| p.dict()).and_then(|p| p.get(key)).and_then(Object::number).unwrap_or(default)
+    };
+    match param("Predictor", 1.0) as u32 {
+        1 => Some(out),
+        p if p >= 10 => unpredict(&out, param("Colors", 1.0) as usize, param("Columns", 1.0) as usize, max_row),
+        _ => None,
+    }
+}
+
+impl Document {
+    fn parse(data: &[u8]) -> Document {
+        let offsets = object_offsets(data);
+        let mut objects: HashMap<u32, Object> = offsets
+            .iter()
+            .filter_map(|(&id, &offset)| Some((id, parse_object(data, offset, &offsets)?)))
+            .collect();
+
+        // Objects compressed into object streams
+        let mut compressed = Vec::new();
+        for object in objects.values() {
+            let Object::Stream(dict, stream) = object else { continue };
+            if dict.get("Type").and_then(Object::name) != Some("ObjStm") || filters(dict) != ["FlateDecode"] {
+                continue;
+            }
+            let count = dict.get("N").and_then(Object::number);
+            let first = dict.get("First").and_then(Object::number);
+            let (Some(count), Some(first)) = (count, first) else { continue };
+            let Some(content) = inflate(dict, stream, usize::MAX) else { continue };
+            let mut header = Lexer { data: &content, pos: 0 };
+            for _ in 0..count as usize {
+                let id = header.object(0).and_then(|id| id.number());
+                let offset = header.object(0).and_then(|offset| offset.number());
+                let (Some(id), Some(offset)) = (id, offset) else { break };
+                let Some(pos) = (first as usize).checked_add(offset as usize).filter(|&pos| pos <= content.len()) else {
+                    break;
+                };
+                let mut lexer = Lexer { data: &content, pos };
+                if let Some(object) = lexer.object(0) {
+                    compressed.push((id as u32, object));
+                }
+            }
+        }
+        for (id, object) in compressed {
+            objects.entry(id).or_insert(object);
+        }
+        Document { objects }
+    }
+
+    fn resolve<'a>(&'a self, object: &'a Object) -> &'a Object {
+        let mut object = object;
+        for _ in 0..MAX_DEPTH {
+            match object {
+                Object::Ref(id) => object = self.objects.get(id).unwrap_or(&Object::Null),
+                _ => break,
+            }
+        }
+        object
+    }
+
+    fn get<'a>(&'a self, dict: &'a HashMap<String, Object>, key: &str) -> Option<&'a Object> {
+        dict.get(key).map(|value| self.resolve(value))
+    }
+
+    fn pages(&self) -> Result<Vec<HashMap<String, Object>>, String> {
+        let catalog = self
+            .objects
+            .values()
+            .filter_map(Object::dict)
+            .find(|dict| dict.get("Type").and_then(Object::name) == Some("Catalog"))
+            .ok_or("PDF has no document catalog")?;
+        let root = self.get(catalog, "Pages").and_then(Object::dict).ok_or("PDF has no page tree")?;
+        let mut pages = Vec::new();
+        self.collect_pages(root, None, &mut pages, 0);
+        Ok(pages)
+    }
+
+    // Pages in document order with inherited resources filled in
+    fn collect_pages(
+        &self,
+        node: &HashMap<String, Object>,
+        inherited: Option<&Object>,
+        pages: &mut Vec<HashMap<String, Object>>,
+        depth: usize,
+    ) {
+        let resources = node.get("Resources").or(inherited);
+        match node.get("Kids").map(|kids| self.resolve(kids)) {
+            Some(Object::Array(kids)) if depth < MAX_DEPTH => {
+                for kid in kids {
+                    if let Some(kid) = self.resolve(kid).dict() {
+                        self.collect_pages(kid, resources, pages, depth + 1);
+                    }
+                }
+            }
+            Some(_) => {}
+            None => {
+                let mut page = node.clone();
+                if let Some(resources) = resources {
+                    page.insert(String::from("Resources"), resources.clone());
+                }
+                pages.push(page);
+            }
+        }
+    }
+
+    // Images of `page` go into `images` until there is one past MAX_FRAMES, those `seen` on earlier pages are not
+    // added again
+    fn images(
+        &self,
+        resources: Option<&Object>,
+        limits: &InputLimits,
+        page: usize,
+        seen: &mut HashSet<u32>,
+        images: &mut Vec<PageImage>,
+        depth: usize,
+    ) -> Result<(), String> {
+        let Some(resources) = resources.map(|r| self.resolve(r)).and_then(Object::dict) else { return Ok(()) };
+        let Some(xobjects) = self.get(resources, "XObject").and_then(Object::dict) else { return Ok(()) };
+        let mut names: Vec<&String> = xobjects.keys().collect();
+        names.sort();
+        for name in names {
+            if images.len() > MAX_FRAMES {
+                break;
+            }
+            let reference = &xobjects[name];
+            if let Object::Ref(id) = reference {
+                if !seen.insert(*id) {
+                    continue;
+                }
+            }
+            let Object::Stream(dict, data) = self.resolve(reference) else { continue };
+            match dict.get("Subtype").and_then(Object::name) {
+                Some("Image") => {
+                    let image = self.image(dict, data, limits).map_err(|err| format!("PDF page {}: {}", page, err));
+                    images.extend(image?.map(|data| PageImage { page, data, delay: None }));
+                }
+                Some("Form") if depth < MAX_DEPTH => {
+                    self.images(dict.get("Resources"), limits, page, seen, images, depth + 1)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Images larger than `limits` allow fail before they are inflated, None for unsupported ones
+    fn image(
+        &self,
+        dict: &HashMap<String, Object>,
+        data: &[u8],
+        limits: &InputLimits,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let number = |key: &str| self.get(dict, key).and_then(Object::number).map(|n| n as u32);
+        if let (Some(width), Some(height)) = (number("Width"), number("Height")) {
+            limits.check_dimensions(width, height)?;
+        }
+        Ok(self.decode(dict, data))
+    }
+
+    fn decode(&self, dict: &HashMap<String, Object>, data: &[u8]) -> Option<Vec<u8>> {
+        let filters = filters(dict);
+        match filters.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["DCTDecode"] => return Some(data.to_vec()),
+            ["FlateDecode", "DCTDecode"] => return zlib(data),
+            [] | ["FlateDecode"] => {}
+            // JPEG 2000, JBIG2 and fax encoded images are not supported
+            _ => return None,
+        }
+        let number = |key: &str| self.get(dict, key).and_then(Object::number).map(|n| n as u32);
+        let (width, height) = (number("Width")?, number("Height")?);
+        if number("BitsPerComponent")? != 8 {
+            return None;
+        }
+        let colors = match self.get(dict, "ColorSpace")? {
+            Object::Name(name) => match name.as_str() {
+                "DeviceGray" => 1,
+                "DeviceRGB" => 3,
+                _ => return None,
+            },
+            Object::Array(items) if items.first().and_then(Object::name) == Some("ICCBased") => {
+                let profile = items.get(1).map(|p| self.resolve(p)).and_then(Object::dict)?;
+                self.get(profile, "N").and_then(Object::number)? as u32
+            }
+            _ => return None,
+        };
+        let size = (width as usize).checked_mul(height as usize)?.checked_mul(colors as usize)?;
+        // No row of the image is longer than all of its samples
+        let samples = if filters.is_empty() { data.to_vec() } else { inflate(dict, data, size)? };
+        let samples = samples.get(..size)?.to_vec();
+        let image = match colors {
+            1 => DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, samples)?),
+            3 => DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, samples)?),
+            _ => return None,
+        };
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, ImageOutputFormat::Png).ok()?;
+        Some(encoded.into_inner())
+    }
+}
+
+/// Extracts the images embedded in every page of a PDF. JPEG streams are
+/// passed on untouched, raw samples are re-encoded as PNG. Pages are not
+/// rendered, so text and vector graphics are not analyzed, but scanned
+/// documents carry each page as an embedded image. Up to MAX_FRAMES images
+/// are extracted, each once however many pages show it, and those larger
+/// than `limits` allow fail the document before they are decoded.
+pub fn extract_images(data: &[u8], limits: &InputLimits) -> Result<Vec<PageImage>, String> {
+    let document = Document::parse(data);
+    let mut images = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, page) in document.pages()?.iter().enumerate() {
+        document.images(page.get("Resources"), limits, index + 1, &mut seen, &mut images, 0)?;
+        if images.len() > MAX_FRAMES {
+            warn!("Only analyzing the first {} images of the PDF", MAX_FRAMES);
+            images.truncate(MAX_FRAMES);
+            break;
+        }
+    }
+    Ok(images)
+}
@@ -0,0 +1,1000 @@
+use crate::detector::{DetectionReport, Detector};
+use crate::fusion::{merge_regions, severity_score, suppress_duplicates, Fusion, Sensitivity, Verdict};
+use crate::zero::ZeroDetector;
+use crate::animation::encode_gif;
+use crate::archive::{is_zip, write_zip, zip_entries};
+use crate::banner::stamp_banner;
+use crate::buffers::{to_rgba, BUFFERS};
+use crate::coco::CocoDataset;
+use crate::encoding::OutputEncoding;
+use crate::error::JobError;
+use crate::heatmap::{render_heatmap, Heatmap, Overlay};
+use crate::heif::{decode_image, heif_format};
+use crate::job::{Artifacts, CancelToken, JobSettings, Progress};
+use crate::limits::{dimensions, estimated_memory, InputLimits};
+use crate::messages::Locale;
+use crate::orientation::{exif_orientation, orient};
+use crate::pages::{split_pages, PageImage};
+use crate::prescreen::looks_clean;
+use crate::redact::render_redacted;
+use crate::report::Report;
+use crate::simd_base64::{decode_base64_into, encode_base64};
+use crate::svg::{render_svg, SvgOverlay};
+use crate::{
+    Annotation, DetectorSummary, FileResult, MetadataFinding, PageResult, QueryResult, Region, RegionCrop, RegionDebug,
+    RegionEvidence, RegionReport, TimeRange,
+};
+use image::imageops::FilterType;
+use image::{Delay, DynamicImage, GenericImageView, RgbaImage};
+use log::info;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime};
+
+pub const DEFAULT_MERGE_GAP: u32 = 16;
+pub const DEFAULT_NMS_IOU: f64 = 0.5;
+pub const DEFAULT_VIDEO_SAMPLE_RATE: f64 = 1.0;
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+
+// Images are never downscaled below this many pixels to fit the memory budget
+const MIN_DETECT_PIXELS: u64 = 256 * 256;
+const MIB: u64 = 1024 * 1024;
+
+/// An ordered chain of detectors whose findings are combined into one verdict.
+pub struct Pipeline {
+    detectors: Vec<Box<dyn Detector>>,
+    fusion: Fusion,
+    sensitivity: Sensitivity,
+    merge_gap: u32,
+    nms_iou: f64,
+    annotate_animations: bool,
+    video_sample_rate: f64,
+    overlay: Overlay,
+    svg_overlay: SvgOverlay,
+    coco: bool,
+    encoding: OutputEncoding,
+    annotation: Annotation,
+    report: bool,
+    banner: bool,
+    thumbnail_size: Option<u32>,
+    crops: bool,
+    locale: Locale,
+    limits: InputLimits,
+    parallel: bool,
+    downscale_pixels: Option<u64>,
+    prescreen: bool,
+}
+
+/// The combined findings of every detector in a [`Pipeline`].
+pub struct Analysis {
+    pub result: String,
+    pub width: u32,
+    pub height: u32,
+    /// Fused confidence from 0 to 1 that the image was manipulated.
+    pub confidence: f64,
+    /// 0 to 100, see [`severity_score`].
+    pub severity: u8,
+    pub cropped: bool,
+    /// Forged regions of every detector, duplicates suppressed.
+    pub regions: Vec<RegionReport>,
+    /// Image level findings prefixed with the name of the detector reporting them.
+    pub findings: Vec<String>,
+    pub metadata: Vec<MetadataFinding>,
+    /// Image level manipulation probabilities keyed by the detector reporting them.
+    pub scores: BTreeMap<String, f64>,
+    /// Detector measurements keyed by `detector.name`.
+    pub measurements: BTreeMap<String, f64>,
+    pub detectors: Vec<DetectorSummary>,
+    /// The input image with every region outlined, only drawn when regions were found.
+    pub annotated: Option<RgbaImage>,
+    /// The input cut to each region in order, when enabled.
+    pub crops: Vec<RgbaImage>,
+    /// The statistics behind every region, for jobs asking for `debug`.
+    pub debug: Option<Vec<RegionDebug>>,
+}
+
+impl Analysis {
+    pub fn text(&self, locale: Locale) -> String {
+        let regions = self.regions.iter().map(|r| format!("{}\n", locale.region(r)));
+        let findings = self.findings.iter().map(|finding| format!("{}\n", finding));
+        let metadata = self.metadata.iter().map(|finding| format!("{}\n", locale.metadata(&finding.detail)));
+        regions.chain(findings).chain(metadata).collect()
+    }
+}
+
+// The evidence of the detector of every region where the region is, which may be several areas merged into it
+fn region_debug(regions: &[RegionReport], evidence: &[(&'static str, RegionEvidence)]) -> Vec<RegionDebug> {
+    let region_debug = |report: &RegionReport| RegionDebug {
+        index: report.index,
+        detector: report.detector,
+        evidence: evidence
+            .iter()
+            .filter(|(detector, evidence)| *detector == report.detector && evidence.region.overlaps(&report.region))
+            .map(|(_, evidence)| evidence.clone())
+            .collect(),
+    };
+    regions.iter().map(region_debug).collect()
+}
+
+// What one detector found in an image, before it is combined with the others
+struct Detected {
+    report: DetectionReport,
+    // The orientation of the image the detector saw
+    seen: u32,
+    kept: Vec<Region>,
+    evidence: f64,
+    summary: DetectorSummary,
+}
+
+// A copy of `image` with about `max_pixels`, or none when it has no more than that
+fn downscale(image: &DynamicImage, max_pixels: u64) -> Option<DynamicImage> {
+    let pixels = image.width() as f64 * image.height() as f64;
+    if pixels <= max_pixels as f64 {
+        return None;
+    }
+    let scale = (max_pixels as f64 / pixels).sqrt();
+    let scaled = |side: u32| ((side as f64 * scale) as u32).max(1);
+    Some(image.resize_exact(scaled(image.width()), scaled(image.height()), FilterType::Triangle))
+}
+
+// Maps what a detector found on a downscaled copy of an image back onto the image
+fn upscale(report: DetectionReport, copy: &DynamicImage, image: &DynamicImage) -> DetectionReport {
+    let (width, height) = image.dimensions();
+    let sx = width as f64 / copy.width() as f64;
+    let sy = height as f64 / copy.height() as f64;
+    let upscale = |region: Region| region.clip(copy.width(), copy.height()).map(|r| r.scale(sx, sy, width, height));
+    let regions = report.regions.into_iter().filter_map(upscale).collect();
+    let evidence = report
+        .evidence
+        .into_iter()
+        .filter_map(|evidence| Some(RegionEvidence { region: upscale(evidence.region)?, ..evidence }))
+        .collect();
+    let heatmap = report.heatmap.map(|heatmap| Heatmap {
+        block_width: heatmap.block_width * sx,
+        block_height: heatmap.block_height * sy,
+        ..heatmap
+    });
+    DetectionReport { regions, evidence, heatmap, ..report }
+}
+
+fn verdict_name(verdict: Verdict, cropped: bool) -> &'static str {
+    match (verdict, cropped) {
+        (Verdict::Edited, true) => "editcrop",
+        (Verdict::Edited, false) => "edited",
+        (Verdict::Suspicious, _) => "suspicious",
+        (Verdict::Clean, true) => "cropped",
+        (Verdict::Clean, false) => "clean",
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new(vec![Box::new(ZeroDetector)])
+    }
+}
+
+impl Pipeline {
+    pub fn new(detectors: Vec<Box<dyn Detector>>) -> Self {
+        Pipeline {
+            detectors,
+            fusion: Fusion::default(),
+            sensitivity: Sensitivity::default(),
+            merge_gap: DEFAULT_MERGE_GAP,
+            nms_iou: DEFAULT_NMS_IOU,
+            annotate_animations: false,
+            video_sample_rate: DEFAULT_VIDEO_SAMPLE_RATE,
+            overlay: Overlay::default(),
+            svg_overlay: SvgOverlay::default(),
+            coco: false,
+            annotation: Annotation::default(),
+            report: false,
+            banner: false,
+            thumbnail_size: None,
+            crops: false,
+            locale: Locale::default(),
+            encoding: OutputEncoding::default(),
+            limits: InputLimits::default(),
+            parallel: true,
+            downscale_pixels: None,
+            prescreen: false,
+        }
+    }
+
+    pub fn with_fusion(self, fusion: Fusion) -> Self {
+        Pipeline { fusion, ..self }
+    }
+
+    /// The sensitivity jobs are analyzed with unless they bring their own.
+    pub fn with_sensitivity(self, sensitivity: Sensitivity) -> Self {
+        Pipeline { sensitivity, ..self }
+    }
+
+    /// Regions of one detector at most `merge_gap` pixels apart are reported as one.
+    pub fn with_merge_gap(self, merge_gap: u32) -> Self {
+        Pipeline { merge_gap, ..self }
+    }
+
+    /// Regions of different detectors overlapping a more confident one by an
+    /// intersection over union of at least `nms_iou` are dropped.
+    pub fn with_nms_iou(self, nms_iou: f64) -> Self {
+        Pipeline { nms_iou, ..self }
+    }
+
+    /// Returns animated inputs as an animated GIF with every frame annotated,
+    /// instead of only the worst frame.
+    pub fn with_annotated_animations(self, annotate_animations: bool) -> Self {
+        Pipeline { annotate_animations, ..self }
+    }
+
+    /// Frames per second analyzed of video inputs.
+    pub fn with_video_sample_rate(self, video_sample_rate: f64) -> Self {
+        Pipeline { video_sample_rate, ..self }
+    }
+
+    /// How regions are drawn onto annotated images.
+    pub fn with_overlay(self, overlay: Overlay) -> Self {
+        Pipeline { overlay, ..self }
+    }
+
+    /// Also returns regions as an SVG layer, or only that instead of the annotated image.
+    pub fn with_svg_overlay(self, svg_overlay: SvgOverlay) -> Self {
+        Pipeline { svg_overlay, ..self }
+    }
+
+    /// Also returns the regions in COCO format.
+    pub fn with_coco_export(self, coco: bool) -> Self {
+        Pipeline { coco, ..self }
+    }
+
+    /// How annotated images are encoded for jobs that don't choose.
+    pub fn with_output_encoding(self, encoding: OutputEncoding) -> Self {
+        Pipeline { encoding, ..self }
+    }
+
+    /// Also return a PDF forensic report of every job.
+    pub fn with_forensic_report(self, report: bool) -> Self {
+        Pipeline { report, ..self }
+    }
+
+    /// Stamp the verdict, region count and date onto annotated images.
+    pub fn with_banner(self, banner: bool) -> Self {
+        Pipeline { banner, ..self }
+    }
+
+    /// Also return the output image downscaled to fit `size` pixels, for listing results.
+    pub fn with_thumbnail(self, size: Option<u32>) -> Self {
+        Pipeline { thumbnail_size: size, ..self }
+    }
+
+    /// Also return every region cut from the input at full resolution, for reviewing the evidence.
+    pub fn with_region_crops(self, crops: bool) -> Self {
+        Pipeline { crops, ..self }
+    }
+
+    /// The language of result texts for jobs that don't choose.
+    pub fn with_locale(self, locale: Locale) -> Self {
+        Pipeline { locale, ..self }
+    }
+
+    pub fn with_annotation(self, annotation: Annotation) -> Self {
+        Pipeline { annotation, ..self }
+    }
+
+    /// Inputs and images over `limits` fail instead of being analyzed.
+    pub fn with_input_limits(self, limits: InputLimits) -> Self {
+        Pipeline { limits, ..self }
+    }
+
+    /// Run the detectors of an image concurrently on the rayon thread pool, sized by `RAYON_NUM_THREADS`, instead
+    /// of one after another. The default.
+    pub fn with_parallel_detectors(self, parallel: bool) -> Self {
+        Pipeline { parallel, ..self }
+    }
+
+    /// Detect on a copy of images over `max_pixels` downscaled to about that
+    /// many, with regions mapped back onto the full image. Much faster on huge
+    /// scans, but resampling wipes out most compression traces.
+    pub fn with_downscale(self, max_pixels: Option<u64>) -> Self {
+        Pipeline { downscale_pixels: max_pixels, ..self }
+    }
+
+    /// Answer clean without running the detectors for JPEGs that a few cheap
+    /// checks find saved once at a high quality and untouched since. Off by
+    /// default, the checks only stand in for the grid and metadata detectors.
+    pub fn with_prescreen(self, prescreen: bool) -> Self {
+        Pipeline { prescreen, ..self }
+    }
+
+    /// Fails `data` when it is over the input limits, see [`InputLimits::check`].
+    pub fn check_limits(&self, data: &[u8]) -> Result<(), JobError> {
+        self.limits.check(data)
+    }
+
+    /// The configured settings, for jobs that don't bring their own.
+    pub fn job_settings(&self) -> JobSettings {
+        JobSettings {
+            sensitivity: self.sensitivity,
+            encoding: self.encoding,
+            annotation: self.annotation,
+            locale: self.locale,
+            cancel: CancelToken::default(),
+            progress: Progress::default(),
+            artifacts: Artifacts::default(),
+            debug: false,
+        }
+    }
+
+    pub fn analyze(&self, job_id: &str, image_data: &[u8]) -> Result<Analysis, JobError> {
+        self.analyze_with(job_id, image_data, &self.job_settings())
+    }
+
+    /// Like [`Pipeline::analyze`], with the sensitivity and annotation of a single job.
+    pub fn analyze_with(
+        &self,
+        job_id: &str,
+        image_data: &[u8],
+        settings: &JobSettings,
+    ) -> Result<Analysis, JobError> {
+        let JobSettings { sensitivity, annotation, cancel, progress, artifacts, debug, .. } = settings;
+        cancel.check()?;
+        self.limits.check(image_data)?;
+        // Before decoding where the header has the dimensions, or else right after
+        let detect_pixels = dimensions(image_data).map(|(width, height)| self.detect_pixels(job_id, width, height));
+        let detect_pixels = detect_pixels.transpose()?;
+        progress.set("decoding", 0);
+        let image = decode_image(image_data).map_err(decode_failed)?;
+        info!(job_id, stage = "decode"; "{}: Loaded image from memory, processing...", job_id);
+        let detect_pixels = match detect_pixels {
+            Some(detect_pixels) => detect_pixels,
+            None => self.detect_pixels(job_id, image.width(), image.height())?,
+        };
+        if self.prescreen {
+            progress.set("prescreen", 0);
+            let started = Instant::now();
+            if looks_clean(image_data, &image) {
+                let elapsed = started.elapsed();
+                let duration_ms = elapsed.as_secs_f64() * 1000.0;
+                info!(
+                    job_id, stage = "prescreen", duration_ms;
+                    "{}: Prescreened as clean in {:?}, skipping the detectors", job_id, elapsed
+                );
+                return Ok(self.prescreened(&image, settings, duration_ms));
+            }
+        }
+        let orientation = exif_orientation(image_data);
+        let downscaled = detect_pixels.and_then(|max_pixels| downscale(&image, max_pixels));
+        if let Some(downscaled) = &downscaled {
+            info!(
+                "{}: Detecting on a {}x{} copy of the {}x{} image",
+                job_id, downscaled.width(), downscaled.height(), image.width(), image.height()
+            );
+        }
+        // Oriented the first time a detector asks, once for each resolution
+        let (upright, downscaled_upright) = (OnceLock::new(), OnceLock::new());
+
+        // Run by every detector on its own, concurrently when parallel, then combined in detector order so the
+        // verdict does not depend on which finished first
+        let finished = AtomicUsize::new(0);
+        let detect = |detector: &dyn Detector| -> Result<Detected, JobError> {
+            cancel.check()?;
+            progress.set(detector.name(), (finished.load(Ordering::Relaxed) * 100 / self.detectors.len()) as u8);
+            let started = Instant::now();
+            let (stored, upright) = match &downscaled {
+                Some(downscaled) if !detector.full_resolution() => (downscaled, &downscaled_upright),
+                _ => (&image, &upright),
+            };
+            // Along with the orientation of the image the detector saw
+            let (report, seen) = match orientation {
+                1 => (detector.analyze_encoded(stored, image_data), 1),
+                _ if detector.upright() => {
+                    let upright = upright.get_or_init(|| orient(stored, orientation));
+                    let report = detector.analyze_encoded(upright, image_data);
+                    let (width, height) = (upright.width(), upright.height());
+                    let unorient = |region: &Region| {
+                        region.clip(width, height).map(|region| region.unorient(orientation, width, height))
+                    };
+                    let regions = report.regions.iter().filter_map(unorient).collect();
+                    let evidence = report
+                        .evidence
+                        .into_iter()
+                        .filter_map(|evidence| Some(RegionEvidence { region: unorient(&evidence.region)?, ..evidence }))
+                        .collect();
+                    (DetectionReport { regions, evidence, ..report }, orientation)
+                }
+                _ => (detector.analyze_encoded(stored, image_data), 1),
+            };
+            let report = if stored.dimensions() == image.dimensions() {
+                report
+            } else {
+                upscale(report, stored, &image)
+            };
+            let elapsed = started.elapsed();
+            let duration_ms = elapsed.as_secs_f64() * 1000.0;
+            info!(
+                job_id, stage = "detect", detector = detector.name(), duration_ms;
+                "{}: {} found {} forged regions in {:?}", job_id, detector.name(), report.regions.len(), elapsed
+            );
+            // Detectors may report regions reaching past the image, which would break drawing
+            let clipped = report.regions.iter().filter_map(|region| region.clip(image.width(), image.height()));
+            // Merged first, adjacent blocks of one edit may only be large enough together
+            let kept: Vec<Region> = merge_regions(clipped.collect(), self.merge_gap)
+                .into_iter()
+                .filter(|region| sensitivity.keeps(region, image.width(), image.height()))
+                .collect();
+            let localized = if kept.is_empty() { 0.0 } else { 1.0 };
+            let evidence = report.score.unwrap_or(0.0).max(localized);
+            let summary = DetectorSummary {
+                name: detector.name().to_string(),
+                elapsed_ms: duration_ms,
+                regions: kept.len(),
+                result: verdict_name(sensitivity.verdict(evidence), report.cropped).to_string(),
+            };
+            progress.finished(summary.clone(), report.score);
+            finished.fetch_add(1, Ordering::Relaxed);
+            Ok(Detected { report, seen, kept, evidence, summary })
+        };
+        let detected = if self.parallel {
+            self.detectors.par_iter().map(|detector| detect(detector.as_ref())).collect::<Result<Vec<_>, _>>()?
+        } else {
+            self.detectors.iter().map(|detector| detect(detector.as_ref())).collect::<Result<Vec<_>, _>>()?
+        };
+        // Only the detectors look at the upright and downscaled copies, no need to hold them through annotating
+        drop((upright, downscaled_upright, downscaled));
+
+        let mut regions = Vec::new();
+        let mut findings = Vec::new();
+        let mut metadata = Vec::new();
+        let mut scores = BTreeMap::new();
+        let mut measurements = BTreeMap::new();
+        let mut evidence = Vec::new();
+        let mut summaries = Vec::new();
+        let mut heatmaps = Vec::new();
+        let mut evidence_of = Vec::new();
+        let mut cropped = false;
+        for (detector, Detected { report, seen, kept, evidence: detector_evidence, summary }) in
+            self.detectors.iter().zip(detected)
+        {
+            cropped |= report.cropped;
+            evidence.push((detector.name(), detector_evidence));
+            summaries.push(summary);
+            if *debug {
+                evidence_of.extend(report.evidence.into_iter().map(|evidence| (detector.name(), evidence)));
+            }
+            let (kind, name) = (detector.region_type(), detector.name());
+            regions.extend(kept.into_iter().map(|region| RegionReport { index: 0, region, kind, detector: name }));
+            metadata.extend(report.metadata);
+            if let Some(heatmap) = report.heatmap {
+                artifacts.add_map(job_id, detector.name(), &heatmap);
+                heatmaps.push((detector.name(), heatmap, seen));
+            }
+            if let Some(score) = report.score {
+                scores.insert(detector.name().to_string(), score);
+            }
+            for (name, value) in report.measurements {
+                measurements.insert(format!("{}.{}", detector.name(), name), value);
+            }
+            findings.extend(report.findings.iter().map(|finding| format!("{}: {}", detector.name(), finding)));
+        }
+        let mut regions = suppress_duplicates(regions, self.nms_iou);
+        for (index, report) in regions.iter_mut().enumerate() {
+            report.index = index + 1;
+        }
+        info!(job_id; "{}: found {} forged regions", job_id, regions.len());
+        let debug = debug.then(|| region_debug(&regions, &evidence_of));
+
+        let confidence = self.fusion.confidence(evidence);
+        let result = verdict_name(sensitivity.verdict(confidence), cropped);
+        let severity = severity_score(&regions, image.width(), image.height(), confidence);
+        progress.set("annotating", 100);
+        let mut annotated = if regions.is_empty() {
+            None
+        } else if self.overlay == Overlay::Heatmap {
+            Some(render_heatmap(&image, &heatmaps, &regions))
+        } else if matches!(self.overlay, Overlay::Blur | Overlay::Blackout) {
+            Some(render_redacted(&image, &regions, self.overlay))
+        } else {
+            let annotation = annotation.scaled(image.width(), image.height());
+            let mut image_buffer = to_rgba(&image);
+            for r in &regions {
+                annotation.draw(&mut image_buffer, &r.region);
+            }
+            // After every outline, so no box is drawn over a label
+            if annotation.labels {
+                for r in &regions {
+                    annotation.label(&mut image_buffer, &r.region, r.index);
+                }
+            }
+            Some(image_buffer)
+        };
+        // From the input, the evidence should not be covered by annotations
+        let crops = if self.crops { regions.iter().map(|r| crop(&image, &r.region)).collect() } else { Vec::new() };
+        if let (Some(image_buffer), true) = (&mut annotated, self.banner) {
+            stamp_banner(image_buffer, result, regions.len(), SystemTime::now());
+        }
+        info!(
+            job_id, verdict = result, confidence;
+            "{}: Finished processing image, result: {} ({:.2})", job_id, result, confidence
+        );
+        Ok(Analysis {
+            result: String::from(result),
+            width: image.width(),
+            height: image.height(),
+            confidence,
+            severity,
+            cropped,
+            regions,
+            findings,
+            metadata,
+            scores,
+            measurements,
+            detectors: summaries,
+            annotated,
+            crops,
+            debug,
+        })
+    }
+
+    // The clean analysis of an image the prescreen let through
+    fn prescreened(&self, image: &DynamicImage, settings: &JobSettings, duration_ms: f64) -> Analysis {
+        let result = verdict_name(settings.sensitivity.verdict(0.0), false);
+        let summary = DetectorSummary {
+            name: String::from("prescreen"),
+            elapsed_ms: duration_ms,
+            regions: 0,
+            result: result.to_string(),
+        };
+        settings.progress.finished(summary.clone(), None);
+        Analysis {
+            result: String::from(result),
+            width: image.width(),
+            height: image.height(),
+            confidence: 0.0,
+            severity: severity_score::<Region>(&[], image.width(), image.height(), 0.0),
+            cropped: false,
+            regions: Vec::new(),
+            findings: Vec::new(),
+            metadata: Vec::new(),
+            scores: BTreeMap::new(),
+            measurements: BTreeMap::new(),
+            detectors: vec![summary],
+            annotated: None,
+            crops: Vec::new(),
+            debug: settings.debug.then(Vec::new),
+        }
+    }
+
+    pub fn detect(&self, job_id: &str, image_data: &[u8]) -> Result<QueryResult, JobError> {
+        self.detect_with(job_id, image_data, &self.sensitivity)
+    }
+
+    pub fn detect_with(
+        &self,
+        job_id: &str,
+        image_data: &[u8],
+        sensitivity: &Sensitivity,
+    ) -> Result<QueryResult, JobError> {
+        let settings = JobSettings { sensitivity: *sensitivity, ..self.job_settings() };
+        let (result, image_out) = self.detect_raw(job_id, image_data, &settings)?;
+        let enc_img_out = encode_base64(&image_out);
+        BUFFERS.give(image_out);
+        Ok(QueryResult { enc_img_out, ..result })
+    }
+
+    /// Like [`Pipeline::detect_with`], with every setting of a single job, and
+    /// returns the output image as raw bytes next to the result instead of
+    /// base64 encoded in `enc_img_out`.
+    pub fn detect_raw(
+        &self,
+        job_id: &str,
+        image_data: &[u8],
+        settings: &JobSettings,
+    ) -> Result<(QueryResult, Vec<u8>), JobError> {
+        let received = SystemTime::now();
+        let encoding = settings.encoding;
+        let (mut result, image_out) = self.detect_input(job_id, image_data, settings)?;
+        let thumbnail_size = self.thumbnail_size.filter(|_| !encoding.regions_only);
+        if !self.report && thumbnail_size.is_none() {
+            return Ok((result, image_out));
+        }
+        // Archives have no single image to show
+        let image = image::load_from_memory(&image_out).or_else(|_| decode_image(image_data)).ok();
+        if let (Some(size), Some(image)) = (thumbnail_size, &image) {
+            let fits = image.width().max(image.height()) <= size;
+            let thumbnail = if fits { image.clone() } else { image.thumbnail(size, size) };
+            result.enc_thumbnail = Some(encoding.encode_base64(&thumbnail.to_rgba8()).map_err(encode_failed)?);
+        }
+        if !self.report {
+            return Ok((result, image_out));
+        }
+        let report = Report {
+            job_id,
+            result: &result,
+            input: image_data,
+            output: &image_out,
+            image: image.as_ref(),
+            received,
+            completed: SystemTime::now(),
+        };
+        let enc_report = Some(encode_base64(&report.render().map_err(encode_failed)?));
+        Ok((QueryResult { enc_report, ..result }, image_out))
+    }
+
+    // How many pixels to downscale a `width`x`height` image to for detection, if at all, so it is within the
+    // configured downscale threshold and memory budget. Fails images that fit neither.
+    fn detect_pixels(&self, job_id: &str, width: u32, height: u32) -> Result<Option<u64>, JobError> {
+        let pixels = u64::from(width) * u64::from(height);
+        let mut detect_pixels = self.downscale_pixels.filter(|&max_pixels| pixels > max_pixels);
+        let Some(budget) = self.limits.max_memory_bytes else {
+            return Ok(detect_pixels);
+        };
+        let needed = |detect_pixels: u64| {
+            let detector_pixels = self.detectors.iter().map(|d| if d.full_resolution() { pixels } else { detect_pixels });
+            estimated_memory(pixels, &detector_pixels.collect::<Vec<_>>(), self.parallel)
+        };
+        let needed_full = needed(detect_pixels.unwrap_or(pixels));
+        // Downscaled further only where the deployment allows detecting on downscaled copies at all
+        while needed(detect_pixels.unwrap_or(pixels)) > budget {
+            match detect_pixels.unwrap_or(pixels) * 4 / 5 {
+                smaller if self.downscale_pixels.is_some() && smaller >= MIN_DETECT_PIXELS => {
+                    detect_pixels = Some(smaller);
+                }
+                _ => {
+                    let (needed, budget) = (needed_full / MIB, budget / MIB);
+                    let problem = format!(
+                        "Image is {}x{} and needs about {} MiB to analyze, at most {} MiB are allowed",
+                        width, height, needed, budget
+                    );
+                    return Err(JobError::TooLarge(problem));
+                }
+            }
+        }
+        if needed_full > budget {
+            info!("{}: Downscaling to fit the {} MiB memory budget", job_id, budget / MIB);
+        }
+        Ok(detect_pixels)
+    }
+
+    fn detect_input(
+        &self,
+        job_id: &str,
+        image_data: &[u8],
+        settings: &JobSettings,
+    ) -> Result<(QueryResult, Vec<u8>), JobError> {
+        self.limits.check(image_data)?;
+        if is_zip(image_data) {
+            return self.detect_archive(job_id, image_data, settings);
+        }
+        if let Some(pages) = split_pages(image_data, self.video_sample_rate, &self.limits) {
+            return self.detect_pages(job_id, pages.map_err(JobError::Decode)?, settings);
+        }
+        let (encoding, locale) = (settings.encoding, settings.locale);
+        let mut analysis = self.analyze_with(job_id, image_data, settings)?;
+        let text = analysis.text(locale);
+        settings.progress.set("encoding", 100);
+        let (image_out, svg_overlay) = self.output(&analysis, image_data, encoding)?;
+        let crops = encode_crops(&analysis, encoding)?;
+        if let Some(annotated) = analysis.annotated.take() {
+            BUFFERS.give(annotated.into_raw());
+        }
+        let coco = self.coco_dataset().map(|mut coco| {
+            coco.add_image(job_id, analysis.width, analysis.height, &analysis.regions);
+            coco
+        });
+        let result = QueryResult {
+            svg_overlay,
+            coco,
+            text,
+            crops,
+            result: analysis.result,
+            confidence: analysis.confidence,
+            severity: analysis.severity,
+            regions: analysis.regions,
+            metadata_findings: analysis.metadata,
+            scores: analysis.scores,
+            measurements: analysis.measurements,
+            detectors: analysis.detectors,
+            debug: analysis.debug,
+            ..QueryResult::default()
+        };
+        Ok((result, image_out))
+    }
+
+    // Analyzes every image of a multi-page input on its own
+    fn detect_pages(
+        &self,
+        job_id: &str,
+        images: Vec<PageImage>,
+        settings: &JobSettings,
+    ) -> Result<(QueryResult, Vec<u8>), JobError> {
+        let (encoding, locale) = (settings.encoding, settings.locale);
+        info!("{}: Split input into {} page images", job_id, images.len());
+        let mut pages: Vec<PageResult> = Vec::new();
+        let mut labels = Vec::new();
+        let mut animation = Vec::new();
+        let mut delays = Vec::new();
+        let mut coco = self.coco_dataset();
+        for page_image in images {
+            let image = pages.iter().filter(|page| page.page == page_image.page).count() + 1;
+            let label_in = |locale: Locale| match page_image.delay {
+                Some(_) => locale.frame(page_image.page),
+                None => locale.page(page_image.page, image),
+            };
+            // Logs and COCO file names stay English
+            let page_job_id = format!("{} {}", job_id, label_in(Locale::En));
+            let label = label_in(locale);
+            let analysis = self.analyze_with(&page_job_id, &page_image.data, settings)?;
+            if let Some(coco) = &mut coco {
+                coco.add_image(&page_job_id, analysis.width, analysis.height, &analysis.regions);
+            }
+            let (image_out, svg_overlay) = self.output(&analysis, &page_image.data, encoding)?;
+            let enc_img_out = encode_base64(&image_out);
+            let crops = encode_crops(&analysis, encoding)?;
+            let text = analysis.text(locale);
+            let annotate_animation = self.annotate_animations && !self.skips_image(encoding);
+            if let (Some(delay), true) = (page_image.delay, annotate_animation) {
+                let frame = match analysis.annotated {
+                    Some(image_buffer) => image_buffer,
+                    None => decode_image(&page_image.data).map_err(decode_failed)?.to_rgba8(),
+                };
+                animation.push((frame, delay));
+            }
+            labels.push(label);
+            delays.push(page_image.delay);
+            pages.push(PageResult {
+                page: page_image.page,
+                image,
+                enc_img_out,
+                svg_overlay,
+                text,
+                crops,
+                result: analysis.result,
+                confidence: analysis.confidence,
+                severity: analysis.severity,
+                regions: analysis.regions,
+                debug: analysis.debug,
+            });
+        }
+        let worst = pages
+            .iter()
+            .max_by(|a, b| verdict_rank(&a.result).cmp(&verdict_rank(&b.result)).then(a.confidence.total_cmp(&b.confidence)))
+            .ok_or_else(|| JobError::Decode(String::from("Input contains no supported images")))?;
+        let tampered_ranges = tampered_ranges(&pages, &delays);
+        let ranges = tampered_ranges
+            .iter()
+            .map(|range| format!("{}\n", locale.tampering(range)));
+        let text = pages
+            .iter()
+            .zip(&labels)
+            .map(|(page, label)| format!("{}\n{}", locale.verdict(label, &page.result), page.text))
+            .chain(ranges)
+            .collect();
+        let image_out = if animation.is_empty() {
+            let mut decoded = BUFFERS.take(worst.enc_img_out.len() / 4 * 3);
+            let res = decode_base64_into(worst.enc_img_out.as_bytes(), &mut decoded);
+            res.map_err(|err| JobError::Internal(err.to_string()))?;
+            decoded
+        } else {
+            encode_gif(animation).map_err(encode_failed)?
+        };
+        let severity = pages.iter().map(|page| page.severity).max().unwrap_or(0);
+        let result = QueryResult {
+            text,
+            result: worst.result.clone(),
+            confidence: worst.confidence,
+            severity,
+            pages,
+            tampered_ranges,
+            coco,
+            ..QueryResult::default()
+        };
+        Ok((result, image_out))
+    }
+
+    // Analyzes every file of an archive on its own, files that fail are reported without failing the job
+    fn detect_archive(
+        &self,
+        job_id: &str,
+        archive: &[u8],
+        settings: &JobSettings,
+    ) -> Result<(QueryResult, Vec<u8>), JobError> {
+        let locale = settings.locale;
+        let entries = zip_entries(archive).map_err(JobError::Decode)?;
+        info!("{}: Read {} files from archive", job_id, entries.len());
+        let mut files = Vec::new();
+        let mut outputs = Vec::new();
+        let mut coco = self.coco_dataset();
+        for (name, contents) in entries {
+            // Files fail on their own, except when the job was cancelled
+            settings.cancel.check()?;
+            let file_job_id = format!("{} {}", job_id, name);
+            let detected = contents
+                .map_err(JobError::Decode)
+                .and_then(|contents| self.detect_input(&file_job_id, &contents, settings));
+            let (result, image_out) = match detected {
+                Ok(detected) => detected,
+                Err(err) => {
+                    info!("{}: Failed to analyze {}: {}", job_id, name, err);
+                    files.push(FileResult {
+                        name,
+                        result: String::from("Failed"),
+                        confidence: 0.0,
+                        severity: 0,
+                        regions: Vec::new(),
+                        text: format!("{}\n", err),
+                        output: None,
+                        svg_overlay: None,
+                        crops: Vec::new(),
+                        debug: None,
+                    });
+                    continue;
+                }
+            };
+            if let (Some(coco), Some(file_coco)) = (&mut coco, result.coco) {
+                coco.merge(file_coco);
+            }
+            // Nothing to archive when the images are skipped
+            let output = (!image_out.is_empty()).then(|| output_name(&name, &image_out));
+            if let Some(output) = &output {
+                outputs.push((output.clone(), image_out));
+            }
+            files.push(FileResult {
+                name,
+                result: result.result,
+                confidence: result.confidence,
+                severity: result.severity,
+                regions: result.regions,
+                text: result.text,
+                output,
+                svg_overlay: result.svg_overlay,
+                crops: result.crops,
+                debug: result.debug,
+            });
+        }
+        let worst = files
+            .iter()
+            .filter(|file| file.result != "Failed")
+            .max_by(|a, b| verdict_rank(&a.result).cmp(&verdict_rank(&b.result)).then(a.confidence.total_cmp(&b.confidence)))
+            .ok_or_else(|| JobError::Decode(String::from("Archive contains no supported images")))?;
+        let text = files.iter().map(|file| format!("{}\n{}", locale.verdict(&file.name, &file.result), file.text)).collect();
+        let severity = files.iter().map(|file| file.severity).max().unwrap_or(0);
+        let result = QueryResult {
+            text,
+            result: worst.result.clone(),
+            confidence: worst.confidence,
+            severity,
+            files,
+            coco,
+            ..QueryResult::default()
+        };
+        let archive_out = if outputs.is_empty() { Vec::new() } else { write_zip(outputs).map_err(encode_failed)? };
+        Ok((result, archive_out))
+    }
+
+    fn coco_dataset(&self) -> Option<CocoDataset> {
+        self.coco.then(|| CocoDataset::new(self.detectors.iter().map(|detector| (detector.region_type(), detector.name()))))
+    }
+
+    // Whether no output image is encoded, clients draw the regions themselves
+    fn skips_image(&self, encoding: OutputEncoding) -> bool {
+        self.svg_overlay == SvgOverlay::Only || encoding.regions_only
+    }
+
+    // The output image, empty when skipped, and the SVG layer if enabled
+    fn output(
+        &self,
+        analysis: &Analysis,
+        image_data: &[u8],
+        encoding: OutputEncoding,
+    ) -> Result<(Vec<u8>, Option<String>), JobError> {
+        let svg_overlay = (self.svg_overlay != SvgOverlay::Off)
+            .then(|| render_svg(analysis.width, analysis.height, &analysis.regions));
+        let image_out = if self.skips_image(encoding) {
+            Vec::new()
+        } else {
+            encode_output(analysis, image_data, encoding)?
+        };
+        Ok((image_out, svg_overlay))
+    }
+}
+
+// Names an output file after its input, with the extension of what it was encoded as
+fn output_name(name: &str, image_out: &[u8]) -> String {
+    let extension = match image::guess_format(image_out) {
+        Ok(format) => format.extensions_str()[0],
+        Err(_) if is_zip(image_out) => "zip",
+        Err(_) => "bin",
+    };
+    format!("{}.annotated.{}", name, extension)
+}
+
+// The annotated image, or the input itself when nothing was found and clients can display it
+fn encode_output(analysis: &Analysis, image_data: &[u8], encoding: OutputEncoding) -> Result<Vec<u8>, JobError> {
+    match &analysis.annotated {
+        Some(image_buffer) => encoding.encode(&encoding.fit(image_buffer)).map_err(encode_failed),
+        None if heif_format(image_data).is_some() || !encoding.fits(analysis.width, analysis.height) => {
+            let image_buffer = decode_image(image_data).map_err(decode_failed)?.to_rgba8();
+            encoding.encode(&encoding.fit(&image_buffer)).map_err(encode_failed)
+        }
+        None => Ok(image_data.to_vec()),
+    }
+}
+
+fn decode_failed(err: impl Display) -> JobError {
+    JobError::Decode(format!("Failed to decode image: {}", err))
+}
+
+fn encode_failed(err: impl Display) -> JobError {
+    JobError::Encode(format!("Failed to encode output: {}", err))
+}
+
+fn crop(image: &DynamicImage, region: &Region) -> RgbaImage {
+    let Region { start, end, .. } = *region;
+    image.crop_imm(start.x, start.y, end.x - start.x + 1, end.y - start.y + 1).to_rgba8()
+}
+
+// Encoded like the output image, numbered after the regions they were cut to
+fn encode_crops(analysis: &Analysis, encoding: OutputEncoding) -> Result<Vec<RegionCrop>, JobError> {
+    let crops = analysis.regions.iter().zip(&analysis.crops).map(|(r, crop)| {
+        let enc_img = encoding.encode_base64(crop).map_err(encode_failed)?;
+        Ok(RegionCrop { index: r.index, enc_img })
+    });
+    crops.collect()
+}
+
+// Joins consecutive suspicious or edited frames into spans of the timeline their delays make up
+fn tampered_ranges(pages: &[PageResult], delays: &[Option<Delay>]) -> Vec<TimeRange> {
+    let mut ranges: Vec<TimeRange> = Vec::new();
+    let mut start = 0.0;
+    for (page, delay) in pages.iter().zip(delays) {
+        let Some(delay) = delay else { return Vec::new() };
+        let (numer, denom) = delay.numer_denom_ms();
+        let end = start + numer as f64 / denom as f64 / 1000.0;
+        if verdict_rank(&page.result) >= verdict_rank("suspicious") {
+            match ranges.last_mut() {
+                Some(range) if range.end == start => range.end = end,
+                _ => ranges.push(TimeRange { start, end }),
+            }
+        }
+        start = end;
+    }
+    ranges
+}
+
+/// Combines the results of every image of a batch job, the top level verdict
+/// being the worst of the images that could be analyzed.
+pub fn batch_result(results: Vec<QueryResult>, locale: Locale) -> QueryResult {
+    let analyzed = results.iter().filter(|result| !matches!(result.result.as_str(), "Failed" | "unsupported"));
+    let worst = analyzed
+        .max_by(|a, b| verdict_rank(&a.result).cmp(&verdict_rank(&b.result)).then(a.confidence.total_cmp(&b.confidence)));
+    let (result, confidence) = worst.map_or((String::from("Failed"), 0.0), |worst| (worst.result.clone(), worst.confidence));
+    // Failed as a whole only when no image could be analyzed, as the first of them failed
+    let error = if result == "Failed" { results.iter().find_map(|result| result.error.clone()) } else { None };
+    let text = results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| format!("{}\n{}", locale.verdict(&locale.image(i + 1), &result.result), result.text))
+        .collect();
+    QueryResult {
+        text,
+        result,
+        confidence,
+        severity: results.iter().map(|result| result.severity).max().unwrap_or(0),
+        batch: results,
+        error,
+        ..QueryResult::default()
+    }
+}
+
+// Orders verdicts so the worst page of a document decides its verdict
+fn verdict_rank(result: &str) -> u8 {
+    match result {
+        "editcrop" => 4,
+        "edited" => 3,
+        "suspicious" => 2,
+        "cropped" => 1,
+        _ => 0,
+    }
+}
@@ -0,0 +1,133 @@
+use crate::dct::cosine_table;
+use crate::detector::Detector;
+use crate::metadata::MetadataDetector;
+use image::{DynamicImage, GenericImageView};
+
+// Largest luma quantization step of the lowest AC frequencies, about libjpeg quality 65, coarser saves may wipe out
+// the traces of earlier edits
+const MAX_LOW_STEP: u16 = 12;
+// Blocks sampled for the grid, spread evenly over the image
+const GRID_SAMPLES: usize = 4096;
+// Parts along each side of the image, checked 2 by 2 so no edit falls between them
+const PARTS: usize = 4;
+// Fewest votes 2 by 2 parts need to tell their grid, and the largest share of them any one shifted grid may get
+const MIN_VOTES: u32 = 32;
+const MAX_FOREIGN_SHARE: f64 = 0.04;
+
+/// Cheap checks that an input is a JPEG saved once at a high quality and left
+/// alone since, so its detectors can be skipped: a fine luma quantization
+/// table, metadata naming no editor, and the uncropped 8x8 grid of that save
+/// all over a sample of its blocks. Images failing any of them are not
+/// suspicious, only left to the detectors.
+pub(crate) fn looks_clean(encoded: &[u8], image: &DynamicImage) -> bool {
+    let Some(table) = luma_table(encoded) else {
+        return false;
+    };
+    // Zig-zag order, the DC step comes first
+    table[1..10].iter().all(|&step| step <= MAX_LOW_STEP)
+        && MetadataDetector::default().analyze_encoded(image, encoded).metadata.is_empty()
+        && grid_everywhere(image)
+}
+
+// Table 0 of a JPEG in zig-zag order, which encoders use for luma
+fn luma_table(encoded: &[u8]) -> Option<[u16; 64]> {
+    if !encoded.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut offset = 2;
+    while offset + 4 <= encoded.len() && encoded[offset] == 0xFF {
+        let marker = encoded[offset + 1];
+        let length = u16::from_be_bytes([encoded[offset + 2], encoded[offset + 3]]) as usize;
+        let mut segment = encoded.get(offset + 4..offset + 2 + length)?;
+        // Every table of the segment starts with its precision and id
+        while let (0xDB, Some((&info, rest))) = (marker, segment.split_first()) {
+            let wide = info >> 4 == 1;
+            let values = rest.get(..if wide { 128 } else { 64 })?;
+            if info & 0x0F == 0 {
+                let mut table = [0; 64];
+                for (index, step) in table.iter_mut().enumerate() {
+                    *step = if wide {
+                        u16::from_be_bytes([values[2 * index], values[2 * index + 1]])
+                    } else {
+                        u16::from(values[index])
+                    };
+                }
+                return Some(table);
+            }
+            segment = &rest[values.len()..];
+        }
+        // Start of scan, tables only come before it
+        if marker == 0xDA {
+            return None;
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+// Whether the zero detector's votes for the grid of each block, taken on a sample of them, are mostly for the
+// origin in every 2 by 2 parts of the image and hardly ever agree on another grid, as they do in spliced areas
+fn grid_everywhere(image: &DynamicImage) -> bool {
+    let (width, height) = image.dimensions();
+    // Every block sampled is also tried at the 63 shifts of up to 7 pixels further
+    let cols = width.checked_sub(15).map_or(0, |width| width / 8 + 1);
+    let rows = height.checked_sub(15).map_or(0, |height| height / 8 + 1);
+    let total = (cols * rows) as usize;
+    let stride = total.div_ceil(GRID_SAMPLES).max(1);
+    // Of every part for every shift
+    let mut votes = [[[0u32; 64]; PARTS]; PARTS];
+    for index in (0..total).step_by(stride) {
+        let (x0, y0) = (index as u32 % cols * 8, index as u32 / cols * 8);
+        let mut window = [[0.0; 15]; 15];
+        for (y, row) in window.iter_mut().enumerate() {
+            for (x, level) in row.iter_mut().enumerate() {
+                let [r, g, b, _] = image.get_pixel(x0 + x as u32, y0 + y as u32).0;
+                // Rounded like the zero detector's luminance
+                *level = (0.299 * f64::from(r) + 0.587 * f64::from(g) + 0.114 * f64::from(b)).round() - 128.0;
+            }
+        }
+        let mut zeros = [0; 64];
+        for (shift, count) in zeros.iter_mut().enumerate() {
+            *count = zero_coefficients(&window, shift % 8, shift / 8);
+        }
+        let most = zeros.iter().max().copied().unwrap_or(0);
+        // Ties, like flat blocks, vote for nothing
+        let mut best = zeros.iter().enumerate().filter(|(_, &count)| count == most).map(|(shift, _)| shift);
+        if let (Some(shift), None) = (best.next(), best.next()) {
+            let (row, col) = (y0 as usize * PARTS / height as usize, x0 as usize * PARTS / width as usize);
+            votes[row][col][shift] += 1;
+        }
+    }
+    (0..PARTS - 1).all(|row| {
+        (0..PARTS - 1).all(|col| {
+            let mut shifts = [0; 64];
+            for part in [&votes[row][col], &votes[row][col + 1], &votes[row + 1][col], &votes[row + 1][col + 1]] {
+                for (sum, count) in shifts.iter_mut().zip(part) {
+                    *sum += count;
+                }
+            }
+            let all: u32 = shifts.iter().sum();
+            let foreign = shifts[1..].iter().max().copied().unwrap_or(0);
+            all >= MIN_VOTES && shifts[0] * 2 > all && f64::from(foreign) <= MAX_FOREIGN_SHARE * f64::from(all)
+        })
+    })
+}
+
+// AC coefficients of the 8x8 block of `window` at `(dx, dy)` rounding to 0, as quantized by the finest JPEG
+fn zero_coefficients(window: &[[f64; 15]; 15], dx: usize, dy: usize) -> u32 {
+    let cos = cosine_table();
+    let mut rows = [[0.0; 8]; 8];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..8).map(|x| window[dy + y][dx + x] * cos[u][x]).sum();
+        }
+    }
+    let mut zeros = 0;
+    for (v, cos) in cos.iter().enumerate() {
+        for u in 0..8 {
+            let coefficient: f64 = rows.iter().zip(cos).map(|(row, cos)| row[u] * cos).sum();
+            zeros += u32::from((v, u) != (0, 0) && coefficient.abs() < 0.5);
+        }
+    }
+    zeros
+}
@@ -0,0 +1,260 @@
+use crate::blocks::BlockGrid;
+use crate::heatmap::suspicion;
+use crate::detector::{DetectionReport, Detector};
+use crate::metadata::ascii;
+use exif::Tag;
+use image::{imageops, DynamicImage};
+use log::info;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"PRNU";
+// Pixels this bright are likely clipped and carry no sensor noise
+const SATURATED: f32 = 250.0;
+
+/// A camera sensor's photo response non-uniformity (PRNU) pattern: the
+/// per-pixel gain deviation every image taken with it is multiplied by.
+pub struct Fingerprint {
+    pub width: u32,
+    pub height: u32,
+    values: Vec<f32>,
+}
+
+/// Normalizes the EXIF make and model (or a user given name) into the file
+/// stem fingerprints are stored under, e.g. `canon_eos_5d`.
+pub fn camera_key(name: &str) -> String {
+    let key: String = name.to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    key.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_")
+}
+
+/// The camera an encoded image claims to come from, from its EXIF make and model.
+pub fn claimed_camera(encoded: &[u8]) -> Option<String> {
+    let exif = exif::Reader::new().read_from_container(&mut Cursor::new(encoded)).ok()?;
+    let make = ascii(&exif, Tag::Make).unwrap_or_default();
+    let model = ascii(&exif, Tag::Model)?;
+    // Models usually repeat the make, e.g. "Canon" / "Canon EOS 5D"
+    let name = if model.to_lowercase().starts_with(&make.to_lowercase()) { model } else { format!("{} {}", make, model) };
+    Some(camera_key(&name))
+}
+
+struct Residual {
+    intensity: Vec<f32>,
+    noise: Vec<f32>,
+}
+
+// Luminance and its high frequency noise residual left after denoising
+fn residual(img: &DynamicImage) -> Residual {
+    // Blurring works on 0-1 floats, scaled to 0-255 afterwards
+    let luma = img.to_luma32f();
+    let denoised = imageops::blur(&luma, 1.0);
+    let intensity: Vec<f32> = luma.pixels().map(|p| p.0[0] * 255.0).collect();
+    let noise = intensity.iter().zip(denoised.pixels()).map(|(i, d)| i - d.0[0] * 255.0).collect();
+    Residual { intensity, noise }
+}
+
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    if a.is_empty() {
+        return 0.0;
+    }
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        let (x, y) = (x - mean_a, y - mean_b);
+        ab += x * y;
+        aa += x * x;
+        bb += y * y;
+    }
+    if aa == 0.0 || bb == 0.0 {
+        0.0
+    } else {
+        ab / (aa * bb).sqrt()
+    }
+}
+
+impl Fingerprint {
+    /// Maximum likelihood estimate `K = Σ W·I / Σ I²` over images taken with
+    /// the same camera, all at its native resolution and orientation.
+    pub fn estimate(images: &[DynamicImage]) -> Result<Fingerprint, String> {
+        let first = images.first().ok_or("at least one image is needed")?;
+        let (width, height) = (first.width(), first.height());
+        let mut numerator = vec![0f64; (width * height) as usize];
+        let mut denominator = vec![0f64; (width * height) as usize];
+        for img in images {
+            if (img.width(), img.height()) != (width, height) {
+                return Err(format!("images are {}x{} and {}x{}", width, height, img.width(), img.height()));
+            }
+            let residual = residual(img);
+            for (index, (&i, &w)) in residual.intensity.iter().zip(&residual.noise).enumerate() {
+                if i < SATURATED {
+                    numerator[index] += (w * i) as f64;
+                    denominator[index] += (i * i) as f64;
+                }
+            }
+        }
+        let mut values: Vec<f32> =
+            numerator.iter().zip(&denominator).map(|(n, d)| if *d > 0.0 { (n / d) as f32 } else { 0.0 }).collect();
+
+        // Remove row and column means, they come from demosaicing and JPEG
+        // artifacts shared by every camera of a model rather than the sensor
+        for row in values.chunks_mut(width as usize) {
+            let mean = row.iter().sum::<f32>() / width as f32;
+            row.iter_mut().for_each(|v| *v -= mean);
+        }
+        for x in 0..width as usize {
+            let mean = (0..height as usize).map(|y| values[y * width as usize + x]).sum::<f32>() / height as f32;
+            (0..height as usize).for_each(|y| values[y * width as usize + x] -= mean);
+        }
+        // Likewise the 8x8 JPEG block grid leaves the same pattern in every image
+        let mut phase_sums = [[(0f32, 0usize); 8]; 8];
+        for (index, v) in values.iter().enumerate() {
+            let sum = &mut phase_sums[index / width as usize % 8][index % width as usize % 8];
+            sum.0 += v;
+            sum.1 += 1;
+        }
+        for (index, v) in values.iter_mut().enumerate() {
+            let (sum, count) = phase_sums[index / width as usize % 8][index % width as usize % 8];
+            *v -= sum / count as f32;
+        }
+        Ok(Fingerprint { width, height, values })
+    }
+
+    pub fn read(path: &Path) -> io::Result<Fingerprint> {
+        let data = fs::read(path)?;
+        let invalid = |problem: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), problem));
+        if data.len() < 12 || &data[..4] != MAGIC {
+            return Err(invalid("not a PRNU fingerprint"));
+        }
+        let width = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        let height = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+        if data.len() != 12 + 4 * width as usize * height as usize {
+            return Err(invalid("truncated fingerprint"));
+        }
+        let values = data[12..].chunks_exact(4).map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]])).collect();
+        Ok(Fingerprint { width, height, values })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut data = Vec::with_capacity(12 + 4 * self.values.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        for value in &self.values {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        fs::write(path, data)
+    }
+}
+
+/// Verifies the camera claimed in the EXIF data against reference sensor
+/// fingerprints by correlating the noise residual with the expected PRNU
+/// pattern, and flags blocks that don't carry the pattern when it matches.
+pub struct PrnuDetector {
+    fingerprints: HashMap<String, Fingerprint>,
+    pub block_size: u32,
+    /// Whole image correlation needed to accept the claimed camera.
+    pub min_correlation: f64,
+    /// Fraction of the whole image correlation below which a block is flagged.
+    pub block_ratio: f64,
+    pub min_blocks: usize,
+}
+
+impl PrnuDetector {
+    pub fn new(fingerprints: HashMap<String, Fingerprint>) -> Self {
+        PrnuDetector { fingerprints, block_size: 64, min_correlation: 0.01, block_ratio: 0.3, min_blocks: 2 }
+    }
+
+    /// Loads every `<camera_key>.prnu` fingerprint in `dir`.
+    pub fn load_dir(dir: &Path) -> io::Result<PrnuDetector> {
+        let mut fingerprints = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "prnu") {
+                let key = path.file_stem().and_then(|stem| stem.to_str()).map(camera_key).unwrap_or_default();
+                fingerprints.insert(key, Fingerprint::read(&path)?);
+            }
+        }
+        info!("Loaded {} PRNU fingerprints from {}", fingerprints.len(), dir.display());
+        Ok(PrnuDetector::new(fingerprints))
+    }
+
+    fn verify(&self, img: &DynamicImage, camera: &str, fingerprint: &Fingerprint) -> DetectionReport {
+        let residual = residual(img);
+        let expected: Vec<f64> =
+            residual.intensity.iter().zip(&fingerprint.values).map(|(&i, &k)| (i * k) as f64).collect();
+        let noise: Vec<f64> = residual.noise.iter().map(|&w| w as f64).collect();
+        let usable: Vec<bool> = residual.intensity.iter().map(|&i| i < SATURATED).collect();
+        let pixels = |indices: &mut dyn Iterator<Item = usize>| -> (Vec<f64>, Vec<f64>) {
+            indices.filter(|&index| usable[index]).map(|index| (noise[index], expected[index])).unzip()
+        };
+
+        let (w, k) = pixels(&mut (0..noise.len()));
+        let overall = correlation(&w, &k);
+        if overall < self.min_correlation {
+            return DetectionReport {
+                findings: vec![format!("noise does not match claimed camera {}, correlation {:.4}", camera, overall)],
+                ..DetectionReport::default()
+            };
+        }
+
+        let width = img.width();
+        let grid = BlockGrid::from_fn(width, img.height(), self.block_size, |x0, y0, x1, y1| {
+            let mut indices = (y0..y1).flat_map(|y| (x0..x1).map(move |x| (y * width + x) as usize));
+            let (w, k) = pixels(&mut indices);
+            // Mostly clipped blocks can't be judged
+            if w.len() < ((x1 - x0) * (y1 - y0) / 2) as usize {
+                f64::NAN
+            } else {
+                correlation(&w, &k)
+            }
+        });
+        let mask: Vec<bool> = grid.values.iter().map(|&c| c < self.block_ratio * overall).collect();
+        DetectionReport {
+            regions: grid.regions(&mask, self.min_blocks),
+            heatmap: Some(grid.heatmap_with(|c| suspicion(1.0 - c / overall, 1.0 - self.block_ratio))),
+            findings: vec![format!("noise matches claimed camera {}, correlation {:.4}", camera, overall)],
+            ..DetectionReport::default()
+        }
+    }
+}
+
+impl Detector for PrnuDetector {
+    fn name(&self) -> &'static str {
+        "prnu"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "sensor_pattern"
+    }
+
+    // Fingerprints are only comparable at the resolution of the sensor
+    fn full_resolution(&self) -> bool {
+        true
+    }
+
+    // The claimed camera only exists in the encoded file
+    fn analyze(&self, _img: &DynamicImage) -> DetectionReport {
+        DetectionReport::default()
+    }
+
+    fn analyze_encoded(&self, img: &DynamicImage, encoded: &[u8]) -> DetectionReport {
+        let Some(camera) = claimed_camera(encoded) else {
+            return DetectionReport::default();
+        };
+        let finding = |finding: String| DetectionReport { findings: vec![finding], ..DetectionReport::default() };
+        match self.fingerprints.get(&camera) {
+            None => finding(format!("no reference fingerprint for claimed camera {}", camera)),
+            Some(fingerprint) if (fingerprint.width, fingerprint.height) != (img.width(), img.height()) => finding(format!(
+                "image is {}x{} but camera {} takes {}x{} images",
+                img.width(),
+                img.height(),
+                camera,
+                fingerprint.width,
+                fingerprint.height
+            )),
+            Some(fingerprint) => self.verify(img, &camera, fingerprint),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::heatmap::Overlay;
+use crate::RegionReport;
+use image::imageops;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// Blurred with a sigma of this fraction of the region's shorter side, so the
+// content is unrecognizable whatever the region's size
+const BLUR_FRACTION: f32 = 0.25;
+const MIN_BLUR_SIGMA: f32 = 8.0;
+
+/// Hides the content of every region, blurred or filled black depending on `overlay`.
+pub(crate) fn render_redacted(image: &DynamicImage, regions: &[RegionReport], overlay: Overlay) -> RgbaImage {
+    let mut image_buffer = image.to_rgba8();
+    let (width, height) = image_buffer.dimensions();
+    for report in regions {
+        let Some(region) = report.region.clip(width, height) else {
+            continue;
+        };
+        let (x, y) = (region.start.x, region.start.y);
+        let (w, h) = (region.end.x - x + 1, region.end.y - y + 1);
+        match overlay {
+            Overlay::Blur => {
+                let sigma = (w.min(h) as f32 * BLUR_FRACTION).max(MIN_BLUR_SIGMA);
+                let blurred = imageops::blur(&*imageops::crop_imm(&image_buffer, x, y, w, h), sigma);
+                imageops::replace(&mut image_buffer, &blurred, x as i64, y as i64);
+            }
+            _ => {
+                for py in y..y + h {
+                    for px in x..x + w {
+                        image_buffer.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+                    }
+                }
+            }
+        }
+    }
+    image_buffer
+}
@@ -0,0 +1,248 @@
+use crate::QueryResult;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A4 in points, everything is laid out from the top left margin down
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 40.0;
+const LEADING: f64 = 1.3;
+const IMAGE_HEIGHT: f64 = 300.0;
+// Downscaled before embedding, the page shows it at most 515pt wide
+const MAX_IMAGE_PIXELS: u32 = 1200;
+// Lines are cut to what fits the page width in 9pt Helvetica
+const MAX_LINE_CHARS: usize = 110;
+
+/// Everything a forensic report of one job is made from.
+pub(crate) struct Report<'a> {
+    pub job_id: &'a str,
+    pub result: &'a QueryResult,
+    pub input: &'a [u8],
+    pub output: &'a [u8],
+    /// The annotated image, or the input when there is none.
+    pub image: Option<&'a DynamicImage>,
+    pub received: SystemTime,
+    pub completed: SystemTime,
+}
+
+struct Layout {
+    content: String,
+    y: f64,
+    skipped: usize,
+}
+
+impl Layout {
+    // Keeps the last line free for noting what did not fit
+    fn text(&mut self, font: &str, size: f64, text: &str) {
+        if self.y - 2.0 * LEADING * size < MARGIN {
+            self.skipped += 1;
+            return;
+        }
+        self.write(font, size, text);
+    }
+
+    fn write(&mut self, font: &str, size: f64, text: &str) {
+        self.y -= LEADING * size;
+        let _ = writeln!(self.content, "BT /{} {} Tf {} {:.1} Td ({}) Tj ET", font, size, MARGIN, self.y, escape(text));
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.y -= 8.0;
+        self.text("F2", 12.0, text);
+    }
+
+    fn line(&mut self, text: &str) {
+        self.text("F1", 9.0, text);
+    }
+}
+
+// A PDF string literal body, only printable ASCII is kept since the font has no other glyphs
+fn escape(text: &str) -> String {
+    let mut chars: Vec<char> = text.chars().map(|c| if c == ' ' || c.is_ascii_graphic() { c } else { '?' }).collect();
+    if chars.len() > MAX_LINE_CHARS {
+        chars.truncate(MAX_LINE_CHARS - 3);
+        chars.extend("...".chars());
+    }
+    chars.into_iter().fold(String::new(), |mut escaped, c| {
+        if matches!(c, '\\' | '(' | ')') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// Lowercase hex of `bytes`, as digests are written.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+// Civil dates and days since the epoch, see https://howardhinnant.github.io/date_algorithms.html
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// `time` in RFC 3339 in UTC with milliseconds.
+pub fn timestamp(time: SystemTime) -> String {
+    let millis = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis());
+    let (secs, millis) = ((millis / 1000) as u64, millis % 1000);
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, time / 3600, time / 60 % 60, time % 60, millis
+    )
+}
+
+impl Report<'_> {
+    /// A one-page PDF with the verdict, the annotated image, the evidence of
+    /// every detector and the hashes of the input and output.
+    pub(crate) fn render(&self) -> io::Result<Vec<u8>> {
+        let result = self.result;
+        let mut layout = Layout { content: String::new(), y: PAGE_HEIGHT - MARGIN, skipped: 0 };
+        layout.text("F2", 16.0, "Forensic report");
+        layout.y -= 4.0;
+        layout.line(&format!("Job: {}", self.job_id));
+        layout.line(&format!(
+            "Verdict: {} (confidence {:.2}, severity {})",
+            result.result, result.confidence, result.severity
+        ));
+        layout.line(&format!("Received: {}", timestamp(self.received)));
+        layout.line(&format!("Completed: {}", timestamp(self.completed)));
+        layout.line(&format!("Input SHA-256: {} ({} bytes)", sha256(self.input), self.input.len()));
+        if !self.output.is_empty() {
+            layout.line(&format!("Output SHA-256: {} ({} bytes)", sha256(self.output), self.output.len()));
+        }
+
+        let image = self.image.map(|image| {
+            let image = if image.width().max(image.height()) > MAX_IMAGE_PIXELS {
+                image.resize(MAX_IMAGE_PIXELS, MAX_IMAGE_PIXELS, FilterType::Triangle)
+            } else {
+                image.clone()
+            };
+            image.to_rgb8()
+        });
+        if let Some(image) = &image {
+            let max_width = PAGE_WIDTH - 2.0 * MARGIN;
+            let scale = (max_width / image.width() as f64).min(IMAGE_HEIGHT / image.height() as f64);
+            let (width, height) = (image.width() as f64 * scale, image.height() as f64 * scale);
+            layout.y -= 10.0 + height;
+            let _ = writeln!(layout.content, "q {:.2} 0 0 {:.2} {} {:.2} cm /Im1 Do Q", width, height, MARGIN, layout.y);
+        }
+
+        layout.heading("Detector evidence");
+        for detector in &result.detectors {
+            let score = result.scores.get(&detector.name).map_or(String::new(), |p| format!(", score {:.2}", p));
+            layout.line(&format!(
+                "{}: {}, {} regions{}, {:.1} ms",
+                detector.name, detector.result, detector.regions, score, detector.elapsed_ms
+            ));
+        }
+        for report in &result.regions {
+            let (start, end) = (report.region.start, report.region.end);
+            layout.line(&format!(
+                "#{} {} ({}) from ({}, {}) to ({}, {}), confidence {:.2}",
+                report.index, report.kind, report.detector, start.x, start.y, end.x, end.y, report.region.confidence
+            ));
+        }
+        for finding in &result.metadata_findings {
+            layout.line(&format!("metadata: {}", finding.detail));
+        }
+        if result.detectors.is_empty() {
+            for line in result.text.lines() {
+                layout.line(line);
+            }
+        }
+        if layout.skipped > 0 {
+            let skipped = layout.skipped;
+            layout.write("F1", 9.0, &format!("... {} more lines, see the job result", skipped));
+        }
+
+        let mut objects = vec![
+            String::from("<< /Type /Catalog /Pages 2 0 R >>").into_bytes(),
+            String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>").into_bytes(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 6 0 R \
+                 /Resources << /Font << /F1 4 0 R /F2 5 0 R >>{} >> >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                if image.is_some() { " /XObject << /Im1 7 0 R >>" } else { "" }
+            )
+            .into_bytes(),
+            String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>").into_bytes(),
+            String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold >>").into_bytes(),
+            stream("", layout.content.as_bytes())?,
+        ];
+        if let Some(image) = &image {
+            let dict = format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 ",
+                image.width(),
+                image.height()
+            );
+            objects.push(stream(&dict, image.as_raw())?);
+        }
+        Ok(write_pdf(&objects))
+    }
+}
+
+// A Flate compressed stream object with `dict` entries besides its filter and length
+fn stream(dict: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let mut object = format!("<< {}/Filter /FlateDecode /Length {} >>\nstream\n", dict, compressed.len()).into_bytes();
+    object.extend_from_slice(&compressed);
+    object.extend_from_slice(b"\nendstream");
+    Ok(object)
+}
+
+// Numbers objects from 1 in order, the first being the catalog
+fn write_pdf(objects: &[Vec<u8>]) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = writeln!(trailer, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF", objects.len() + 1, xref);
+    pdf.extend_from_slice(trailer.as_bytes());
+    pdf
+}
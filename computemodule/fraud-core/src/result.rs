@@ -0,0 +1,218 @@
+use crate::{CocoDataset, ErrorReport, JobError, MetadataFinding, Region, RegionEvidence};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+/// Names of detectors and the like. Static in results as built, those of
+/// results read back are interned, leaking every distinct one once. Spelled
+/// out `&'static str` serde would borrow them from the input instead.
+pub(crate) type Name = &'static str;
+
+static INTERNED: Mutex<BTreeSet<Name>> = Mutex::new(BTreeSet::new());
+
+fn interned(name: String) -> Name {
+    let mut interned = INTERNED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match interned.get(name.as_str()) {
+        Some(name) => name,
+        None => {
+            let name = Box::leak(name.into_boxed_str());
+            interned.insert(name);
+            name
+        }
+    }
+}
+
+pub(crate) fn intern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Name, D::Error> {
+    String::deserialize(deserializer).map(interned)
+}
+
+pub(crate) fn intern_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<Name, f64>, D::Error> {
+    let map = BTreeMap::<String, f64>::deserialize(deserializer)?;
+    Ok(map.into_iter().map(|(key, value)| (interned(key), value)).collect())
+}
+
+/// A forged region with the detector that found it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RegionReport {
+    /// 1-based position in the image's region list, the label drawn next to the region.
+    pub index: usize,
+    #[serde(flatten)]
+    pub region: Region,
+    /// What the region shows, see [`Detector::region_type`](crate::Detector::region_type).
+    #[serde(rename = "type", deserialize_with = "intern")]
+    pub kind: Name,
+    #[serde(deserialize_with = "intern")]
+    pub detector: Name,
+}
+
+impl Borrow<Region> for RegionReport {
+    fn borrow(&self) -> &Region {
+        &self.region
+    }
+}
+
+/// The statistics behind one reported region, for jobs asking for `debug`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegionDebug {
+    /// The [`RegionReport::index`] of the region.
+    pub index: usize,
+    #[serde(deserialize_with = "intern")]
+    pub detector: Name,
+    /// Of every area the detector reported where the region is, several when they were merged into it.
+    pub evidence: Vec<RegionEvidence>,
+}
+
+/// The input pixels of one forged region.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegionCrop {
+    /// The [`RegionReport::index`] of the region.
+    pub index: usize,
+    /// Base64 encoded like `enc_img_out`.
+    pub enc_img: String,
+}
+
+/// What a single detector contributed to the verdict.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DetectorSummary {
+    pub name: String,
+    pub elapsed_ms: f64,
+    /// Regions left after merging and the sensitivity filters.
+    pub regions: usize,
+    /// The verdict the detector's evidence would give on its own.
+    pub result: String,
+}
+
+/// The verdict for one image of a multi-page input.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PageResult {
+    /// 1-based page or animation frame number.
+    pub page: usize,
+    /// 1-based position of the image on its page, PDF pages can embed several.
+    pub image: usize,
+    pub result: String,
+    pub confidence: f64,
+    pub severity: u8,
+    pub regions: Vec<RegionReport>,
+    pub text: String,
+    pub enc_img_out: String,
+    pub svg_overlay: Option<String>,
+    pub crops: Vec<RegionCrop>,
+    /// The statistics behind every region, for jobs asking for `debug`.
+    pub debug: Option<Vec<RegionDebug>>,
+}
+
+/// The verdict for one file of an archive input.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileResult {
+    /// Path of the file within the archive.
+    pub name: String,
+    /// "Failed" when the file could not be read or analyzed, `text` says why.
+    pub result: String,
+    pub confidence: f64,
+    pub severity: u8,
+    pub regions: Vec<RegionReport>,
+    pub text: String,
+    /// Path of the annotated file within the output archive.
+    pub output: Option<String>,
+    pub svg_overlay: Option<String>,
+    pub crops: Vec<RegionCrop>,
+    /// The statistics behind every region, for jobs asking for `debug`.
+    pub debug: Option<Vec<RegionDebug>>,
+}
+
+/// A span of an animation or video, in seconds from its start.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct TimeRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct QueryResult {
+    /// Empty for regions only jobs.
+    pub enc_img_out: String,
+    /// Where the annotated image was uploaded to instead of being returned in `enc_img_out`.
+    pub img_out_url: Option<String>,
+    /// The regions as an SVG layer the size of the image, when enabled. When it
+    /// replaces the annotated image `enc_img_out` is left empty.
+    pub svg_overlay: Option<String>,
+    pub text: String,
+    pub result: String,
+    pub confidence: f64,
+    /// 0 to 100 for ranking cases, weighing regions by area, count and confidence. The worst of the pages or files
+    /// of multi-image inputs.
+    pub severity: u8,
+    pub regions: Vec<RegionReport>,
+    pub metadata_findings: Vec<MetadataFinding>,
+    pub scores: BTreeMap<String, f64>,
+    pub measurements: BTreeMap<String, f64>,
+    pub detectors: Vec<DetectorSummary>,
+    /// Per image verdicts of a multi-page input, the top level verdict is the worst of them.
+    pub pages: Vec<PageResult>,
+    /// Spans of an animation or video whose frames are suspicious or edited.
+    pub tampered_ranges: Vec<TimeRange>,
+    /// Per file verdicts of an archive input, whose annotated files are returned as a ZIP.
+    pub files: Vec<FileResult>,
+    /// The regions of every analyzed image in COCO format, when enabled.
+    pub coco: Option<CocoDataset>,
+    /// A one-page PDF summary of the job for audits, base64 encoded, when enabled.
+    pub enc_report: Option<String>,
+    /// The output image downscaled for result lists, base64 encoded like `enc_img_out`, when enabled.
+    pub enc_thumbnail: Option<String>,
+    /// Every region cut from the input at full resolution, when enabled.
+    pub crops: Vec<RegionCrop>,
+    /// Per image results of a batch job, in the order of its images.
+    pub batch: Vec<QueryResult>,
+    /// Why the job failed, for "Failed", "cancelled", "timeout" and "dead_letter" results.
+    pub error: Option<ErrorReport>,
+    /// The statistics behind every region, for jobs asking for `debug`. Those of pages and archive files are with them.
+    pub debug: Option<Vec<RegionDebug>>,
+    /// Milliseconds the job spent in each of its steps, e.g. `decode` and `detect`, when enabled.
+    pub timings: Option<BTreeMap<String, f64>>,
+    /// Proof of the verdict for consumers receiving it through other services, when results are signed.
+    pub signature: Option<ResultSignature>,
+}
+
+/// A signature of the verdict of a job by the worker that posted it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResultSignature {
+    /// Of the key that signed it, for picking the one to verify it with.
+    pub key_id: String,
+    /// "ed25519" or "hmac-sha256".
+    pub algorithm: String,
+    /// The signed JSON, verified as sent rather than serialized again and then compared to the result: the key ID
+    /// and algorithm, the job ID, the SHA-256 of the input, the verdict with its regions, the SHA-256 of
+    /// `enc_img_out`, `img_out_url`, and the verdicts of batch images, pages and files.
+    pub payload: String,
+    /// Of `payload`, base64 encoded.
+    pub signature: String,
+}
+
+impl QueryResult {
+    /// A result without a verdict, "cancelled" or "timeout" when `err` is a cancellation or timeout and
+    /// "Failed" otherwise.
+    pub fn failed(err: &JobError) -> QueryResult {
+        let result = match err {
+            JobError::Cancelled => "cancelled",
+            JobError::TimedOut(_) => "timeout",
+            _ => "Failed",
+        };
+        QueryResult {
+            text: err.to_string(),
+            result: String::from(result),
+            error: Some(ErrorReport::from(err)),
+            ..QueryResult::default()
+        }
+    }
+
+    /// A "dead_letter" result, telling the caller to stop retrying a job that failed `failures` times.
+    pub fn dead_letter(failures: u32, last_error: ErrorReport) -> QueryResult {
+        QueryResult {
+            text: format!("Gave up after {} failed attempts: {}", failures, last_error.message),
+            result: String::from("dead_letter"),
+            error: Some(last_error),
+            ..QueryResult::default()
+        }
+    }
+}
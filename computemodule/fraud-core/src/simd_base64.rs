@@ -0,0 +1,183 @@
+use base64::engine::general_purpose;
+use base64::{DecodeError, Engine as _};
+use std::io::{self, Write};
+
+// Input bytes buffered by `Base64Writer` before encoding them, a multiple of 3 so only the end is padded
+const WRITER_CHUNK: usize = 48 * 1024;
+
+/// Standard base64 with padding, like `base64`'s `STANDARD` engine which
+/// it matches byte for byte. The bulk is done 16 characters at a time with
+/// SSSE3 where the CPU has it, the engine encodes the last few bytes and
+/// decodes the last block with its padding, and reports invalid input.
+pub fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    encode_base64_into(data, &mut encoded);
+    encoded
+}
+
+/// Appends to `encoded`.
+pub fn encode_base64_into(data: &[u8], encoded: &mut String) {
+    let done = simd::encode(data, encoded);
+    general_purpose::STANDARD.encode_string(&data[done..], encoded);
+}
+
+/// Appends to `decoded`, leaving it as it was when `encoded` is invalid.
+pub fn decode_base64_into(encoded: &[u8], decoded: &mut Vec<u8>) -> Result<(), DecodeError> {
+    let len = decoded.len();
+    let done = simd::decode(encoded, decoded);
+    if general_purpose::STANDARD.decode_vec(&encoded[done..], decoded).is_ok() {
+        return Ok(());
+    }
+    // Again from the start, for where in `encoded` the error is
+    decoded.truncate(len);
+    let res = general_purpose::STANDARD.decode_vec(encoded, decoded);
+    decoded.truncate(len);
+    res
+}
+
+/// Base64 encodes what is written to it, see [`encode_base64`].
+pub(crate) struct Base64Writer {
+    encoded: String,
+    pending: Vec<u8>,
+}
+
+impl Base64Writer {
+    pub(crate) fn new() -> Base64Writer {
+        Base64Writer { encoded: String::new(), pending: Vec::with_capacity(WRITER_CHUNK + 2) }
+    }
+
+    pub(crate) fn into_string(mut self) -> String {
+        encode_base64_into(&self.pending, &mut self.encoded);
+        self.encoded
+    }
+}
+
+impl Write for Base64Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if self.pending.len() >= WRITER_CHUNK {
+            let whole = self.pending.len() / 3 * 3;
+            encode_base64_into(&self.pending[..whole], &mut self.encoded);
+            self.pending.drain(..whole);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    /// Appends the base64 of the longest prefix of `data` it does 12 bytes at
+    /// a time, returning its length.
+    pub(super) fn encode(data: &[u8], encoded: &mut String) -> usize {
+        if !is_x86_feature_detected!("ssse3") {
+            return 0;
+        }
+        // SAFETY: SSSE3 is available, and only base64 characters are appended
+        unsafe { encode_ssse3(data, encoded.as_mut_vec()) }
+    }
+
+    /// Appends the bytes of the longest prefix of `encoded` it does 16
+    /// characters at a time, stopping at the first padding or invalid
+    /// character, returning its length.
+    pub(super) fn decode(encoded: &[u8], decoded: &mut Vec<u8>) -> usize {
+        if !is_x86_feature_detected!("ssse3") {
+            return 0;
+        }
+        // SAFETY: SSSE3 is available
+        unsafe { decode_ssse3(encoded, decoded) }
+    }
+
+    // Every 16 byte load reads 4 bytes past the 12 it encodes
+    #[target_feature(enable = "ssse3")]
+    unsafe fn encode_ssse3(data: &[u8], out: &mut Vec<u8>) -> usize {
+        let blocks = data.len().saturating_sub(4) / 12;
+        out.reserve(blocks * 16);
+        let (src, dst) = (data.as_ptr(), out.as_mut_ptr().add(out.len()));
+        for block in 0..blocks {
+            let input = _mm_loadu_si128(src.add(block * 12) as *const __m128i);
+            _mm_storeu_si128(dst.add(block * 16) as *mut __m128i, encode_block(input));
+        }
+        out.set_len(out.len() + blocks * 16);
+        blocks * 12
+    }
+
+    // Spreads 12 bytes over 16 lanes of 6 bits and maps those to the alphabet, after Muła and Lemire
+    #[target_feature(enable = "ssse3")]
+    unsafe fn encode_block(input: __m128i) -> __m128i {
+        let input = _mm_shuffle_epi8(input, _mm_set_epi8(10, 11, 9, 10, 7, 8, 6, 7, 4, 5, 3, 4, 1, 2, 0, 1));
+        let high = _mm_mulhi_epu16(_mm_and_si128(input, _mm_set1_epi32(0x0fc0fc00)), _mm_set1_epi32(0x04000040));
+        let low = _mm_mullo_epi16(_mm_and_si128(input, _mm_set1_epi32(0x003f03f0)), _mm_set1_epi32(0x01000010));
+        let indices = _mm_or_si128(high, low);
+        // 0 for a-z, 1 to 10 for 0-9, 11 for +, 12 for / and 13 for A-Z, picking what to add to the index
+        let range = _mm_subs_epu8(indices, _mm_set1_epi8(51));
+        let upper = _mm_cmpgt_epi8(_mm_set1_epi8(26), indices);
+        let range = _mm_or_si128(range, _mm_and_si128(upper, _mm_set1_epi8(13)));
+        let (digits, plus, slash) = ((b'0' as i8) - 52, (b'+' as i8) - 62, (b'/' as i8) - 63);
+        let offsets = _mm_setr_epi8(
+            b'a' as i8 - 26, digits, digits, digits, digits, digits, digits, digits, digits, digits, digits, plus,
+            slash, b'A' as i8, 0, 0,
+        );
+        _mm_add_epi8(indices, _mm_shuffle_epi8(offsets, range))
+    }
+
+    // Every 16 byte store writes 4 bytes past the 12 it decodes
+    #[target_feature(enable = "ssse3")]
+    unsafe fn decode_ssse3(encoded: &[u8], out: &mut Vec<u8>) -> usize {
+        let blocks = encoded.len() / 16;
+        out.reserve(blocks * 12 + 4);
+        let (src, dst) = (encoded.as_ptr(), out.as_mut_ptr().add(out.len()));
+        let mut done = 0;
+        while done < blocks {
+            let Some(bytes) = decode_block(_mm_loadu_si128(src.add(done * 16) as *const __m128i)) else {
+                break;
+            };
+            _mm_storeu_si128(dst.add(done * 12) as *mut __m128i, bytes);
+            done += 1;
+        }
+        out.set_len(out.len() + done * 12);
+        done * 16
+    }
+
+    // Validates and maps 16 characters to their 6 bits by their nibbles, then packs those into 12 bytes, after
+    // Muła and Lemire
+    #[target_feature(enable = "ssse3")]
+    unsafe fn decode_block(input: __m128i) -> Option<__m128i> {
+        let valid_low = _mm_setr_epi8(
+            0x15, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x13, 0x1a, 0x1b, 0x1b, 0x1b, 0x1a,
+        );
+        let valid_high = _mm_setr_epi8(
+            0x10, 0x10, 0x01, 0x02, 0x04, 0x08, 0x04, 0x08, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10,
+        );
+        let offsets = _mm_setr_epi8(0, 16, 19, 4, -65, -65, -71, -71, 0, 0, 0, 0, 0, 0, 0, 0);
+        let slash = _mm_set1_epi8(0x2f);
+        let high_nibbles = _mm_and_si128(_mm_srli_epi32(input, 4), slash);
+        let low_nibbles = _mm_and_si128(input, slash);
+        let invalid = _mm_and_si128(_mm_shuffle_epi8(valid_low, low_nibbles), _mm_shuffle_epi8(valid_high, high_nibbles));
+        if _mm_movemask_epi8(_mm_cmpgt_epi8(invalid, _mm_setzero_si128())) != 0 {
+            return None;
+        }
+        let offset = _mm_shuffle_epi8(offsets, _mm_add_epi8(_mm_cmpeq_epi8(input, slash), high_nibbles));
+        let sextets = _mm_add_epi8(input, offset);
+        let pairs = _mm_maddubs_epi16(sextets, _mm_set1_epi32(0x01400140));
+        let packed = _mm_madd_epi16(pairs, _mm_set1_epi32(0x00011000));
+        Some(_mm_shuffle_epi8(packed, _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1)))
+    }
+}
+
+// Other CPUs leave it all to the engine
+#[cfg(not(target_arch = "x86_64"))]
+mod simd {
+    pub(super) fn encode(_data: &[u8], _encoded: &mut String) -> usize {
+        0
+    }
+
+    pub(super) fn decode(_encoded: &[u8], _decoded: &mut Vec<u8>) -> usize {
+        0
+    }
+}
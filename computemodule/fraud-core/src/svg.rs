@@ -0,0 +1,55 @@
+use crate::RegionReport;
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+/// Whether region annotations are also returned as an SVG layer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SvgOverlay {
+    #[default]
+    Off,
+    /// Next to the annotated image.
+    Alongside,
+    /// Instead of the annotated image, clients draw it over the input they already have.
+    Only,
+}
+
+impl FromStr for SvgOverlay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "off" => Ok(SvgOverlay::Off),
+            "alongside" => Ok(SvgOverlay::Alongside),
+            "only" => Ok(SvgOverlay::Only),
+            _ => Err(String::from("expected off, alongside or only")),
+        }
+    }
+}
+
+/// An SVG the size of the image with a rectangle per region, grouped by
+/// detector so frontends can toggle them.
+pub(crate) fn render_svg(width: u32, height: u32, regions: &[RegionReport]) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        width, height
+    );
+    let mut detectors: Vec<&str> = regions.iter().map(|report| report.detector).collect();
+    detectors.sort_unstable();
+    detectors.dedup();
+    for detector in detectors {
+        let _ = writeln!(svg, "  <g class=\"detector\" data-detector=\"{}\" fill=\"none\" stroke=\"red\">", detector);
+        for report in regions.iter().filter(|report| report.detector == detector) {
+            let (region, kind) = (report.region, report.kind);
+            // Regions are inclusive pixel ranges, strokes are centered on the box edges
+            let (x, y) = (region.start.x as f64 + 0.5, region.start.y as f64 + 0.5);
+            let (width, height) = (region.end.x - region.start.x, region.end.y - region.start.y);
+            let _ = write!(svg, "    <rect class=\"region\" data-index=\"{}\" data-type=\"{}\"", report.index, kind);
+            let _ = write!(svg, " data-confidence=\"{:.2}\"", region.confidence);
+            let _ = write!(svg, " x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">", x, y, width, height);
+            let _ = writeln!(svg, "<title>#{} {} {} {:.2}</title></rect>", report.index, detector, kind, region.confidence);
+        }
+        svg.push_str("  </g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,123 @@
+use crate::blocks::BlockGrid;
+use crate::detector::{DetectionReport, Detector};
+use crate::Heatmap;
+use exif::{In, Tag};
+use image::imageops::FilterType;
+use image::{load_from_memory, DynamicImage, GrayImage};
+use log::warn;
+use std::io::Cursor;
+
+/// Compares the EXIF thumbnail written at capture time with the full image.
+/// Editors often update the pixels but leave the thumbnail alone, so blocks
+/// where the downscaled image no longer matches the thumbnail were changed
+/// after capture.
+pub struct ThumbnailDetector {
+    /// Block size in thumbnail pixels.
+    pub block_size: u32,
+    /// How many robust standard deviations above the median difference a block must be.
+    pub sensitivity: f64,
+    /// Minimum mean absolute luma difference (in 0-255 levels) for a block to be flagged.
+    pub min_difference: f64,
+    pub min_blocks: usize,
+    /// Largest relative aspect ratio difference still considered the same framing.
+    pub max_aspect_drift: f64,
+}
+
+impl Default for ThumbnailDetector {
+    fn default() -> Self {
+        ThumbnailDetector { block_size: 8, sensitivity: 4.0, min_difference: 16.0, min_blocks: 2, max_aspect_drift: 0.03 }
+    }
+}
+
+fn thumbnail(encoded: &[u8]) -> Option<DynamicImage> {
+    let exif = exif::Reader::new().read_from_container(&mut Cursor::new(encoded)).ok()?;
+    let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let length = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let data = exif.buf().get(offset..offset.checked_add(length)?)?;
+    match load_from_memory(data) {
+        Ok(thumbnail) => Some(thumbnail),
+        Err(err) => {
+            warn!("Failed to decode EXIF thumbnail: {}", err);
+            None
+        }
+    }
+}
+
+impl ThumbnailDetector {
+    fn difference_grid(&self, thumbnail: &GrayImage, downscaled: &GrayImage) -> BlockGrid {
+        let (width, height) = thumbnail.dimensions();
+        BlockGrid::from_fn(width, height, self.block_size, |x0, y0, x1, y1| {
+            let mut total = 0u64;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    total += thumbnail.get_pixel(x, y).0[0].abs_diff(downscaled.get_pixel(x, y).0[0]) as u64;
+                }
+            }
+            total as f64 / ((x1 - x0) * (y1 - y0)) as f64
+        })
+    }
+}
+
+impl Detector for ThumbnailDetector {
+    fn name(&self) -> &'static str {
+        "thumbnail"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "thumbnail_mismatch"
+    }
+
+    // The thumbnail only exists in the encoded file
+    fn analyze(&self, _img: &DynamicImage) -> DetectionReport {
+        DetectionReport::default()
+    }
+
+    fn analyze_encoded(&self, img: &DynamicImage, encoded: &[u8]) -> DetectionReport {
+        let Some(thumbnail) = thumbnail(encoded) else {
+            return DetectionReport::default();
+        };
+        let (width, height) = (img.width(), img.height());
+        let (thumb_width, thumb_height) = (thumbnail.width(), thumbnail.height());
+        if thumb_width == 0 || thumb_height == 0 {
+            return DetectionReport::default();
+        }
+
+        // A different framing means the image was cropped or padded after capture
+        // and the blocks can no longer be lined up.
+        let aspect = width as f64 / height as f64;
+        let thumb_aspect = thumb_width as f64 / thumb_height as f64;
+        if (aspect / thumb_aspect - 1.0).abs() > self.max_aspect_drift {
+            return DetectionReport {
+                cropped: true,
+                findings: vec![format!(
+                    "{}x{} thumbnail but a {}x{} image, the framing changed after capture",
+                    thumb_width, thumb_height, width, height
+                )],
+                ..DetectionReport::default()
+            };
+        }
+
+        let thumbnail = thumbnail.to_luma8();
+        let downscaled = img.resize_exact(thumb_width, thumb_height, FilterType::Triangle).to_luma8();
+        let grid = self.difference_grid(&thumbnail, &downscaled);
+        let (median, _) = grid.robust_stats();
+        if median > self.min_difference {
+            return DetectionReport {
+                findings: vec![format!("does not match the image, mean block difference {:.1}", median)],
+                ..DetectionReport::default()
+            };
+        }
+
+        let mask = grid.high_outliers(self.sensitivity, self.min_difference);
+        let (sx, sy) = (width as f64 / thumb_width as f64, height as f64 / thumb_height as f64);
+        let regions = grid
+            .regions(&mask, self.min_blocks)
+            .into_iter()
+            .map(|region| region.scale(sx, sy, width, height))
+            .collect();
+        let heatmap = grid.heatmap(self.sensitivity, self.min_difference, false);
+        let (block_width, block_height) = (heatmap.block_width * sx, heatmap.block_height * sy);
+        let heatmap = Heatmap { block_width, block_height, ..heatmap };
+        DetectionReport { regions, heatmap: Some(heatmap), ..DetectionReport::default() }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::animation::MAX_FRAMES;
+use crate::limits::InputLimits;
+use crate::pages::PageImage;
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat};
+use log::warn;
+use std::io::Cursor;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+pub fn is_tiff(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+fn to_image(width: u32, height: u32, color: ColorType, samples: DecodingResult) -> Option<DynamicImage> {
+    Some(match (color, samples) {
+        (ColorType::Gray(8), DecodingResult::U8(s)) => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::GrayA(8), DecodingResult::U8(s)) => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::RGB(8), DecodingResult::U8(s)) => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::RGBA(8), DecodingResult::U8(s)) => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::Gray(16), DecodingResult::U16(s)) => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::RGB(16), DecodingResult::U16(s)) => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, s)?),
+        (ColorType::RGBA(16), DecodingResult::U16(s)) => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, s)?),
+        _ => return None,
+    })
+}
+
+/// Decodes the frames of a TIFF, up to MAX_FRAMES, and re-encodes them as
+/// PNG, or returns None when the file holds a single frame and can be
+/// analyzed directly. Frames larger than `limits` allow fail it before they
+/// are decoded.
+pub fn tiff_pages(data: &[u8], limits: &InputLimits) -> Option<Result<Vec<PageImage>, String>> {
+    let mut decoder = match Decoder::new(Cursor::new(data)) {
+        Ok(decoder) => decoder,
+        Err(err) => return Some(Err(format!("Invalid TIFF: {}", err))),
+    };
+    if !decoder.more_images() {
+        return None;
+    }
+    let mut pages = Vec::new();
+    for page in 1.. {
+        if page > MAX_FRAMES {
+            warn!("Only analyzing the first {} frames of the TIFF", MAX_FRAMES);
+            break;
+        }
+        let checked = decoder.dimensions().map(|(width, height)| limits.check_dimensions(width, height));
+        if let Ok(Err(problem)) = checked {
+            return Some(Err(format!("TIFF frame {}: {}", page, problem)));
+        }
+        let frame = decoder.dimensions().and_then(|(width, height)| {
+            let color = decoder.colortype()?;
+            Ok((width, height, color, decoder.read_image()?))
+        });
+        match frame {
+            Ok((width, height, color, samples)) => match to_image(width, height, color, samples) {
+                Some(image) => {
+                    let mut encoded = Cursor::new(Vec::new());
+                    if let Err(err) = image.write_to(&mut encoded, ImageOutputFormat::Png) {
+                        return Some(Err(err.to_string()));
+                    }
+                    pages.push(PageImage { page, data: encoded.into_inner(), delay: None });
+                }
+                None => warn!("Skipping TIFF frame {} with unsupported color type {:?}", page, color),
+            },
+            Err(err) => warn!("Skipping TIFF frame {}: {}", page, err),
+        }
+        if !decoder.more_images() {
+            break;
+        }
+        if let Err(err) = decoder.next_image() {
+            return Some(Err(format!("Invalid TIFF frame {}: {}", page + 1, err)));
+        }
+    }
+    Some(Ok(pages))
+}
@@ -0,0 +1,101 @@
+use crate::heif::{ftyp_brands, heif_format};
+use crate::limits::InputLimits;
+use crate::pages::PageImage;
+
+const VIDEO_BRANDS: [&[u8; 4]; 12] =
+    [b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"qt  ", b"3gp4", b"3gp5"];
+
+pub fn is_video(data: &[u8]) -> bool {
+    heif_format(data).is_none() && ftyp_brands(data).iter().any(|brand| VIDEO_BRANDS.iter().any(|video| brand == video))
+}
+
+#[cfg(feature = "video")]
+fn decode_frames(
+    data: &[u8],
+    sample_rate: f64,
+    limits: &InputLimits,
+) -> Result<Vec<PageImage>, Box<dyn std::error::Error>> {
+    use crate::animation::max_frames;
+    use image::{Delay, ImageOutputFormat, RgbImage};
+    use log::warn;
+    use mp4::{MediaType, Mp4Reader, TrackType};
+    use openh264::decoder::Decoder;
+    use openh264::formats::YUVSource;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    let mut reader = Mp4Reader::read_header(Cursor::new(data), data.len() as u64)?;
+    let track = reader
+        .tracks()
+        .values()
+        .find(|track| matches!(track.track_type(), Ok(TrackType::Video)))
+        .ok_or("Video has no video track")?;
+    if !matches!(track.media_type(), Ok(MediaType::H264)) {
+        return Err("Only H.264 video is supported".into());
+    }
+    let (track_id, timescale) = (track.track_id(), track.timescale() as f64);
+    // The decoder reads Annex B, parameter sets first
+    let mut stream = Vec::new();
+    for parameter_set in [track.sequence_parameter_set()?, track.picture_parameter_set()?] {
+        stream.extend_from_slice(&[0, 0, 0, 1]);
+        stream.extend_from_slice(parameter_set);
+    }
+
+    let interval = 1.0 / sample_rate;
+    let mut decoder = Decoder::new()?;
+    let mut frames: Vec<PageImage> = Vec::new();
+    let mut times = Vec::new();
+    let mut next = 0.0;
+    for sample_id in 1..=reader.sample_count(track_id)? {
+        let Some(sample) = reader.read_sample(track_id, sample_id)? else { continue };
+        let mut nal_units = sample.bytes.as_ref();
+        while nal_units.len() > 4 {
+            let length = u32::from_be_bytes([nal_units[0], nal_units[1], nal_units[2], nal_units[3]]) as usize;
+            let nal_unit = nal_units.get(4..4 + length).ok_or("Truncated H.264 sample")?;
+            stream.extend_from_slice(&[0, 0, 0, 1]);
+            stream.extend_from_slice(nal_unit);
+            nal_units = &nal_units[4 + length..];
+        }
+        let time = (sample.start_time as f64 + sample.rendering_offset as f64) / timescale;
+        let picture = decoder.decode(&stream)?;
+        stream.clear();
+        // Sample times are rounded to the track's timescale
+        let Some(picture) = picture.filter(|_| time + 1e-6 >= next) else { continue };
+        let (width, height) = picture.dimensions();
+        let max_frames = max_frames(limits, width as u32, height as u32);
+        if frames.len() >= max_frames {
+            warn!("Only analyzing the first {} sampled frames of the video", max_frames);
+            break;
+        }
+        let checked = limits.check_dimensions(width as u32, height as u32);
+        checked.map_err(|problem| format!("Frame {}: {}", frames.len() + 1, problem))?;
+        let mut pixels = vec![0; width * height * 3];
+        picture.write_rgb8(&mut pixels);
+        let image = RgbImage::from_raw(width as u32, height as u32, pixels).ok_or("Invalid video frame")?;
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, ImageOutputFormat::Png)?;
+        frames.push(PageImage { page: frames.len() + 1, data: encoded.into_inner(), delay: None });
+        times.push(time);
+        next = ((time * sample_rate + 1e-6).floor() + 1.0) / sample_rate;
+    }
+    // Frames last until the next sampled one, so their delays add up to the video's timeline
+    times.push(times.last().map_or(0.0, |last| last + interval));
+    for (frame, shown) in frames.iter_mut().zip(times.windows(2)) {
+        frame.delay = Some(Delay::from_saturating_duration(Duration::from_secs_f64(shown[1] - shown[0])));
+    }
+    Ok(frames)
+}
+
+/// Decodes a frame every `1 / sample_rate` seconds of an MP4 video and
+/// re-encodes it as PNG, up to MAX_FRAMES and as many as fit the memory
+/// budget of `limits`. Frames larger than `limits` allow fail it before they
+/// are converted.
+#[cfg(feature = "video")]
+pub fn video_frames(data: &[u8], sample_rate: f64, limits: &InputLimits) -> Result<Vec<PageImage>, String> {
+    decode_frames(data, sample_rate, limits).map_err(|err| format!("Invalid video: {}", err))
+}
+
+#[cfg(not(feature = "video"))]
+pub fn video_frames(_data: &[u8], _sample_rate: f64, _limits: &InputLimits) -> Result<Vec<PageImage>, String> {
+    Err(String::from("Video input requires the video feature"))
+}
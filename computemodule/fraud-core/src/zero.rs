@@ -0,0 +1,86 @@
+use crate::detector::{DetectionReport, Detector, RegionEvidence};
+use crate::{Point, Region};
+use forgery_detection_zero::{ForgedRegion, Grid, Vote, Votes, Zero};
+use image::DynamicImage;
+use std::collections::BTreeMap;
+
+/// JPEG grid alignment analysis from the `forgery-detection-zero` crate,
+/// reporting both foreign grid and missing grid areas.
+pub struct ZeroDetector;
+
+// An NFA of 1 (one such region expected by chance) maps to 0.5, lower NFAs towards 1
+fn to_region(r: &ForgedRegion) -> Region {
+    let confidence = 1.0 / (1.0 + 10f64.powf(r.lnfa));
+    Region { start: Point { x: r.start.0, y: r.start.1 }, end: Point { x: r.end.0, y: r.end.1 }, confidence }
+}
+
+// The NFA and grid of the region, and how the pixels of its box voted: for its grid, the main one, another or none
+fn grid_evidence(
+    r: &ForgedRegion,
+    votes: &Votes,
+    main_grid: Option<Grid>,
+    missing: bool,
+    img: &DynamicImage,
+) -> RegionEvidence {
+    let mut values = BTreeMap::from([
+        ("lnfa", r.lnfa),
+        ("nfa", 10f64.powf(r.lnfa)),
+        ("grid_x", f64::from(r.grid.x())),
+        ("grid_y", f64::from(r.grid.y())),
+        ("missing_grid", if missing { 1.0 } else { 0.0 }),
+    ]);
+    if let Some(main_grid) = main_grid {
+        values.insert("main_grid_x", f64::from(main_grid.x()));
+        values.insert("main_grid_y", f64::from(main_grid.y()));
+    }
+    let (mut grid_votes, mut main_votes, mut other_votes, mut invalid_votes) = (0, 0, 0, 0);
+    let (x0, x1) = (r.start.0.min(r.end.0), r.start.0.max(r.end.0).min(img.width().saturating_sub(1)));
+    let (y0, y1) = (r.start.1.min(r.end.1), r.start.1.max(r.end.1).min(img.height().saturating_sub(1)));
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            match &votes[[x, y]] {
+                Vote::AlignedWith(grid) if *grid == r.grid => grid_votes += 1,
+                Vote::AlignedWith(grid) if Some(*grid) == main_grid => main_votes += 1,
+                Vote::AlignedWith(_) => other_votes += 1,
+                Vote::Invalid => invalid_votes += 1,
+            }
+        }
+    }
+    values.insert("votes_grid", f64::from(grid_votes));
+    values.insert("votes_main_grid", f64::from(main_votes));
+    values.insert("votes_other_grid", f64::from(other_votes));
+    values.insert("votes_invalid", f64::from(invalid_votes));
+    RegionEvidence { region: to_region(r), values }
+}
+
+impl Detector for ZeroDetector {
+    fn name(&self) -> &'static str {
+        "zero"
+    }
+
+    fn region_type(&self) -> &'static str {
+        "jpeg_grid"
+    }
+
+    fn analyze(&self, img: &DynamicImage) -> DetectionReport {
+        let foreign_grid_areas = Zero::from_image(img).detect_forgeries();
+        // Missing grid areas are only defined relative to a main grid, which images that
+        // were never saved as JPEGs (like animation frames) lack
+        let missing_grid_areas = foreign_grid_areas.detect_missing_grid_areas().ok().flatten();
+        let missing = missing_grid_areas.as_ref().map_or(&[][..], |areas| areas.forged_regions());
+        let regions = foreign_grid_areas
+            .forged_regions()
+            .iter()
+            .chain(missing)
+            .map(to_region)
+            .collect();
+        let main_grid = foreign_grid_areas.main_grid();
+        let foreign = foreign_grid_areas.forged_regions().iter();
+        let mut evidence: Vec<RegionEvidence> =
+            foreign.map(|r| grid_evidence(r, foreign_grid_areas.votes(), main_grid, false, img)).collect();
+        if let Some(areas) = &missing_grid_areas {
+            evidence.extend(missing.iter().map(|r| grid_evidence(r, areas.votes(), main_grid, true, img)));
+        }
+        DetectionReport { regions, cropped: foreign_grid_areas.is_cropped(), evidence, ..DetectionReport::default() }
+    }
+}
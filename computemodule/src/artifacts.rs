@@ -0,0 +1,98 @@
+use fraud_core::{DetectorMap, Heatmap};
+use image::{GrayImage, ImageFormat, Luma};
+use log::{error, info};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Inputs, detector maps and annotated outputs of jobs, kept on disk for
+/// diagnosing disputed verdicts with one directory per job. Only the newest
+/// `max_jobs` directories are kept, and none older than `max_age`.
+pub struct ArtifactDir {
+    dir: PathBuf,
+    max_jobs: usize,
+    max_age: Duration,
+}
+
+// Job IDs come from the caller, only letters, digits, dots, dashes and underscores make it into file names, never
+// empty or starting with a dot
+fn file_name(name: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+    let name: String = name.trim().chars().map(|c| if safe(c) { c } else { '-' }).collect();
+    match name.trim_start_matches('.') {
+        "" => String::from("-"),
+        name => name.to_string(),
+    }
+}
+
+// With the extension of its format where it is an image
+fn with_extension(stem: &str, data: &[u8]) -> String {
+    let extension = image::guess_format(data).ok().and_then(|format| format.extensions_str().first().copied());
+    format!("{}.{}", stem, extension.unwrap_or("bin"))
+}
+
+// The suspicion of every pixel from black for none to white, at the size of the image it covers
+fn map_png(heatmap: &Heatmap) -> io::Result<Vec<u8>> {
+    let width = (heatmap.cols as f64 * heatmap.block_width).ceil() as u32;
+    let height = (heatmap.rows as f64 * heatmap.block_height).ceil() as u32;
+    let map = GrayImage::from_fn(width, height, |x, y| Luma([(heatmap.at(x, y).clamp(0.0, 1.0) * 255.0) as u8]));
+    let mut png = Vec::new();
+    map.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).map_err(io::Error::other)?;
+    Ok(png)
+}
+
+impl ArtifactDir {
+    pub fn open(dir: PathBuf, max_jobs: usize, max_age: Duration) -> io::Result<ArtifactDir> {
+        fs::create_dir_all(&dir)?;
+        Ok(ArtifactDir { dir, max_jobs, max_age })
+    }
+
+    /// Saves the `files` of `job_id`, named without their extension, and the
+    /// detector `maps` of its images in place of what an earlier delivery of
+    /// it saved, then drops the directories of jobs past the limits. Blocks.
+    pub fn save(&self, job_id: &str, files: &[(String, &[u8])], maps: &[DetectorMap]) -> io::Result<()> {
+        let dir = self.dir.join(file_name(job_id));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        for (stem, data) in files {
+            fs::write(dir.join(with_extension(&file_name(stem), data)), data)?;
+        }
+        for map in maps {
+            // Maps of pages, files and batch images are prefixed with what sets them apart, e.g. `page-2-map-ela.png`
+            let image = map.job_id.strip_prefix(job_id).unwrap_or(&map.job_id).trim();
+            let prefix = if image.is_empty() { String::new() } else { format!("{}-", image) };
+            fs::write(dir.join(file_name(&format!("{}map-{}.png", prefix, map.detector))), map_png(&map.heatmap)?)?;
+        }
+        info!("{}: Saved debug artifacts to {}", job_id, dir.display());
+        self.prune();
+        Ok(())
+    }
+
+    fn prune(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to list debug artifacts in {}: {}", self.dir.display(), err);
+                return;
+            }
+        };
+        let mut jobs: Vec<(SystemTime, PathBuf)> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        // Newest first
+        jobs.sort_by(|a, b| b.cmp(a));
+        for (index, (modified, path)) in jobs.into_iter().enumerate() {
+            let expired = modified.elapsed().is_ok_and(|age| age > self.max_age);
+            if index >= self.max_jobs || expired {
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    error!("Failed to remove debug artifacts {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use fraud_core::{hex, timestamp};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Entries are far shorter, the last one is found within this much of the end of the log
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// The verdict of every job, appended to a file one JSON line at a time and
+/// synced before the result is posted. When chained every line has the
+/// SHA-256 of the line before it, so editing or removing lines breaks the
+/// chain from there on.
+pub struct AuditLog {
+    file: Mutex<File>,
+    // The hash of the last line, with chaining enabled
+    chain: Option<Mutex<String>>,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    job_id: &'a str,
+    /// None when the job failed before its input was read.
+    input_sha256: Option<&'a str>,
+    verdict: &'a str,
+    regions: usize,
+    /// Left out unless chained, empty for the first line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prev_sha256: Option<&'a str>,
+}
+
+/// Hex encoded.
+pub fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+// Without its newline, or empty when there is none yet. The tail may start within a character of an earlier line,
+// only the last one is decoded.
+fn last_line(file: &mut File) -> io::Result<String> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let end = tail.iter().rposition(|&byte| byte != b'\n').map_or(0, |last| last + 1);
+    let start = tail[..end].iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+    String::from_utf8(tail[start..end].to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+impl AuditLog {
+    /// Appends to `path`, or to `audit.jsonl` in it when it is a directory,
+    /// continuing the chain of an existing log.
+    pub fn open(path: PathBuf, chained: bool) -> io::Result<AuditLog> {
+        let path = if path.is_dir() { path.join("audit.jsonl") } else { path };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let chain = if chained {
+            let last = last_line(&mut file)?;
+            Some(Mutex::new(if last.is_empty() { String::new() } else { sha256(last.as_bytes()) }))
+        } else {
+            None
+        };
+        Ok(AuditLog { file: Mutex::new(file), chain })
+    }
+
+    /// Blocks until the entry is on disk.
+    pub fn record(&self, job_id: &str, input_sha256: Option<&str>, verdict: &str, regions: usize) -> io::Result<()> {
+        // Held throughout so lines are chained in the order they are written
+        let mut chain = self.chain.as_ref().map(|chain| chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = Entry {
+            timestamp: timestamp(SystemTime::now()),
+            job_id,
+            input_sha256,
+            verdict,
+            regions,
+            prev_sha256: chain.as_deref().map(String::as_str),
+        };
+        let line = serde_json::to_string(&entry)?;
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        file.sync_data()?;
+        if let Some(chain) = chain.as_mut() {
+            **chain = sha256(line.as_bytes());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use log::{error, info};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// The module auth token, read again from its file when a server refuses it
+/// so rotated tokens are picked up without a restart.
+#[derive(Clone)]
+pub struct AuthToken(Arc<Inner>);
+
+struct Inner {
+    path: PathBuf,
+    token: RwLock<String>,
+}
+
+impl AuthToken {
+    pub fn load(path: PathBuf) -> io::Result<AuthToken> {
+        let token = RwLock::new(fs::read_to_string(&path)?);
+        Ok(AuthToken(Arc::new(Inner { path, token })))
+    }
+
+    pub fn current(&self) -> String {
+        self.0.token.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Reads the token again after a server answered 401 or 403 to `refused`.
+    /// Returns whether there is a different one to try, which another request
+    /// may have picked up already.
+    pub fn refresh(&self, refused: &str) -> bool {
+        let mut token = self.0.token.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match fs::read_to_string(&self.0.path) {
+            Ok(read) if read != *token => {
+                info!("Reloaded module auth token from {}", self.0.path.display());
+                *token = read;
+            }
+            Ok(_) => {}
+            Err(err) => error!("Failed to reload module auth token from {}: {}", self.0.path.display(), err),
+        }
+        *token != refused
+    }
+}
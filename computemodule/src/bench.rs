@@ -0,0 +1,48 @@
+use crate::config::Config;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize)]
+struct BenchReport<'a> {
+    path: &'a Path,
+    iterations: u32,
+    result: String,
+    min_ms: f64,
+    mean_ms: f64,
+    max_ms: f64,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Runs the full detection pipeline, including encoding the annotated output,
+/// `iterations` times over a local file and prints timing statistics as JSON.
+pub fn run(config: &Config, path: &Path, iterations: u32) -> Result<(), Box<dyn Error>> {
+    let image_data = fs::read(path)?;
+    let pipeline = config.pipeline()?;
+    let job_id = path.display().to_string();
+
+    let mut timings = Vec::new();
+    let mut result = String::new();
+    for _ in 0..iterations.max(1) {
+        let start = Instant::now();
+        result = pipeline.detect(&job_id, &image_data)?.result;
+        timings.push(start.elapsed());
+    }
+
+    let total: Duration = timings.iter().sum();
+    let report = BenchReport {
+        path,
+        iterations: timings.len() as u32,
+        result,
+        min_ms: millis(*timings.iter().min().unwrap()),
+        mean_ms: millis(total) / timings.len() as f64,
+        max_ms: millis(*timings.iter().max().unwrap()),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use log::{error, info};
+use reqwest::{Certificate, Client, ClientBuilder, Identity};
+use rustls::{ClientConfig, PrivateKey, RootCertStore};
+use rustls_pemfile::Item;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::sleep;
+
+type Build<C> = dyn Fn(Tls) -> Result<C, String> + Send + Sync;
+
+/// The PEM files of the CA certificates clients trust and, for job APIs
+/// requiring mutual TLS, of the certificate and private key they present.
+#[derive(Clone)]
+pub struct TlsPaths {
+    /// A file of any number of certificates or a directory of such files.
+    pub ca: PathBuf,
+    /// Also trust the certificates of the system store, where OpenSSL finds them.
+    pub system_roots: bool,
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+/// The contents of every file of [`TlsPaths`].
+#[derive(Clone, PartialEq, Default)]
+pub struct TlsFiles {
+    ca: Vec<(PathBuf, Vec<u8>)>,
+    // The certificate followed by the key, as reqwest reads them
+    client_cert: Option<Vec<u8>>,
+}
+
+/// Parsed [`TlsFiles`], what clients are built with.
+#[derive(Clone)]
+pub struct Tls {
+    ca: Vec<Certificate>,
+    identity: Option<Identity>,
+    // For clients on rustls directly, which reqwest's certificates can't be handed to
+    files: TlsFiles,
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// The file at `path`, or every file in it but hidden ones by name, like the `..data` links of Kubernetes volumes
+fn read_certs(path: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<(), String> {
+    if !path.is_dir() {
+        files.push((path.to_path_buf(), read(path)?));
+        return Ok(());
+    }
+    let entries = fs::read_dir(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let pem = read(&path)?;
+        files.push((path, pem));
+    }
+    Ok(())
+}
+
+impl TlsPaths {
+    pub fn read(&self) -> Result<TlsFiles, String> {
+        let mut ca = Vec::new();
+        read_certs(&self.ca, &mut ca)?;
+        if self.system_roots {
+            let found = ca.len();
+            // The bundle holds every certificate of the directories
+            let probed = openssl_probe::probe();
+            for path in probed.cert_file.map_or(probed.cert_dir, |file| vec![file]) {
+                read_certs(&path, &mut ca)?;
+            }
+            if ca.len() == found {
+                return Err(String::from("ca_system_roots is set but there is no system store, install ca-certificates"));
+            }
+        }
+        let client_cert = match &self.client_cert {
+            Some((cert, key)) => Some([read(cert)?, b"\n".to_vec(), read(key)?].concat()),
+            None => None,
+        };
+        Ok(TlsFiles { ca, client_cert })
+    }
+}
+
+impl Display for TlsPaths {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.ca.display())?;
+        if self.system_roots {
+            write!(f, ", the system store")?;
+        }
+        match &self.client_cert {
+            Some((cert, key)) => write!(f, ", {} and {}", cert.display(), key.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TlsFiles {
+    /// Files of a directory without certificates are skipped, but there must be one.
+    pub fn parse(&self) -> Result<Tls, String> {
+        let mut ca = Vec::new();
+        for (path, pem) in &self.ca {
+            ca.extend(Certificate::from_pem_bundle(pem).map_err(|err| format!("{}: {}", path.display(), err))?);
+        }
+        if ca.is_empty() {
+            return Err(String::from("no CA certificates found"));
+        }
+        let identity = self.client_cert.as_deref().map(Identity::from_pem).transpose();
+        let identity = identity.map_err(|err| format!("client certificate: {}", err))?;
+        Ok(Tls { ca, identity, files: self.clone() })
+    }
+}
+
+impl Tls {
+    /// Trusting the CA certificates, and presenting the client certificate if any.
+    pub fn apply(self, builder: ClientBuilder) -> ClientBuilder {
+        let builder = self.ca.into_iter().fold(builder, ClientBuilder::add_root_certificate);
+        match self.identity {
+            Some(identity) => builder.identity(identity),
+            None => builder,
+        }
+    }
+
+    /// The same for clients on rustls rather than reqwest, without ALPN protocols set.
+    pub fn rustls_config(&self) -> Result<ClientConfig, String> {
+        let mut roots = RootCertStore::empty();
+        for (path, pem) in &self.files.ca {
+            let certs = rustls_pemfile::certs(&mut &pem[..]).map_err(|err| format!("{}: {}", path.display(), err))?;
+            // The system store may hold certificates rustls can't read, the others are still trusted
+            roots.add_parsable_certificates(&certs);
+        }
+        let builder = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots);
+        let Some(pem) = &self.files.client_cert else {
+            return Ok(builder.with_no_client_auth());
+        };
+        let items = rustls_pemfile::read_all(&mut &pem[..]).map_err(|err| format!("client certificate: {}", err))?;
+        let (mut chain, mut key) = (Vec::new(), None);
+        for item in items {
+            match item {
+                Item::X509Certificate(der) => chain.push(rustls::Certificate(der)),
+                Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => key = key.or(Some(PrivateKey(der))),
+                _ => {}
+            }
+        }
+        let key = key.ok_or("client certificate: no private key found")?;
+        builder.with_client_auth_cert(chain, key).map_err(|err| format!("client certificate: {}", err))
+    }
+}
+
+/// A client built with the configured certificates, rebuilt by [`watch`]
+/// whenever one of them changes. Requests already sent keep the client they
+/// were sent with.
+pub struct ReloadingClient<C = Client>(Arc<Inner<C>>);
+
+struct Inner<C> {
+    client: RwLock<C>,
+    build: Box<Build<C>>,
+}
+
+// Not derived, that would need `C: Clone` for sharing the `Arc`
+impl<C> Clone for ReloadingClient<C> {
+    fn clone(&self) -> Self {
+        ReloadingClient(self.0.clone())
+    }
+}
+
+impl<C: Clone> ReloadingClient<C> {
+    pub fn new<E: Display>(
+        tls: &Tls,
+        build: impl Fn(Tls) -> Result<C, E> + Send + Sync + 'static,
+    ) -> Result<ReloadingClient<C>, E> {
+        let client = RwLock::new(build(tls.clone())?);
+        let build = Box::new(move |tls| build(tls).map_err(|err| err.to_string()));
+        Ok(ReloadingClient(Arc::new(Inner { client, build })))
+    }
+
+    /// The client built for the latest certificates.
+    pub fn current(&self) -> C {
+        self.0.client.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// A [`ReloadingClient`] of any kind, for [`watch`] to rebuild.
+pub trait Reload: Send + Sync {
+    fn rebuild(&self, tls: &Tls) -> Result<(), String>;
+}
+
+impl<C: Send + Sync> Reload for ReloadingClient<C> {
+    fn rebuild(&self, tls: &Tls) -> Result<(), String> {
+        let client = (self.0.build)(tls.clone())?;
+        *self.0.client.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = client;
+        Ok(())
+    }
+}
+
+/// Reads the certificates at `paths` every `interval` and rebuilds `clients`
+/// when any changed, keeping the old ones while a new file is unreadable or
+/// invalid, e.g. while it is half written.
+pub async fn watch(paths: TlsPaths, mut loaded: TlsFiles, interval: Duration, clients: Vec<Box<dyn Reload>>) {
+    // Invalid contents are reported once, not at every check until they are fixed
+    let mut rejected = TlsFiles::default();
+    loop {
+        sleep(interval).await;
+        let files = match paths.read() {
+            Ok(files) if files != loaded && files != rejected => files,
+            Ok(_) => continue,
+            Err(err) => {
+                error!("Failed to read TLS certificate {}", err);
+                continue;
+            }
+        };
+        let rebuilt = files.parse().and_then(|tls| clients.iter().try_for_each(|client| client.rebuild(&tls)));
+        match rebuilt {
+            Ok(()) => {
+                info!("Reloaded TLS certificates {}", paths);
+                loaded = files;
+            }
+            Err(err) => {
+                error!("Failed to reload TLS certificates {}: {}", paths, err);
+                rejected = files;
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::audit;
+use fraud_core::{JobSettings, QueryResult};
+use log::error;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Verdicts of recent jobs, keyed by the SHA-256 of their decoded input and
+/// what they asked for, so identical resubmissions are answered without
+/// analyzing them again. The newest `max_entries` are kept in memory as JSON,
+/// and as `<key>.json` files in `dir` when set to survive restarts. Cached
+/// reports and COCO data name the job that first computed them. Clear `dir`
+/// after changing the detection settings, only the detectors and the version
+/// of the module are part of the key.
+pub struct ResultCache {
+    // Most recently used last
+    entries: Mutex<VecDeque<(String, Vec<u8>)>>,
+    max_entries: usize,
+    dir: Option<PathBuf>,
+    namespace: String,
+}
+
+impl ResultCache {
+    pub fn open(max_entries: usize, dir: Option<PathBuf>, detectors: &[String]) -> io::Result<ResultCache> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)?;
+        }
+        let namespace = format!("{} {}", env!("CARGO_PKG_VERSION"), detectors.join(","));
+        Ok(ResultCache { entries: Mutex::new(VecDeque::new()), max_entries, dir, namespace })
+    }
+
+    /// Hex encoded, differing for every query type and job setting that changes the result.
+    pub fn key(&self, input_sha256: &str, query_type: &str, settings: &JobSettings) -> String {
+        let JobSettings { sensitivity, encoding, annotation, locale, debug, .. } = settings;
+        let asked = format!("{:?} {:?} {:?} {:?} {}", sensitivity, encoding, annotation, locale, debug);
+        audit::sha256(format!("{}\n{}\n{}\n{}", self.namespace, input_sha256, query_type, asked).as_bytes())
+    }
+
+    /// From memory, or else from `dir`. Blocks.
+    pub fn get(&self, key: &str) -> Option<QueryResult> {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let json = match entries.iter().position(|(cached, _)| cached == key) {
+            Some(index) => entries.remove(index)?.1,
+            None => {
+                drop(entries);
+                let json = fs::read(self.dir.as_ref()?.join(format!("{}.json", key))).ok()?;
+                entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                json
+            }
+        };
+        let result = serde_json::from_slice(&json);
+        if result.is_ok() {
+            self.insert(&mut entries, key, json);
+        }
+        result.ok()
+    }
+
+    /// Failing to write to `dir` is only logged. Blocks.
+    pub fn put(&self, key: &str, result: &QueryResult) {
+        let json = match serde_json::to_vec(result) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Failed to cache result: {}", err);
+                return;
+            }
+        };
+        if let Some(dir) = &self.dir {
+            // Renamed into place so readers never see half of it
+            let (tmp, path) = (dir.join(format!("{}.tmp", key)), dir.join(format!("{}.json", key)));
+            if let Err(err) = fs::write(&tmp, &json).and_then(|_| fs::rename(&tmp, &path)) {
+                error!("Failed to cache result in {}: {}", dir.display(), err);
+            }
+            self.prune();
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.insert(&mut entries, key, json);
+    }
+
+    fn insert(&self, entries: &mut VecDeque<(String, Vec<u8>)>, key: &str, json: Vec<u8>) {
+        entries.retain(|(cached, _)| cached != key);
+        entries.push_back((key.to_string(), json));
+        while entries.len() > self.max_entries {
+            entries.pop_front();
+        }
+    }
+
+    // Drops the least recently written files past `max_entries`
+    fn prune(&self) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to list cached results in {}: {}", dir.display(), err);
+                return;
+            }
+        };
+        let mut files: Vec<(SystemTime, PathBuf)> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        // Newest first
+        files.sort_by(|a, b| b.cmp(a));
+        for (_, path) in files.into_iter().skip(self.max_entries) {
+            if let Err(err) = fs::remove_file(&path) {
+                error!("Failed to remove cached result {}: {}", path.display(), err);
+            }
+        }
+    }
+}
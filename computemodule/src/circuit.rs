@@ -0,0 +1,137 @@
+use crate::transport::{BoxFuture, FetchedJob, JobSource, PostError, ResultBody, ResultSink};
+use log::{error, info};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// When a circuit opens and how long it stays open.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitSettings {
+    /// Failures in a row that open the circuit.
+    pub failure_threshold: u32,
+    /// How long the circuit stays open at first, doubled whenever a probe fails.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+/// Failures of one upstream endpoint. While open requests are held back,
+/// except for one probe once the backoff passed, which closes the circuit
+/// when it succeeds and opens it for longer when not.
+pub struct Circuit {
+    pub name: &'static str,
+    settings: CircuitSettings,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    backoff: Duration,
+    // Set while open, when the next probe may be sent
+    probe_at: Option<Instant>,
+}
+
+impl Circuit {
+    pub fn new(name: &'static str, settings: CircuitSettings) -> Circuit {
+        Circuit { name, settings, state: Mutex::default() }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state().probe_at.is_some()
+    }
+
+    // Whether a request may be sent now, or when it may be. Taking the probe
+    // pushes the next one back, so only one request at a time tests the endpoint.
+    fn try_request(&self) -> Result<(), Instant> {
+        let mut state = self.state();
+        match state.probe_at {
+            Some(probe_at) if Instant::now() < probe_at => Err(probe_at),
+            Some(_) => {
+                state.probe_at = Some(Instant::now() + state.backoff);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut state = self.state();
+        if state.probe_at.is_some() {
+            info!("{} recovered, closing its circuit", self.name);
+        }
+        *state = State::default();
+    }
+
+    fn failed(&self) {
+        let mut state = self.state();
+        state.failures += 1;
+        if state.failures < self.settings.failure_threshold {
+            return;
+        }
+        state.backoff = match state.probe_at {
+            Some(_) => (state.backoff * 2).min(self.settings.max_backoff),
+            None => self.settings.initial_backoff,
+        };
+        state.probe_at = Some(Instant::now() + state.backoff);
+        error!("{} failed {} times in a row, holding off for {:?}", self.name, state.failures, state.backoff);
+    }
+}
+
+/// Jobs from `jobs`, waited for without asking while `circuit` is open.
+pub struct GuardedJobs {
+    pub jobs: Arc<dyn JobSource>,
+    pub circuit: Arc<Circuit>,
+}
+
+impl JobSource for GuardedJobs {
+    fn next_job(&self) -> BoxFuture<'_, Result<Option<FetchedJob>, String>> {
+        Box::pin(async move {
+            while let Err(probe_at) = self.circuit.try_request() {
+                sleep_until(probe_at).await;
+            }
+            let job = self.jobs.next_job().await;
+            match &job {
+                Ok(_) => self.circuit.succeeded(),
+                Err(_) => self.circuit.failed(),
+            }
+            job
+        })
+    }
+
+    fn is_pushed(&self) -> bool {
+        self.jobs.is_pushed()
+    }
+}
+
+/// Results posted to `results`, failing right away while `circuit` is open.
+pub struct GuardedResults {
+    pub results: Arc<dyn ResultSink>,
+    pub circuit: Arc<Circuit>,
+}
+
+impl ResultSink for GuardedResults {
+    fn post_result<'a>(
+        &'a self,
+        job_id: &'a str,
+        idempotency_key: &'a str,
+        correlation_id: Option<&'a str>,
+        result: &'a ResultBody,
+    ) -> BoxFuture<'a, Result<(), PostError>> {
+        Box::pin(async move {
+            if self.circuit.try_request().is_err() {
+                return Err(PostError::Transient(format!("Not posting while {} is failing", self.circuit.name)));
+            }
+            let posted = self.results.post_result(job_id, idempotency_key, correlation_id, result).await;
+            // A refused result still means the server is up
+            match &posted {
+                Err(PostError::Transient(_)) => self.circuit.failed(),
+                Ok(()) | Err(PostError::Rejected(_)) => self.circuit.succeeded(),
+            }
+            posted
+        })
+    }
+}
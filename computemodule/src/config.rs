@@ -0,0 +1,687 @@
+use crate::ca::TlsPaths;
+use crate::circuit::CircuitSettings;
+use crate::s3::S3;
+use crate::signing::{Algorithm, KeySource, SigningConfig};
+use crate::transport::{Timeouts, Transport};
+use fraud_core::{
+    detector_by_name, Annotation, Detector, Fusion, InputLimits, Locale, OutputEncoding, OutputFormat, Overlay, Pipeline,
+    PrnuDetector, Sensitivity, SvgOverlay, BUFFERS, DEFAULT_BUFFER_POOL_BYTES, DEFAULT_MAX_DIMENSION,
+    DEFAULT_MAX_INPUT_BYTES, DEFAULT_MAX_PIXELS, DEFAULT_MERGE_GAP, DEFAULT_NMS_IOU, DEFAULT_OUTPUT_QUALITY,
+    DEFAULT_THUMBNAIL_SIZE, DEFAULT_VIDEO_SAMPLE_RATE, DETECTOR_NAMES,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Detection settings shared by every mode of the binary.
+pub struct Config {
+    pub detectors: Vec<String>,
+    pub onnx_model_path: Option<PathBuf>,
+    pub prnu_fingerprint_dir: Option<PathBuf>,
+    pub fusion: Fusion,
+    pub sensitivity: Sensitivity,
+    /// Forged regions at most this many pixels apart are merged.
+    pub merge_gap: u32,
+    /// Minimum intersection over union for one detector's region to duplicate another's.
+    pub nms_iou: f64,
+    /// Return every frame of animated inputs annotated as an animated GIF.
+    pub annotate_animations: bool,
+    /// Frames per second analyzed of video inputs.
+    pub video_sample_rate: f64,
+    /// Boxes or a heatmap drawn on annotated images, or regions redacted.
+    pub overlay: Overlay,
+    /// Return regions as an SVG layer next to or instead of the annotated image.
+    pub svg_overlay: SvgOverlay,
+    /// Also return regions in COCO format.
+    pub coco_export: bool,
+    /// Format and quality of annotated images, or whether to skip them.
+    pub output_encoding: OutputEncoding,
+    /// Also return a PDF forensic report of every job.
+    pub forensic_report: bool,
+    /// Stamp the verdict onto annotated images.
+    pub banner: bool,
+    /// Color, thickness and style of region outlines, and whether they are labeled.
+    pub annotation: Annotation,
+    /// Longest side of the thumbnail returned with every job, none when disabled.
+    pub thumbnail_size: Option<u32>,
+    /// Also return every region cut from the input.
+    pub region_crops: bool,
+    /// Language of result texts.
+    pub locale: Locale,
+    /// Largest input, and image within it, that is analyzed.
+    pub input_limits: InputLimits,
+    /// Run the detectors of an image concurrently, on as many threads as `RAYON_NUM_THREADS` allows.
+    pub parallel_detectors: bool,
+    /// Most capacity kept of buffers finished jobs leave for the next ones, 0 to never reuse them.
+    pub buffer_pool_max_bytes: usize,
+    /// Images over this many megapixels are detected on a copy downscaled to it, trading compression traces for
+    /// speed. Never when unset.
+    pub downscale_max_megapixels: Option<f64>,
+    /// Answer clean without running the detectors for JPEGs cheap checks find untouched, off when every image must
+    /// be fully analyzed. Those images bypass every detector, on by default only when the detectors are ones the
+    /// checks stand in for, `zero` and `metadata`, not e.g. `prnu`, `onnx`, `ela` or `double_jpeg`.
+    pub prescreen: bool,
+    /// CUDA device the ONNX model and the noise filter run on, the CPU when unset or unusable.
+    pub gpu_device_id: Option<i32>,
+}
+
+/// Settings only needed when polling the compute module job API.
+pub struct WorkerConfig {
+    /// The CA certificates in the file or directory at `default_ca_path`, also the system's with
+    /// `ca_system_roots`, and the client certificate and key at `client_cert_path` and `client_key_path` for job
+    /// APIs requiring mutual TLS.
+    pub tls: TlsPaths,
+    /// How often the certificates are checked for changes, which rebuild the HTTP clients.
+    pub ca_reload_interval: Duration,
+    pub module_auth_token_path: PathBuf,
+    pub job_api: JobApi,
+    /// Gzip jobs and results sent over REST, which the job API must accept.
+    pub compression: bool,
+    /// Bounds connecting to the job API and every other server the worker talks to.
+    pub connect_timeout: Duration,
+    pub get_job_timeouts: Timeouts,
+    /// Uploading the result is bounded by its request timeout, the read timeout starting once it is sent.
+    pub post_result_timeouts: Timeouts,
+    /// When fetching jobs or posting results is held off after failing.
+    pub circuit_breaker: CircuitSettings,
+    /// Where `GET /healthz`, `/readyz` and `/health` are served, if anywhere.
+    pub health_listen_addr: Option<SocketAddr>,
+    /// How long without a successful poll `/healthz` tolerates while the worker has nothing else to do.
+    pub health_max_poll_age: Duration,
+    /// How long to wait before asking again when there was no job, doubled
+    /// while there is none up to `poll_max_interval`.
+    pub poll_interval: Duration,
+    pub poll_max_interval: Duration,
+    /// How long jobs are polled for after the job socket dropped before reconnecting is tried.
+    pub job_socket_reconnect_interval: Duration,
+    pub worker_concurrency: usize,
+    /// Jobs fetched ahead while every detection worker is busy, so the next one is there when one frees up.
+    pub job_prefetch: usize,
+    /// Times a result is posted again after failing to post it.
+    pub post_result_retries: u32,
+    /// Failed attempts at a job after which redeliveries of it are answered with a dead-letter result.
+    pub max_job_failures: u32,
+    /// Where results that failed to post after every retry are kept until they can be.
+    pub result_queue_dir: PathBuf,
+    pub result_queue_interval: Duration,
+    /// Images of a batch job analyzed at once.
+    pub batch_concurrency: usize,
+    pub shutdown_drain_timeout: Duration,
+    /// How long a job may take, from fetching its image to uploading the annotated one. Detectors running
+    /// past it are not interrupted, they only keep the next one from starting.
+    pub job_timeout: Duration,
+    /// Polled with the job ID appended for whether the caller gave up on a job, none to never cancel jobs.
+    pub cancel_job_uri: Option<String>,
+    pub cancel_poll_interval: Duration,
+    /// Where the progress of running jobs is posted to with the job ID appended, none to not report it.
+    pub progress_uri: Option<String>,
+    pub progress_interval: Duration,
+    /// Largest image jobs may reference by `img_url`.
+    pub fetch_max_bytes: usize,
+    pub fetch_timeout: Duration,
+    /// Only configured when credentials are, `s3://` URLs are rejected otherwise.
+    pub s3: Option<S3>,
+    /// OTLP/HTTP collector the spans of jobs are exported to, none to not trace them.
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of the exported spans.
+    pub otlp_service_name: String,
+    pub otlp_export_interval: Duration,
+    /// Add the time spent fetching, decoding, detecting, annotating, encoding and uploading to results.
+    pub stage_timings: bool,
+    /// File, or directory to keep `audit.jsonl` in, the verdict of every job is appended to, none to not keep one.
+    pub audit_log_path: Option<PathBuf>,
+    /// Have every audit log line carry the SHA-256 of the one before it.
+    pub audit_log_hash_chain: bool,
+    /// Where the input, detector maps and annotated output of every job are saved to, none to not save them.
+    pub debug_artifact_dir: Option<PathBuf>,
+    /// Jobs whose debug artifacts are kept, older ones are removed.
+    pub debug_artifact_max_jobs: usize,
+    pub debug_artifact_max_age: Duration,
+    /// Verdicts of recent single image jobs kept for answering resubmissions of the same image, 0 to not cache them.
+    pub result_cache_max_entries: usize,
+    /// Where cached verdicts are kept across restarts, none to only keep them in memory.
+    pub result_cache_dir: Option<PathBuf>,
+    /// How often the results of the jobs of the last hour are logged.
+    pub verdict_summary_interval: Duration,
+    /// The key the verdict of every result is signed with, none to post them unsigned.
+    pub result_signing: Option<SigningConfig>,
+}
+
+/// Where jobs are fetched from and results posted to, picked by `transport`.
+pub enum JobApi {
+    Rest {
+        get_job_uri: String,
+        post_result_uri: String,
+        /// Jobs are pushed over this WebSocket rather than polled for while it is connected.
+        job_socket_uri: Option<String>,
+    },
+    Grpc { uri: String },
+}
+
+/// Settings for the standalone HTTP server mode.
+pub struct ServerConfig {
+    pub listen_addr: SocketAddr,
+    pub max_body_bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Values given on the command line, taking precedence over env vars and the
+/// config file.
+#[derive(Default)]
+pub struct Overrides {
+    pub config_path: Option<PathBuf>,
+    values: HashMap<&'static str, String>,
+}
+
+impl Overrides {
+    pub fn set<T: ToString>(&mut self, key: &'static str, value: Option<T>) {
+        if let Some(value) = value {
+            self.values.insert(key, value.to_string());
+        }
+    }
+}
+
+// Settings flattened into `section.key` strings. Every key can be overridden
+// by the env var named after it, e.g. `get_job_uri` by `GET_JOB_URI`.
+pub struct Settings<'a> {
+    overrides: &'a Overrides,
+    values: HashMap<String, String>,
+    problems: Vec<String>,
+}
+
+fn env_name(key: &str) -> String {
+    key.replace('.', "_").to_uppercase()
+}
+
+fn flatten(prefix: &str, value: &Value, values: &mut HashMap<String, String>) {
+    let key = |k: &str| if prefix.is_empty() { k.to_string() } else { format!("{}.{}", prefix, k) };
+    match value {
+        Value::Object(map) => map.iter().for_each(|(k, v)| flatten(&key(k), v, values)),
+        Value::Array(items) => {
+            let joined = items.iter().map(|item| match item {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+            values.insert(prefix.to_string(), joined.collect::<Vec<_>>().join(","));
+        }
+        Value::String(s) => {
+            values.insert(prefix.to_string(), s.clone());
+        }
+        Value::Null => {}
+        other => {
+            values.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<Value, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("CONFIG_PATH {}: {}", path.display(), err))?;
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str::<Value>(&contents).map_err(|err| err.to_string()),
+        Some("toml") => toml::from_str::<toml::Value>(&contents)
+            .map_err(|err| err.to_string())
+            .and_then(|value| serde_json::to_value(value).map_err(|err| err.to_string())),
+        _ => Err(String::from("unsupported extension, expected .toml, .yaml or .yml")),
+    };
+    parsed.map_err(|err| format!("CONFIG_PATH {}: {}", path.display(), err))
+}
+
+impl Settings<'_> {
+    fn load(overrides: &Overrides) -> Settings<'_> {
+        let mut settings = Settings { overrides, values: HashMap::new(), problems: Vec::new() };
+        let config_path = overrides.config_path.clone().or_else(|| env::var_os("CONFIG_PATH").map(PathBuf::from));
+        if let Some(path) = config_path {
+            match read_config_file(&path) {
+                Ok(value) => flatten("", &value, &mut settings.values),
+                Err(problem) => settings.problems.push(problem),
+            }
+        }
+        settings
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.overrides.values.get(key).cloned()
+            .or_else(|| env::var(env_name(key)).ok())
+            .or_else(|| self.values.get(key).cloned())
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.get(key).unwrap_or_else(|| {
+            self.problems.push(format!("{} ({}) is not set", key, env_name(key)));
+            String::new()
+        })
+    }
+
+    fn parse_or<T: FromStr>(&mut self, key: &str, default: T) -> T
+    where
+        T::Err: fmt::Display,
+    {
+        match self.get(key) {
+            Some(raw) => raw.trim().parse().unwrap_or_else(|err| {
+                self.problems.push(format!("{} ({}) has invalid value {:?}: {}", key, env_name(key), raw, err));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    fn list(&mut self, key: &str, default: &[&str]) -> Vec<String> {
+        match self.get(key) {
+            Some(raw) => raw.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect(),
+            None => default.iter().map(|item| item.to_string()).collect(),
+        }
+    }
+
+    fn check(&mut self, ok: bool, problem: &str) {
+        if !ok {
+            self.problems.push(problem.to_string());
+        }
+    }
+}
+
+/// Loads the config file pointed to by `--config` or `CONFIG_PATH` (if any),
+/// applies env var and command line overrides and reads it with `read`,
+/// reporting every missing or invalid field at once.
+pub fn load<T>(overrides: &Overrides, read: impl FnOnce(&mut Settings) -> T) -> Result<T, ConfigError> {
+    let mut settings = Settings::load(overrides);
+    let config = read(&mut settings);
+
+    if settings.problems.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError { problems: settings.problems })
+    }
+}
+
+// Detectors that need more than a name to be built, see Config::detector
+const MODEL_DETECTORS: &[&str] = &["onnx", "prnu"];
+// Detectors the grid and metadata checks of the prescreen stand in for
+const PRESCREENED_DETECTORS: &[&str] = &["zero", "metadata"];
+
+impl Config {
+    pub fn read(settings: &mut Settings) -> Config {
+        let detectors = settings.list("detectors", &["zero"]);
+        let prescreened = detectors.iter().all(|name| PRESCREENED_DETECTORS.contains(&name.as_str()));
+        let config = Config {
+            detectors,
+            onnx_model_path: settings.get("onnx.model_path").map(PathBuf::from),
+            prnu_fingerprint_dir: settings.get("prnu.fingerprint_dir").map(PathBuf::from),
+            fusion: Config::read_fusion(settings),
+            sensitivity: Config::read_sensitivity(settings),
+            merge_gap: settings.parse_or("regions.merge_gap", DEFAULT_MERGE_GAP),
+            nms_iou: settings.parse_or("regions.nms_iou", DEFAULT_NMS_IOU),
+            annotate_animations: settings.parse_or("animation.annotate", false),
+            video_sample_rate: settings.parse_or("video.sample_rate", DEFAULT_VIDEO_SAMPLE_RATE),
+            overlay: settings.parse_or("overlay", Overlay::default()),
+            svg_overlay: settings.parse_or("svg_overlay", SvgOverlay::default()),
+            coco_export: settings.parse_or("coco_export", false),
+            output_encoding: OutputEncoding {
+                format: settings.parse_or("output.format", OutputFormat::default()),
+                quality: settings.parse_or("output.quality", DEFAULT_OUTPUT_QUALITY),
+                regions_only: settings.parse_or("output.regions_only", false),
+                max_size: settings.get("output.max_size").map(|_| settings.parse_or("output.max_size", u32::MAX)),
+            },
+            forensic_report: settings.parse_or("forensic_report", false),
+            banner: settings.parse_or("banner", false),
+            annotation: Config::read_annotation(settings),
+            thumbnail_size: settings
+                .parse_or("thumbnail.enabled", false)
+                .then(|| settings.parse_or("thumbnail.size", DEFAULT_THUMBNAIL_SIZE)),
+            region_crops: settings.parse_or("region_crops", false),
+            locale: settings.parse_or("locale", Locale::default()),
+            input_limits: InputLimits {
+                max_bytes: settings.parse_or("input.max_bytes", DEFAULT_MAX_INPUT_BYTES),
+                max_dimension: settings.parse_or("input.max_dimension", DEFAULT_MAX_DIMENSION),
+                max_pixels: settings.parse_or("input.max_pixels", DEFAULT_MAX_PIXELS),
+                max_memory_bytes: settings
+                    .get("input.max_memory_bytes")
+                    .map(|_| settings.parse_or("input.max_memory_bytes", u64::MAX)),
+            },
+            parallel_detectors: settings.parse_or("parallel_detectors", true),
+            buffer_pool_max_bytes: settings.parse_or("buffer_pool.max_bytes", DEFAULT_BUFFER_POOL_BYTES),
+            downscale_max_megapixels: settings
+                .get("downscale.max_megapixels")
+                .map(|_| settings.parse_or("downscale.max_megapixels", f64::MAX)),
+            prescreen: settings.parse_or("prescreen", prescreened),
+            gpu_device_id: settings.get("gpu.device_id").map(|_| settings.parse_or("gpu.device_id", 0)),
+        };
+        if let Err(problem) = config.output_encoding.validate() {
+            settings.check(false, &format!("output.{}", problem));
+        }
+        settings.check(config.thumbnail_size != Some(0), "thumbnail.size must be above 0");
+        let limits = config.input_limits;
+        settings.check(limits.max_bytes > 0, "input.max_bytes must be above 0");
+        settings.check(limits.max_dimension > 0, "input.max_dimension must be above 0");
+        settings.check(limits.max_pixels > 0, "input.max_pixels must be above 0");
+        settings.check(limits.max_memory_bytes != Some(0), "input.max_memory_bytes must be above 0");
+        let problem = "downscale.max_megapixels must be above 0";
+        settings.check(config.downscale_max_megapixels.is_none_or(|megapixels| megapixels > 0.0), problem);
+        if let Some(device_id) = config.gpu_device_id {
+            settings.check(cfg!(feature = "cuda"), "gpu.device_id needs a build with the cuda feature");
+            settings.check(device_id >= 0, "gpu.device_id must be at least 0");
+        }
+        settings.check(config.video_sample_rate > 0.0, "video.sample_rate must be above 0");
+        settings.check(0.0 < config.nms_iou && config.nms_iou <= 1.0, "regions.nms_iou must be above 0 and at most 1");
+        settings.check(!config.detectors.is_empty(), "detectors must name at least one detector");
+        for name in &config.detectors {
+            let known = DETECTOR_NAMES.contains(&name.as_str()) || MODEL_DETECTORS.contains(&name.as_str());
+            let expected = [DETECTOR_NAMES, MODEL_DETECTORS].concat().join(", ");
+            settings.check(known, &format!("unknown detector {:?}, expected one of {}", name, expected));
+        }
+        if config.detectors.iter().any(|name| name == "onnx") {
+            settings.check(cfg!(feature = "onnx"), "the onnx detector needs a build with the onnx feature");
+            settings.check(config.onnx_model_path.is_some(), "onnx.model_path (ONNX_MODEL_PATH) is not set");
+        }
+        if config.detectors.iter().any(|name| name == "prnu") {
+            let problem = "prnu.fingerprint_dir (PRNU_FINGERPRINT_DIR) is not set";
+            settings.check(config.prnu_fingerprint_dir.is_some(), problem);
+        }
+        config
+    }
+
+    // Weights are read per known detector as `fusion.weights.<name>`
+    fn read_fusion(settings: &mut Settings) -> Fusion {
+        let mut weights = HashMap::new();
+        for name in DETECTOR_NAMES.iter().chain(MODEL_DETECTORS) {
+            let key = format!("fusion.weights.{}", name);
+            if settings.get(&key).is_some() {
+                let weight = settings.parse_or(&key, 1.0);
+                settings.check((0.0..=1.0).contains(&weight), &format!("{} must be between 0 and 1", key));
+                weights.insert(name.to_string(), weight);
+            }
+        }
+        Fusion { weights }
+    }
+
+    fn read_annotation(settings: &mut Settings) -> Annotation {
+        let default = Annotation::default();
+        let annotation = Annotation {
+            color: settings.parse_or("annotation.color", default.color),
+            thickness: settings.parse_or("annotation.thickness", default.thickness),
+            style: settings.parse_or("annotation.style", default.style),
+            labels: settings.parse_or("annotation.labels", default.labels),
+            fill: settings.parse_or("annotation.fill", default.fill),
+            auto_scale: settings.parse_or("annotation.auto_scale", default.auto_scale),
+        };
+        if let Err(problem) = annotation.validate() {
+            settings.check(false, &format!("annotation.{}", problem));
+        }
+        annotation
+    }
+
+    fn read_sensitivity(settings: &mut Settings) -> Sensitivity {
+        let default = Sensitivity::default();
+        let sensitivity = Sensitivity {
+            min_region_confidence: settings.parse_or("sensitivity.min_region_confidence", default.min_region_confidence),
+            min_region_area: settings.parse_or("sensitivity.min_region_area", default.min_region_area),
+            suspicious_threshold: settings.parse_or("sensitivity.suspicious_threshold", default.suspicious_threshold),
+            edited_threshold: settings.parse_or("sensitivity.edited_threshold", default.edited_threshold),
+        };
+        if let Err(problem) = sensitivity.validate() {
+            settings.check(false, &format!("sensitivity: {}", problem));
+        }
+        sensitivity
+    }
+
+    #[cfg(feature = "onnx")]
+    fn onnx_detector(&self) -> Result<Box<dyn Detector>, String> {
+        let path = self.onnx_model_path.as_deref().expect("Model path is validated by Config::read");
+        fraud_core::OnnxDetector::load(path)
+            .map(|detector| Box::new(detector) as Box<dyn Detector>)
+            .map_err(|err| format!("failed to load ONNX model {}: {}", path.display(), err))
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn onnx_detector(&self) -> Result<Box<dyn Detector>, String> {
+        unreachable!("The onnx feature is validated by Config::read")
+    }
+
+    fn prnu_detector(&self) -> Result<Box<dyn Detector>, String> {
+        let dir = self.prnu_fingerprint_dir.as_deref().expect("Fingerprint dir is validated by Config::read");
+        PrnuDetector::load_dir(dir)
+            .map(|detector| Box::new(detector) as Box<dyn Detector>)
+            .map_err(|err| format!("failed to load PRNU fingerprints from {}: {}", dir.display(), err))
+    }
+
+    fn detector(&self, name: &str) -> Result<Box<dyn Detector>, String> {
+        match name {
+            "onnx" => self.onnx_detector(),
+            "prnu" => self.prnu_detector(),
+            name => Ok(detector_by_name(name).expect("Detector names are validated by Config::read")),
+        }
+    }
+
+    /// Builds the configured detectors, failing if a model can't be loaded.
+    pub fn pipeline(&self) -> Result<Pipeline, ConfigError> {
+        // Before the model is loaded onto it
+        #[cfg(feature = "cuda")]
+        if let Some(device_id) = self.gpu_device_id {
+            fraud_core::use_gpu(device_id);
+        }
+        let mut detectors = Vec::new();
+        let mut problems = Vec::new();
+        for name in &self.detectors {
+            match self.detector(name) {
+                Ok(detector) => detectors.push(detector),
+                Err(problem) => problems.push(problem),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+        // Shared by every pipeline of the process
+        BUFFERS.set_max_bytes(self.buffer_pool_max_bytes);
+        let pipeline = Pipeline::new(detectors)
+            .with_fusion(self.fusion.clone())
+            .with_sensitivity(self.sensitivity)
+            .with_merge_gap(self.merge_gap)
+            .with_nms_iou(self.nms_iou)
+            .with_annotated_animations(self.annotate_animations)
+            .with_video_sample_rate(self.video_sample_rate)
+            .with_overlay(self.overlay)
+            .with_svg_overlay(self.svg_overlay)
+            .with_coco_export(self.coco_export)
+            .with_output_encoding(self.output_encoding)
+            .with_annotation(self.annotation)
+            .with_forensic_report(self.forensic_report)
+            .with_banner(self.banner)
+            .with_thumbnail(self.thumbnail_size)
+            .with_region_crops(self.region_crops)
+            .with_locale(self.locale)
+            .with_input_limits(self.input_limits)
+            .with_parallel_detectors(self.parallel_detectors)
+            .with_downscale(self.downscale_max_megapixels.map(|megapixels| (megapixels * 1e6) as u64))
+            .with_prescreen(self.prescreen);
+        Ok(pipeline)
+    }
+}
+
+impl WorkerConfig {
+    pub fn read(settings: &mut Settings) -> WorkerConfig {
+        let config = WorkerConfig {
+            tls: TlsPaths {
+                ca: PathBuf::from(settings.required("default_ca_path")),
+                system_roots: settings.parse_or("ca_system_roots", false),
+                client_cert: match (settings.get("client_cert_path"), settings.get("client_key_path")) {
+                    (Some(cert), Some(key)) => Some((PathBuf::from(cert), PathBuf::from(key))),
+                    _ => None,
+                },
+            },
+            ca_reload_interval: Duration::from_secs(settings.parse_or("ca_reload_interval_secs", 60)),
+            module_auth_token_path: PathBuf::from(settings.required("module_auth_token")),
+            job_api: match settings.parse_or("transport", Transport::Rest) {
+                Transport::Rest => JobApi::Rest {
+                    get_job_uri: settings.required("get_job_uri"),
+                    post_result_uri: settings.required("post_result_uri"),
+                    job_socket_uri: settings.get("job_socket_uri"),
+                },
+                Transport::Grpc => JobApi::Grpc { uri: settings.required("grpc_uri") },
+            },
+            compression: settings.parse_or("compression", false),
+            connect_timeout: Duration::from_secs(settings.parse_or("connect_timeout_secs", 10)),
+            get_job_timeouts: WorkerConfig::read_timeouts(settings, "get_job", 60, 120),
+            post_result_timeouts: WorkerConfig::read_timeouts(settings, "post_result", 60, 300),
+            circuit_breaker: CircuitSettings {
+                failure_threshold: settings.parse_or("circuit_breaker.failure_threshold", 5),
+                initial_backoff: Duration::from_secs(settings.parse_or("circuit_breaker.initial_backoff_secs", 5)),
+                max_backoff: Duration::from_secs(settings.parse_or("circuit_breaker.max_backoff_secs", 300)),
+            },
+            health_listen_addr: settings
+                .get("health_listen_addr")
+                .map(|_| settings.parse_or("health_listen_addr", SocketAddr::from(([0, 0, 0, 0], 8081)))),
+            health_max_poll_age: Duration::from_secs(settings.parse_or("health_max_poll_age_secs", 300)),
+            poll_interval: Duration::from_millis(settings.parse_or("poll_interval_ms", 500)),
+            poll_max_interval: Duration::from_millis(settings.parse_or("poll_max_interval_ms", 10_000)),
+            job_socket_reconnect_interval: Duration::from_secs(
+                settings.parse_or("job_socket_reconnect_interval_secs", 30),
+            ),
+            worker_concurrency: settings.parse_or("worker_concurrency", 1),
+            job_prefetch: settings.parse_or("job_prefetch", 1),
+            post_result_retries: settings.parse_or("post_result_retries", 3),
+            max_job_failures: settings.parse_or("max_job_failures", 3),
+            result_queue_dir: settings.parse_or("result_queue_dir", env::temp_dir().join("computemodule-results")),
+            result_queue_interval: Duration::from_secs(settings.parse_or("result_queue_interval_secs", 60)),
+            batch_concurrency: settings.parse_or("batch_concurrency", 1),
+            shutdown_drain_timeout: Duration::from_secs(settings.parse_or("shutdown_drain_timeout_secs", 30)),
+            job_timeout: Duration::from_secs(settings.parse_or("job_timeout_secs", 600)),
+            cancel_job_uri: settings.get("cancel_job_uri"),
+            cancel_poll_interval: Duration::from_secs(settings.parse_or("cancel_poll_interval_secs", 5)),
+            progress_uri: settings.get("progress_uri"),
+            progress_interval: Duration::from_secs(settings.parse_or("progress_interval_secs", 10)),
+            fetch_max_bytes: settings.parse_or("fetch.max_bytes", 50 * 1024 * 1024),
+            fetch_timeout: Duration::from_secs(settings.parse_or("fetch.timeout_secs", 30)),
+            s3: WorkerConfig::read_s3(settings),
+            otlp_endpoint: settings.get("otlp.endpoint").map(|endpoint| endpoint.trim_end_matches('/').to_string()),
+            otlp_service_name: settings.get("otlp.service_name").unwrap_or_else(|| String::from("computemodule")),
+            otlp_export_interval: Duration::from_secs(settings.parse_or("otlp.export_interval_secs", 5)),
+            stage_timings: settings.parse_or("stage_timings", false),
+            audit_log_path: settings.get("audit_log.path").map(PathBuf::from),
+            audit_log_hash_chain: settings.parse_or("audit_log.hash_chain", false),
+            debug_artifact_dir: settings.get("debug_artifact_dir").map(PathBuf::from),
+            debug_artifact_max_jobs: settings.parse_or("debug_artifact_max_jobs", 100),
+            debug_artifact_max_age: Duration::from_secs(settings.parse_or("debug_artifact_max_age_secs", 7 * 24 * 3600)),
+            result_cache_max_entries: settings.parse_or("result_cache.max_entries", 0),
+            result_cache_dir: settings.get("result_cache.dir").map(PathBuf::from),
+            verdict_summary_interval: Duration::from_secs(settings.parse_or("verdict_summary_interval_secs", 300)),
+            result_signing: WorkerConfig::read_result_signing(settings),
+        };
+        settings.check(!config.connect_timeout.is_zero(), "connect_timeout_secs must be at least 1");
+        let breaker = config.circuit_breaker;
+        settings.check(breaker.failure_threshold > 0, "circuit_breaker.failure_threshold must be at least 1");
+        settings.check(!breaker.initial_backoff.is_zero(), "circuit_breaker.initial_backoff_secs must be at least 1");
+        let problem = "circuit_breaker.max_backoff_secs must be at least circuit_breaker.initial_backoff_secs";
+        settings.check(breaker.max_backoff >= breaker.initial_backoff, problem);
+        settings.check(!config.health_max_poll_age.is_zero(), "health_max_poll_age_secs must be at least 1");
+        settings.check(!config.ca_reload_interval.is_zero(), "ca_reload_interval_secs must be at least 1");
+        let client_cert = [settings.get("client_cert_path"), settings.get("client_key_path")];
+        let problem = "client_cert_path and client_key_path must be set together";
+        settings.check(client_cert[0].is_some() == client_cert[1].is_some(), problem);
+        let problem = "poll_max_interval_ms must be at least poll_interval_ms";
+        settings.check(config.poll_max_interval >= config.poll_interval, problem);
+        settings.check(config.worker_concurrency > 0, "worker_concurrency must be at least 1");
+        settings.check(config.max_job_failures > 0, "max_job_failures must be at least 1");
+        settings.check(!config.job_timeout.is_zero(), "job_timeout_secs must be at least 1");
+        settings.check(config.batch_concurrency > 0, "batch_concurrency must be at least 1");
+        let reconnect_interval = config.job_socket_reconnect_interval;
+        settings.check(!reconnect_interval.is_zero(), "job_socket_reconnect_interval_secs must be at least 1");
+        settings.check(!config.result_queue_interval.is_zero(), "result_queue_interval_secs must be at least 1");
+        settings.check(!config.cancel_poll_interval.is_zero(), "cancel_poll_interval_secs must be at least 1");
+        settings.check(!config.progress_interval.is_zero(), "progress_interval_secs must be at least 1");
+        settings.check(!config.otlp_export_interval.is_zero(), "otlp.export_interval_secs must be at least 1");
+        settings.check(config.debug_artifact_max_jobs > 0, "debug_artifact_max_jobs must be at least 1");
+        let problem = "debug_artifact_max_age_secs must be at least 1";
+        settings.check(!config.debug_artifact_max_age.is_zero(), problem);
+        let problem = "verdict_summary_interval_secs must be at least 1";
+        settings.check(!config.verdict_summary_interval.is_zero(), problem);
+        if let Some(SigningConfig { key: KeySource::Kms { .. }, .. }) = config.result_signing {
+            let problem = "result_signing.kms_key_id needs AWS credentials, see s3.access_key_id";
+            settings.check(config.s3.is_some(), problem);
+        }
+        config
+    }
+
+    // With the key file at `result_signing.key_path` or the KMS key `result_signing.kms_key_id`, whose ID it is
+    // given by default
+    fn read_result_signing(settings: &mut Settings) -> Option<SigningConfig> {
+        let kms_key_id = settings.get("result_signing.kms_key_id");
+        let key = match (settings.get("result_signing.key_path"), kms_key_id.clone()) {
+            (Some(path), None) => KeySource::File(PathBuf::from(path)),
+            (None, Some(key_id)) => KeySource::Kms { key_id, endpoint: settings.get("result_signing.kms_endpoint") },
+            (None, None) => return None,
+            (Some(_), Some(_)) => {
+                settings.check(false, "result_signing.key_path and result_signing.kms_key_id are mutually exclusive");
+                return None;
+            }
+        };
+        let key_id = match kms_key_id {
+            Some(kms_key_id) => settings.get("result_signing.key_id").unwrap_or(kms_key_id),
+            None => settings.required("result_signing.key_id"),
+        };
+        let algorithm = settings.parse_or("result_signing.algorithm", Algorithm::Ed25519);
+        Some(SigningConfig { key_id, algorithm, key })
+    }
+
+    // `<endpoint>.read_timeout_secs` and `<endpoint>.timeout_secs`
+    fn read_timeouts(settings: &mut Settings, endpoint: &str, read: u64, request: u64) -> Timeouts {
+        let read_key = format!("{}.read_timeout_secs", endpoint);
+        let request_key = format!("{}.timeout_secs", endpoint);
+        let timeouts = Timeouts {
+            read: Duration::from_secs(settings.parse_or(&read_key, read)),
+            request: Duration::from_secs(settings.parse_or(&request_key, request)),
+        };
+        settings.check(!timeouts.read.is_zero(), &format!("{} must be at least 1", read_key));
+        settings.check(!timeouts.request.is_zero(), &format!("{} must be at least 1", request_key));
+        timeouts
+    }
+
+    // Credentials fall back to the standard AWS environment variables
+    fn read_s3(settings: &mut Settings) -> Option<S3> {
+        let aws = |key: &str, name: &str| settings.get(key).or_else(|| env::var(name).ok());
+        let access_key_id = aws("s3.access_key_id", "AWS_ACCESS_KEY_ID");
+        let secret_access_key = aws("s3.secret_access_key", "AWS_SECRET_ACCESS_KEY");
+        let session_token = aws("s3.session_token", "AWS_SESSION_TOKEN");
+        let region = aws("s3.region", "AWS_REGION").unwrap_or_else(|| String::from("us-east-1"));
+        let endpoint = settings.get("s3.endpoint");
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => {
+                Some(S3 { region, endpoint, access_key_id, secret_access_key, session_token })
+            }
+            (None, None) => None,
+            _ => {
+                settings.check(false, "s3.access_key_id and s3.secret_access_key must be set together");
+                None
+            }
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn read(settings: &mut Settings) -> ServerConfig {
+        ServerConfig {
+            listen_addr: settings.parse_or("server.listen_addr", SocketAddr::from(([0, 0, 0, 0], 8080))),
+            max_body_bytes: settings.parse_or("server.max_body_bytes", 50 * 1024 * 1024),
+        }
+    }
+}
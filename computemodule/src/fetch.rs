@@ -0,0 +1,69 @@
+use crate::s3::{parse_s3_url, S3};
+use fraud_core::output_mime_type;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, RequestBuilder, Url};
+use std::time::Duration;
+
+/// Limits on images jobs reference by URL instead of inlining them.
+#[derive(Clone, Copy)]
+pub struct FetchLimits {
+    pub max_bytes: usize,
+    /// Covers connecting as well as transferring the whole body.
+    pub timeout: Duration,
+}
+
+// HTTP(S) URLs, presigned ones included, are used as is, `s3://` ones are signed
+fn request(client: &Client, s3: Option<&S3>, method: Method, url: &str) -> Result<RequestBuilder, String> {
+    if url.starts_with("s3://") {
+        let (bucket, key) = parse_s3_url(url).ok_or_else(|| format!("Invalid S3 URL {}, expected s3://bucket/key", url))?;
+        let s3 = s3.ok_or("s3:// URLs need s3.access_key_id and s3.secret_access_key to be configured")?;
+        return s3.request(client, method, bucket, key);
+    }
+    let url = Url::parse(url).map_err(|err| format!("Invalid image URL: {}", err))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Unsupported image URL scheme {:?}", url.scheme()));
+    }
+    Ok(client.request(method, url))
+}
+
+/// Downloads an image, failing once it grows past `limits.max_bytes`.
+pub async fn fetch(client: &Client, s3: Option<&S3>, url: &str, limits: FetchLimits) -> Result<Vec<u8>, String> {
+    let too_large = || format!("Image at {} is larger than {} bytes", url, limits.max_bytes);
+    let mut response = request(client, s3, Method::GET, url)?
+        .timeout(limits.timeout)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Failed to fetch image: {}", err))?;
+    if response.content_length().is_some_and(|length| length > limits.max_bytes as u64) {
+        return Err(too_large());
+    }
+    let mut image_data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| format!("Failed to fetch image: {}", err))? {
+        if image_data.len() + chunk.len() > limits.max_bytes {
+            return Err(too_large());
+        }
+        image_data.extend_from_slice(&chunk);
+    }
+    Ok(image_data)
+}
+
+/// Uploads an annotated image with a PUT, to a presigned URL or an S3 object.
+pub async fn upload(
+    client: &Client,
+    s3: Option<&S3>,
+    url: &str,
+    image_data: Vec<u8>,
+    limits: FetchLimits,
+) -> Result<(), String> {
+    // The output is the annotated image, the input itself when nothing was found, or a ZIP for archives
+    request(client, s3, Method::PUT, url)?
+        .timeout(limits.timeout)
+        .header(CONTENT_TYPE, output_mime_type(&image_data))
+        .body(image_data)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Failed to upload annotated image: {}", err))?;
+    Ok(())
+}
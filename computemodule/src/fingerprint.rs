@@ -0,0 +1,39 @@
+use fraud_core::{camera_key, claimed_camera, Fingerprint};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct FingerprintReport<'a> {
+    camera: &'a str,
+    images: usize,
+    width: u32,
+    height: u32,
+    output: PathBuf,
+}
+
+/// Estimates a PRNU fingerprint from images taken with one camera and writes
+/// it to `<output_dir>/<camera_key>.prnu`, where the prnu detector finds it.
+/// The camera defaults to the EXIF make and model of the first image.
+pub fn run(images: &[PathBuf], camera: Option<String>, output_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let encoded = images.iter().map(fs::read).collect::<Result<Vec<_>, _>>()?;
+    let camera = match camera {
+        Some(camera) => camera_key(&camera),
+        None => encoded.first().and_then(|data| claimed_camera(data)).ok_or("no EXIF camera found, pass --camera")?,
+    };
+    let decoded = encoded.iter().map(|data| image::load_from_memory(data)).collect::<Result<Vec<_>, _>>()?;
+    let fingerprint = Fingerprint::estimate(&decoded)?;
+
+    let output = output_dir.join(format!("{}.prnu", camera));
+    fingerprint.write(&output)?;
+    let report = FingerprintReport {
+        camera: &camera,
+        images: decoded.len(),
+        width: fingerprint.width,
+        height: fingerprint.height,
+        output,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use crate::auth::AuthToken;
+use crate::ca::{ReloadingClient, Tls};
+use crate::transport::{
+    after_upload, within, BoxFuture, FetchedJob, JobSource, PostError, ResultBody, ResultSink, Timeouts, CHUNK_BYTES,
+    CORRELATION_ID,
+};
+use hyper::body::{Bytes, HttpBody};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Body, Client, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+const GET_JOB: &str = "/computemodule.v1.Jobs/GetJob";
+const POST_RESULT: &str = "/computemodule.v1.Jobs/PostResult";
+
+pub type GrpcClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Unary calls to the `computemodule.v1.Jobs` service of `proto/jobs.proto`.
+pub struct Grpc {
+    pub client: ReloadingClient<GrpcClient>,
+    /// Scheme, host and port of the service, e.g. `https://jobs.internal:443`.
+    pub uri: String,
+    pub module_auth_token: AuthToken,
+    pub get_job_timeouts: Timeouts,
+    pub post_result_timeouts: Timeouts,
+}
+
+// Protobuf wire format, the messages of the service only have length-delimited fields
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(buf, u64::from(field) << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn varint(data: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or("Truncated varint")?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(String::from("Varint is too long"))
+}
+
+// The length-delimited `wanted` field of `message`, empty when unset, other fields are skipped
+fn field(mut message: &[u8], wanted: u32) -> Result<&[u8], String> {
+    let mut value: &[u8] = &[];
+    while !message.is_empty() {
+        let key = varint(&mut message)?;
+        let len = match key & 7 {
+            0 => {
+                varint(&mut message)?;
+                continue;
+            }
+            1 => 8,
+            2 => usize::try_from(varint(&mut message)?).map_err(|err| err.to_string())?,
+            5 => 4,
+            wire_type => return Err(format!("Unsupported wire type {}", wire_type)),
+        };
+        if len > message.len() {
+            return Err(String::from("Truncated field"));
+        }
+        let (bytes, rest) = message.split_at(len);
+        // Like protobuf, the last occurrence wins
+        if key == u64::from(wanted) << 3 | 2 {
+            value = bytes;
+        }
+        message = rest;
+    }
+    Ok(value)
+}
+
+// A length-prefixed message, uncompressed as no grpc-encoding is sent
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut body = vec![0];
+    body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    body.extend_from_slice(message);
+    body
+}
+
+// The framed message as a body sent in chunks, and the task sending them, done once the last was handed on
+fn upload(message: &[u8]) -> (Body, JoinHandle<()>) {
+    let framed = Bytes::from(frame(message));
+    let (mut sender, body) = Body::channel();
+    let sending = task::spawn(async move {
+        for start in (0..framed.len()).step_by(CHUNK_BYTES) {
+            let chunk = framed.slice(start..framed.len().min(start + CHUNK_BYTES));
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+    });
+    (body, sending)
+}
+
+fn unframe(body: &[u8]) -> Result<&[u8], String> {
+    let (&compressed, rest) = body.split_first().ok_or("Call ended without a response message")?;
+    if compressed != 0 {
+        return Err(String::from("Compressed response messages are not supported"));
+    }
+    let len = rest.get(..4).ok_or("Truncated response message")?;
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    rest.get(4..4 + len).ok_or_else(|| String::from("Truncated response message"))
+}
+
+/// A client speaking HTTP/2 without negotiating it, over TLS with `tls` for
+/// `https` URIs. Not reqwest, which drops the trailers calls end with.
+pub fn client(tls: Tls, connect_timeout: Duration) -> Result<GrpcClient, String> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(connect_timeout));
+    let https = HttpsConnectorBuilder::new()
+        .with_tls_config(tls.rustls_config()?)
+        .https_or_http()
+        .enable_http2()
+        .wrap_connector(http);
+    Ok(Client::builder().http2_only(true).build(https))
+}
+
+// The trailers of the call, or its headers for calls failing before a response message. Without a status the
+// call was cut short, e.g. by a proxy.
+fn check_status(metadata: &HeaderMap) -> Result<(), PostError> {
+    let header = |name: &str| metadata.get(name).and_then(|value| value.to_str().ok());
+    let status = header("grpc-status").ok_or_else(|| PostError::Transient(String::from("Call ended without a status")))?;
+    let message = format!("gRPC status {}: {}", status, header("grpc-message").unwrap_or(""));
+    match status {
+        "0" => Ok(()),
+        // DEADLINE_EXCEEDED, RESOURCE_EXHAUSTED, ABORTED and UNAVAILABLE
+        "4" | "8" | "10" | "14" => Err(PostError::Transient(message)),
+        _ => Err(PostError::Rejected(message)),
+    }
+}
+
+// The status code, headers, body and trailers of the answer to `request`, each arriving within `read` once
+// `uploaded`
+async fn exchange(
+    client: GrpcClient,
+    request: Request<Body>,
+    uploaded: JoinHandle<()>,
+    read: Duration,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>, Option<HeaderMap>), String> {
+    let (parts, mut body) = after_upload(read, uploaded, client.request(request)).await?.into_parts();
+    let mut data = Vec::new();
+    while let Some(chunk) = within(read, async { body.data().await.transpose() }).await? {
+        data.extend_from_slice(&chunk);
+    }
+    let trailers = within(read, body.trailers()).await?;
+    Ok((parts.status, parts.headers, data, trailers))
+}
+
+impl Grpc {
+    // Along with the headers of the answer, `correlation_id` is sent as metadata
+    async fn call(
+        &self,
+        method: &str,
+        message: &[u8],
+        correlation_id: Option<&str>,
+        timeouts: Timeouts,
+    ) -> Result<(HeaderMap, Vec<u8>), PostError> {
+        let token = self.module_auth_token.current();
+        let mut request = Request::post(format!("{}{}", self.uri, method))
+            .header(CONTENT_TYPE, "application/grpc")
+            .header("te", "trailers")
+            .header("module-auth-token", &token);
+        if let Some(correlation_id) = correlation_id {
+            request = request.header(CORRELATION_ID, correlation_id);
+        }
+        let (body, uploaded) = upload(message);
+        let request = request.body(body).map_err(|err| PostError::Rejected(err.to_string()))?;
+        let exchange = exchange(self.client.current(), request, uploaded, timeouts.read);
+        let answer = time::timeout(timeouts.request, exchange).await;
+        let (status, headers, body, trailers) = answer
+            .map_err(|_| PostError::Transient(format!("Call did not finish within {:?}", timeouts.request)))?
+            .map_err(PostError::Transient)?;
+        let metadata = trailers.as_ref().unwrap_or(&headers);
+        // HTTP 401 or 403, or UNAUTHENTICATED or PERMISSION_DENIED
+        let grpc_status = metadata.get("grpc-status").map(|value| value.as_bytes());
+        let refused = matches!(status.as_u16(), 401 | 403) || matches!(grpc_status, Some(b"7" | b"16"));
+        if refused && self.module_auth_token.refresh(&token) {
+            return Err(PostError::Transient(String::from("Token refused, retrying with the reloaded one")));
+        }
+        if !status.is_success() {
+            let message = format!("Unexpected status code: {}", status);
+            let retry = status.is_server_error();
+            return Err(if retry { PostError::Transient(message) } else { PostError::Rejected(message) });
+        }
+        check_status(metadata)?;
+        let message = unframe(&body).map_err(PostError::Transient)?;
+        Ok((headers, message.to_vec()))
+    }
+}
+
+impl JobSource for Grpc {
+    fn next_job(&self) -> BoxFuture<'_, Result<Option<FetchedJob>, String>> {
+        Box::pin(async move {
+            let call = self.call(GET_JOB, &[], None, self.get_job_timeouts).await;
+            let (headers, response) = call.map_err(|err| err.to_string())?;
+            let job = field(&response, 1)?;
+            let correlation_id = headers.get(CORRELATION_ID).and_then(|value| value.to_str().ok()).map(str::to_string);
+            Ok((!job.is_empty()).then(|| FetchedJob { document: job.to_vec(), correlation_id }))
+        })
+    }
+}
+
+impl ResultSink for Grpc {
+    // Messages are length-prefixed, so the result is serialized in full before it is sent
+    fn post_result<'a>(
+        &'a self,
+        job_id: &'a str,
+        idempotency_key: &'a str,
+        correlation_id: Option<&'a str>,
+        result: &'a ResultBody,
+    ) -> BoxFuture<'a, Result<(), PostError>> {
+        Box::pin(async move {
+            let result = result.to_bytes();
+            let result = result.map_err(|err| PostError::Rejected(format!("Failed to serialize result: {}", err)))?;
+            let mut message = Vec::new();
+            put_field(&mut message, 1, job_id.as_bytes());
+            put_field(&mut message, 2, &result);
+            put_field(&mut message, 3, idempotency_key.as_bytes());
+            let call = self.call(POST_RESULT, &message, correlation_id, self.post_result_timeouts).await;
+            call.map(|_| ()).map_err(|err| match err {
+                PostError::Transient(err) => PostError::Transient(format!("Failed to post result: {}", err)),
+                PostError::Rejected(err) => PostError::Rejected(format!("Failed to post result: {}", err)),
+            })
+        })
+    }
+}
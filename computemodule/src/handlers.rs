@@ -0,0 +1,277 @@
+use fraud_core::{
+    decode_base64_into, decode_image, encode_base64, heif_format, Annotation, Color, Detector, JobError, JobSettings, LineStyle, Locale,
+    MetadataDetector, OutputEncoding, OutputFormat, Pipeline, QueryResult, RegionArea, Sensitivity, BUFFERS,
+};
+use image::io::Reader as ImageReader;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+use tokio::task::JoinError;
+
+/// Handles one compute module query type for an already decoded input image.
+pub type Handler = fn(&Pipeline, &JobSettings, &str, &[u8]) -> Result<QueryResult, JobError>;
+
+/// A base64 encoded image, decoded while the JSON document carrying it is
+/// parsed so the encoded text is never copied out of the document. Invalid
+/// base64 fails the job rather than the document.
+pub struct Base64Image(Result<Vec<u8>, String>);
+
+impl Base64Image {
+    pub fn decoded(self) -> Result<Vec<u8>, String> {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64Image {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Base64Visitor;
+
+        impl Visitor<'_> for Base64Visitor {
+            type Value = Base64Image;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a base64 encoded image")
+            }
+
+            // Borrowed from the document unless its JSON escapes had to be undone
+            fn visit_str<E: de::Error>(self, encoded: &str) -> Result<Base64Image, E> {
+                Ok(Base64Image(decode_base64(encoded)))
+            }
+        }
+
+        deserializer.deserialize_str(Base64Visitor)
+    }
+}
+
+/// Into a pooled buffer, see [`BUFFERS`].
+pub fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = BUFFERS.take(encoded.len() / 4 * 3);
+    match decode_base64_into(encoded.as_bytes(), &mut decoded) {
+        Ok(()) => Ok(decoded),
+        Err(err) => {
+            BUFFERS.give(decoded);
+            Err(format!("Invalid base64 image: {}", err))
+        }
+    }
+}
+
+/// Sensitivity settings sent with a single job, unset ones keep the configured value.
+#[derive(Deserialize, Default)]
+pub struct SensitivityOverride {
+    min_region_confidence: Option<f64>,
+    min_region_area: Option<RegionArea>,
+    suspicious_threshold: Option<f64>,
+    edited_threshold: Option<f64>,
+}
+
+impl SensitivityOverride {
+    pub fn apply(&self, base: Sensitivity) -> Result<Sensitivity, String> {
+        let sensitivity = Sensitivity {
+            min_region_confidence: self.min_region_confidence.unwrap_or(base.min_region_confidence),
+            min_region_area: self.min_region_area.unwrap_or(base.min_region_area),
+            suspicious_threshold: self.suspicious_threshold.unwrap_or(base.suspicious_threshold),
+            edited_threshold: self.edited_threshold.unwrap_or(base.edited_threshold),
+        };
+        sensitivity.validate().map_err(|problem| format!("Invalid sensitivity: {}", problem))?;
+        Ok(sensitivity)
+    }
+}
+
+/// Output settings sent with a single job, unset ones keep the configured value.
+#[derive(Deserialize, Default)]
+pub struct OutputOverride {
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    regions_only: Option<bool>,
+    max_size: Option<u32>,
+}
+
+impl OutputOverride {
+    pub fn apply(&self, base: OutputEncoding) -> Result<OutputEncoding, String> {
+        let encoding = OutputEncoding {
+            format: self.format.unwrap_or(base.format),
+            quality: self.quality.unwrap_or(base.quality),
+            regions_only: self.regions_only.unwrap_or(base.regions_only),
+            max_size: self.max_size.or(base.max_size),
+        };
+        encoding.validate().map_err(|problem| format!("Invalid output: {}", problem))?;
+        Ok(encoding)
+    }
+}
+
+/// Annotation settings sent with a single job, unset ones keep the configured value.
+#[derive(Deserialize, Default)]
+pub struct AnnotationOverride {
+    color: Option<Color>,
+    thickness: Option<u32>,
+    style: Option<LineStyle>,
+    labels: Option<bool>,
+    fill: Option<Color>,
+    auto_scale: Option<bool>,
+}
+
+impl AnnotationOverride {
+    pub fn apply(&self, base: Annotation) -> Result<Annotation, String> {
+        let annotation = Annotation {
+            color: self.color.unwrap_or(base.color),
+            thickness: self.thickness.unwrap_or(base.thickness),
+            style: self.style.unwrap_or(base.style),
+            labels: self.labels.unwrap_or(base.labels),
+            fill: self.fill.unwrap_or(base.fill),
+            auto_scale: self.auto_scale.unwrap_or(base.auto_scale),
+        };
+        annotation.validate().map_err(|problem| format!("Invalid annotation: {}", problem))?;
+        Ok(annotation)
+    }
+}
+
+/// Everything a single job may override, sent next to its image.
+#[derive(Deserialize, Default)]
+pub struct JobOverrides {
+    #[serde(default)]
+    pub sensitivity: SensitivityOverride,
+    /// Format and quality of the annotated image.
+    #[serde(default)]
+    pub output: OutputOverride,
+    /// Color and style of region outlines.
+    #[serde(default)]
+    pub annotation: AnnotationOverride,
+    /// Language of the result text.
+    pub locale: Option<Locale>,
+    /// Add the statistics behind every region to the result.
+    #[serde(default)]
+    pub debug: bool,
+}
+
+impl JobOverrides {
+    /// The configured settings with these applied.
+    pub fn apply(&self, pipeline: &Pipeline) -> Result<JobSettings, String> {
+        let base = pipeline.job_settings();
+        Ok(JobSettings {
+            sensitivity: self.sensitivity.apply(base.sensitivity)?,
+            encoding: self.output.apply(base.encoding)?,
+            annotation: self.annotation.apply(base.annotation)?,
+            locale: self.locale.unwrap_or(base.locale),
+            debug: self.debug,
+            ..base
+        })
+    }
+}
+
+/// Maps the job `query_type` to the handler serving it.
+pub struct Handlers {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        let mut handlers = Handlers { handlers: HashMap::new() };
+        handlers.register("detectFraud", detect_fraud);
+        handlers.register("detectCrop", detect_crop);
+        handlers.register("analyzeMetadata", analyze_metadata);
+        handlers
+    }
+}
+
+impl Handlers {
+    pub fn register(&mut self, query_type: &'static str, handler: Handler) {
+        self.handlers.insert(query_type, handler);
+    }
+
+    pub fn get(&self, query_type: &str) -> Option<Handler> {
+        self.handlers.get(query_type).copied()
+    }
+}
+
+/// Runs `handler`, failing the job instead of the thread running it when it panics.
+pub fn call(
+    handler: Handler,
+    pipeline: &Pipeline,
+    settings: &JobSettings,
+    job_id: &str,
+    image_data: &[u8],
+) -> Result<QueryResult, JobError> {
+    let handled = panic::catch_unwind(AssertUnwindSafe(|| handler(pipeline, settings, job_id, image_data)));
+    handled.unwrap_or_else(|panic| Err(JobError::panicked(panic)))
+}
+
+/// Why a task handling a job did not finish, with the message of its panic if it panicked.
+pub fn join_error(err: JoinError) -> JobError {
+    match err.try_into_panic() {
+        Ok(panic) => JobError::panicked(panic),
+        Err(err) => JobError::Internal(err.to_string()),
+    }
+}
+
+pub fn unsupported_query_type(query_type: &str) -> QueryResult {
+    QueryResult {
+        enc_img_out: String::new(),
+        text: format!("Unsupported query type: {}", query_type),
+        result: String::from("unsupported"),
+        ..QueryResult::default()
+    }
+}
+
+fn detect_fraud(
+    pipeline: &Pipeline,
+    settings: &JobSettings,
+    job_id: &str,
+    image_data: &[u8],
+) -> Result<QueryResult, JobError> {
+    let (result, image_out) = pipeline.detect_raw(job_id, image_data, settings)?;
+    let enc_img_out = encode_base64(&image_out);
+    BUFFERS.give(image_out);
+    Ok(QueryResult { enc_img_out, ..result })
+}
+
+fn detect_crop(
+    pipeline: &Pipeline,
+    settings: &JobSettings,
+    job_id: &str,
+    image_data: &[u8],
+) -> Result<QueryResult, JobError> {
+    let analysis = pipeline.analyze_with(job_id, image_data, settings)?;
+    let result = String::from(if analysis.cropped { "cropped" } else { "uncropped" });
+    Ok(QueryResult { result, ..QueryResult::default() })
+}
+
+#[derive(Serialize)]
+struct ImageMetadata {
+    format: Option<String>,
+    width: u32,
+    height: u32,
+    color_type: String,
+    byte_size: usize,
+}
+
+fn analyze_metadata(
+    pipeline: &Pipeline,
+    _settings: &JobSettings,
+    _job_id: &str,
+    image_data: &[u8],
+) -> Result<QueryResult, JobError> {
+    pipeline.check_limits(image_data)?;
+    let reader = ImageReader::new(Cursor::new(image_data)).with_guessed_format();
+    let reader = reader.map_err(|err| JobError::Decode(err.to_string()))?;
+    let format = heif_format(image_data)
+        .map(String::from)
+        .or_else(|| reader.format().map(|format| format!("{:?}", format).to_lowercase()));
+    let image = decode_image(image_data).map_err(|err| JobError::Decode(format!("Failed to decode image: {}", err)))?;
+    let metadata = ImageMetadata {
+        format,
+        width: image.width(),
+        height: image.height(),
+        color_type: format!("{:?}", image.color()),
+        byte_size: image_data.len(),
+    };
+    let metadata_findings = MetadataDetector::default().analyze_encoded(&image, image_data).metadata;
+    Ok(QueryResult {
+        text: serde_json::to_string(&metadata).map_err(|err| JobError::Internal(err.to_string()))?,
+        result: String::from("analyzed"),
+        metadata_findings,
+        ..QueryResult::default()
+    })
+}
@@ -0,0 +1,136 @@
+use crate::circuit::Circuit;
+use crate::transport::JobSource;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+/// What the worker is up to, kept up to date by it for the probes.
+pub struct WorkerStatus {
+    started: Instant,
+    last_poll: Mutex<Option<Instant>>,
+    in_flight: AtomicUsize,
+    draining: AtomicBool,
+}
+
+impl Default for WorkerStatus {
+    fn default() -> Self {
+        WorkerStatus {
+            started: Instant::now(),
+            last_poll: Mutex::default(),
+            in_flight: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+        }
+    }
+}
+
+impl WorkerStatus {
+    /// Asking for a job succeeded, whether there was one or not.
+    pub fn polled(&self) {
+        *self.last_poll.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Instant::now());
+    }
+
+    pub fn job_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn job_finished(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// No more jobs are fetched, the worker is shutting down.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    fn last_poll(&self) -> Option<Instant> {
+        *self.last_poll.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// What the probes are answered from.
+pub struct Probes {
+    pub jobs: Arc<dyn JobSource>,
+    pub circuits: Vec<Arc<Circuit>>,
+    pub status: Arc<WorkerStatus>,
+    /// How long the worker may go without a successful poll while it has no
+    /// job, isn't waiting for one to be pushed and its endpoints are up before
+    /// it counts as wedged.
+    pub max_poll_age: Duration,
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// `ok`, `degraded` while any upstream endpoint is failing, `draining`
+    /// while shutting down or `wedged` when the worker stopped polling.
+    status: &'static str,
+    /// None before the first successful poll.
+    seconds_since_last_poll: Option<f64>,
+    in_flight_jobs: usize,
+    /// `open` or `closed` by endpoint.
+    circuits: BTreeMap<&'static str, &'static str>,
+}
+
+impl Probes {
+    fn upstream_failing(&self) -> bool {
+        self.circuits.iter().any(|circuit| circuit.is_open())
+    }
+
+    fn report(&self, status: &'static str) -> Health {
+        let circuits = self.circuits.iter();
+        let circuits = circuits.map(|circuit| (circuit.name, if circuit.is_open() { "open" } else { "closed" }));
+        Health {
+            status,
+            seconds_since_last_poll: self.status.last_poll().map(|polled| polled.elapsed().as_secs_f64()),
+            in_flight_jobs: self.status.in_flight.load(Ordering::Relaxed),
+            circuits: circuits.collect(),
+        }
+    }
+}
+
+// Wedged only when nothing explains the missing polls, not running jobs, which time out on their own, waiting for
+// pushed ones or upstream being down, none of which restarting would help with
+async fn liveness(State(probes): State<Arc<Probes>>) -> (StatusCode, Json<Health>) {
+    let status = &probes.status;
+    let polled = status.last_poll().unwrap_or(status.started);
+    let busy = status.in_flight.load(Ordering::Relaxed) > 0 || status.draining.load(Ordering::Relaxed);
+    let waiting = busy || probes.jobs.is_pushed() || probes.upstream_failing();
+    if polled.elapsed() > probes.max_poll_age && !waiting {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(probes.report("wedged")))
+    } else {
+        (StatusCode::OK, Json(probes.report("ok")))
+    }
+}
+
+async fn readiness(State(probes): State<Arc<Probes>>) -> (StatusCode, Json<Health>) {
+    if probes.status.draining.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(probes.report("draining")))
+    } else if probes.upstream_failing() {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(probes.report("degraded")))
+    } else {
+        (StatusCode::OK, Json(probes.report("ok")))
+    }
+}
+
+/// Serves `GET /healthz`, answering 503 when the worker hasn't polled for
+/// `max_poll_age` for no good reason, and `GET /readyz`, answering 503 while
+/// any circuit is open or the worker is shutting down. `GET /health` is the
+/// same as `/readyz`.
+pub async fn serve(listen_addr: SocketAddr, probes: Probes) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .route("/health", get(readiness))
+        .with_state(Arc::new(probes));
+    let listener = TcpListener::bind(listen_addr).await?;
+    info!("Serving health on {}", listen_addr);
+    axum::serve(listener, app).await.inspect_err(|err| error!("Health server failed: {}", err))
+}
@@ -0,0 +1,88 @@
+use env_logger::fmt::Formatter;
+use log::kv::{self, Key, VisitSource};
+use log::Record;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// Of the jobs running right now, by job ID
+static CORRELATION_IDS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Logs as configured by `RUST_LOG`, with `LOG_FORMAT=json` as one JSON
+/// object per line that has the key-values of the record as fields, e.g.
+/// `job_id`, `stage`, `duration_ms` and `verdict`, and `correlation_id` for
+/// lines of jobs that came with one.
+pub fn init() {
+    let mut builder = env_logger::Builder::from_default_env();
+    if env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        builder.format(write_json);
+    }
+    builder.init();
+}
+
+/// Until dropped, JSON log lines of the job carry its correlation ID.
+pub struct Correlated(String);
+
+impl Drop for Correlated {
+    fn drop(&mut self) {
+        CORRELATION_IDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.0);
+    }
+}
+
+/// Has the log lines of `job_id`, those with it as their `job_id` field or
+/// starting with it like `<job_id>: Posted result`, carry `correlation_id`
+/// as a field. Lines of its pages, files and batch images are included.
+pub fn correlate(job_id: &str, correlation_id: &str) -> Correlated {
+    let mut correlation_ids = CORRELATION_IDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    correlation_ids.insert(job_id.to_string(), correlation_id.to_string());
+    Correlated(job_id.to_string())
+}
+
+// Of the longest job ID `text` is or starts with, followed by a colon or space
+fn correlation_id(text: &str) -> Option<String> {
+    let correlation_ids = CORRELATION_IDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let matching = correlation_ids.iter().filter(|(job_id, _)| {
+        text.strip_prefix(job_id.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with([':', ' ']))
+    });
+    matching.max_by_key(|(job_id, _)| job_id.len()).map(|(_, correlation_id)| correlation_id.clone())
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let json = if let Some(number) = value.to_u64() {
+            Value::from(number)
+        } else if let Some(number) = value.to_i64() {
+            Value::from(number)
+        } else if let Some(number) = value.to_f64().and_then(Number::from_f64) {
+            Value::Number(number)
+        } else if let Some(flag) = value.to_bool() {
+            Value::Bool(flag)
+        } else {
+            Value::String(value.to_string())
+        };
+        self.0.insert(key.to_string(), json);
+        Ok(())
+    }
+}
+
+fn write_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut fields = Map::new();
+    // Ignored rather than losing the line over a field
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    let message = record.args().to_string();
+    if !fields.contains_key("correlation_id") {
+        let job_id = fields.get("job_id").and_then(Value::as_str).unwrap_or(&message);
+        if let Some(correlation_id) = correlation_id(job_id) {
+            fields.insert(String::from("correlation_id"), Value::String(correlation_id));
+        }
+    }
+    fields.insert(String::from("timestamp"), Value::String(buf.timestamp_millis().to_string()));
+    fields.insert(String::from("level"), Value::String(record.level().to_string()));
+    fields.insert(String::from("target"), Value::String(record.target().to_string()));
+    fields.insert(String::from("message"), Value::String(message));
+    writeln!(buf, "{}", Value::Object(fields))
+}
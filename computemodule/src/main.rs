@@ -1,201 +1,328 @@
-use base64::engine::general_purpose;
-use base64::Engine as _;
-use image::{load_from_memory, Rgba, RgbaImage};
-use reqwest::Certificate;
-use reqwest::blocking::Client;
-use reqwest::header::CONTENT_TYPE;
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::error::Error;
-use std::fs;
-use std::io::Cursor;
-use std::thread::sleep;
-use std::time::Duration;
-use log::{debug, error, info};
-use forgery_detection_zero::Zero;
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Job {
-    compute_module_job_v1: ComputeModuleJobV1,
-}
+use clap::{Parser, Subcommand};
+use reqwest::Client;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use log::{error, info};
 
-#[derive(Deserialize)]
-#[allow(dead_code)]
-#[serde(rename_all = "camelCase")]
-struct ComputeModuleJobV1 {
-    job_id: String,
-    query_type: String,
-    query: Query,
-}
+mod artifacts;
+mod audit;
+mod auth;
+mod bench;
+mod ca;
+mod cache;
+mod circuit;
+mod config;
+mod fetch;
+mod fingerprint;
+mod grpc;
+mod handlers;
+mod health;
+mod logging;
+mod queue;
+mod s3;
+mod scan;
+mod server;
+mod signing;
+mod stats;
+mod telemetry;
+mod transport;
+mod websocket;
+mod worker;
 
-#[derive(Serialize)]
-struct QueryResult {
-    enc_img_out: String,
-    text: String,
-    result: String,
-}
+use artifacts::ArtifactDir;
+use audit::AuditLog;
+use auth::AuthToken;
+use ca::{Reload, ReloadingClient, Tls};
+use cache::ResultCache;
+use circuit::{Circuit, GuardedJobs, GuardedResults};
+use config::{Config, ConfigError, JobApi, Overrides, ServerConfig, WorkerConfig};
+use fetch::FetchLimits;
+use grpc::Grpc;
+use handlers::Handlers;
+use health::{Probes, WorkerStatus};
+use queue::ResultQueue;
+use server::Server;
+use signing::ResultSigner;
+use stats::VerdictCounts;
+use telemetry::Tracer;
+use transport::{JobSource, ResultSink, Rest};
+use websocket::PushedJobs;
+use worker::{Deliveries, Worker};
+
+#[derive(Parser)]
+#[command(version, about = "Image forgery detection compute module")]
+struct Cli {
+    /// TOML or YAML config file, overrides CONFIG_PATH
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Comma separated detectors to run, overrides the configured list
+    #[arg(long, global = true)]
+    detectors: Option<String>,
 
-#[derive(Deserialize)]
-struct Query {
-    enc_img_in: String
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug)]
-struct Point {
-    x: u32,
-    y: u32,
+#[derive(Subcommand)]
+enum Command {
+    /// Poll the compute module job API for jobs (the default)
+    Worker {
+        /// Number of jobs processed in parallel
+        #[arg(long)]
+        concurrency: Option<usize>,
+
+        /// Seconds to wait for in-flight jobs on shutdown
+        #[arg(long)]
+        drain_timeout_secs: Option<u64>,
+    },
+    /// Serve POST /detect over HTTP
+    Serve {
+        /// Address to listen on
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+    },
+    /// Analyze a local image file and print the verdict as JSON
+    Scan {
+        path: PathBuf,
+
+        /// Where to write the annotated image, defaults to next to the input
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Time the detection pipeline over a local image file
+    Bench {
+        path: PathBuf,
+
+        #[arg(long, default_value_t = 10)]
+        iterations: u32,
+    },
+    /// Estimate a camera's PRNU fingerprint from images it took
+    Fingerprint {
+        #[arg(required = true)]
+        images: Vec<PathBuf>,
+
+        /// Camera name, defaults to the EXIF make and model of the first image
+        #[arg(long)]
+        camera: Option<String>,
+
+        /// Directory to write `<camera>.prnu` to, e.g. the configured prnu.fingerprint_dir
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+    },
 }
 
-#[derive(Debug)]
-struct Region {
-    start: Point,
-    end: Point,
+fn exit_on_config_error(err: ConfigError) -> ! {
+    error!("{}", err);
+    process::exit(1);
 }
 
-fn get_job_blocking(client: &Client, get_job_uri: &str, module_auth_token: &str) -> Result<Job, reqwest::Error> {
-    loop {
-        let response = client.get(get_job_uri)
-            .header("Module-Auth-Token", module_auth_token)
-            .send()?;
-        
-        match response.status().as_u16() {
-            200 => return response.json(),
-            204 => debug!("No job found, trying again!"),
-            _ => error!("Unexpected status code: {}", response.status()),
+async fn run_worker(overrides: Overrides) {
+    let (config, worker_config) = config::load(&overrides, |settings| (Config::read(settings), WorkerConfig::read(settings)))
+        .unwrap_or_else(|err| exit_on_config_error(err));
+
+    let module_auth_token = AuthToken::load(worker_config.module_auth_token_path.clone())
+        .expect("Failed to read module auth token");
+    let tls_files = worker_config.tls.read().unwrap_or_else(|err| panic!("Failed to read TLS certificate {}", err));
+    let tls = tls_files
+        .parse()
+        .unwrap_or_else(|err| panic!("Failed to load TLS certificates {}: {}", worker_config.tls, err));
+
+    let connect_timeout = worker_config.connect_timeout;
+    let client_builder =
+        move |tls: Tls| tls.apply(Client::builder()).use_rustls_tls().connect_timeout(connect_timeout);
+    let client = ReloadingClient::new(&tls, move |tls| client_builder(tls).build()).expect("Failed to build client");
+    let mut clients: Vec<Box<dyn Reload>> = vec![Box::new(client.clone())];
+
+    let (jobs, results): (Arc<dyn JobSource>, Arc<dyn ResultSink>) = match worker_config.job_api {
+        JobApi::Rest { get_job_uri, post_result_uri, job_socket_uri } => {
+            let rest = Arc::new(Rest {
+                client: client.clone(),
+                get_job_uri,
+                post_result_uri,
+                module_auth_token: module_auth_token.clone(),
+                compression: worker_config.compression,
+                get_job_timeouts: worker_config.get_job_timeouts,
+                post_result_timeouts: worker_config.post_result_timeouts,
+            });
+            match job_socket_uri {
+                // Upgrading needs HTTP/1.1, which servers could otherwise talk the client out of
+                Some(uri) => {
+                    let build = move |tls| client_builder(tls).http1_only().build();
+                    let socket_client = ReloadingClient::new(&tls, build).expect("Failed to build socket client");
+                    clients.push(Box::new(socket_client.clone()));
+                    let (token, interval) = (module_auth_token.clone(), worker_config.job_socket_reconnect_interval);
+                    (Arc::new(PushedJobs::new(socket_client, uri, token, interval, rest.clone())), rest)
+                }
+                None => (rest.clone(), rest),
+            }
+        }
+        JobApi::Grpc { uri } => {
+            let build = move |tls| grpc::client(tls, connect_timeout);
+            let grpc_client = ReloadingClient::new(&tls, build).expect("Failed to build gRPC client");
+            clients.push(Box::new(grpc_client.clone()));
+            let grpc = Arc::new(Grpc {
+                client: grpc_client,
+                uri,
+                module_auth_token: module_auth_token.clone(),
+                get_job_timeouts: worker_config.get_job_timeouts,
+                post_result_timeouts: worker_config.post_result_timeouts,
+            });
+            (grpc.clone(), grpc)
         }
+    };
+    tokio::spawn(ca::watch(worker_config.tls.clone(), tls_files, worker_config.ca_reload_interval, clients));
+    let job_circuit = Arc::new(Circuit::new("get_job", worker_config.circuit_breaker));
+    let result_circuit = Arc::new(Circuit::new("post_result", worker_config.circuit_breaker));
+    let jobs = Arc::new(GuardedJobs { jobs, circuit: job_circuit.clone() });
+    let results = Arc::new(GuardedResults { results, circuit: result_circuit.clone() });
+    let status = Arc::new(WorkerStatus::default());
+    if let Some(listen_addr) = worker_config.health_listen_addr {
+        let circuits = vec![job_circuit, result_circuit];
+        let (jobs, max_poll_age) = (jobs.clone(), worker_config.health_max_poll_age);
+        let probes = Probes { jobs, circuits, status: status.clone(), max_poll_age };
+        tokio::spawn(health::serve(listen_addr, probes));
     }
-}
 
-fn draw_hollow_rect(image: &mut RgbaImage, region: &Region, color: Rgba<u8>) {
-    let Region { start, end } = region;
+    let tracer = worker_config.otlp_endpoint.map(|endpoint| {
+        let tracer = Arc::new(Tracer::new(client.clone(), endpoint, worker_config.otlp_service_name));
+        tokio::spawn(telemetry::export(tracer.clone(), worker_config.otlp_export_interval));
+        tracer
+    });
 
-    // Draw top and bottom borders
-    for x in start.x..=end.x {
-        image.put_pixel(x, start.y, color);
-        image.put_pixel(x, end.y, color);
-    }
+    let audit_log = worker_config.audit_log_path.map(|path| {
+        Arc::new(AuditLog::open(path, worker_config.audit_log_hash_chain).expect("Failed to open audit log"))
+    });
 
-    // Draw left and right borders
-    for y in start.y..=end.y {
-        image.put_pixel(start.x, y, color);
-        image.put_pixel(end.x, y, color);
-    }
-}
+    let artifact_dir = worker_config.debug_artifact_dir.map(|dir| {
+        let (max_jobs, max_age) = (worker_config.debug_artifact_max_jobs, worker_config.debug_artifact_max_age);
+        ArtifactDir::open(dir, max_jobs, max_age).expect("Failed to create debug artifact dir")
+    });
 
-fn detect_fraud(job_id: &str, query: Query) -> Result<QueryResult, Box<dyn Error>> {
-    let image_data = general_purpose::STANDARD.decode(query.enc_img_in.clone()).expect("Failed to deserialize base64 enc image");
-    let image = load_from_memory(&image_data).expect("failed to load image");
-    info!("{}: Loaded image from memory, processing...", job_id);
-    let foreign_grid_areas = Zero::from_image(&image).detect_forgeries();
-    let missing_grid_areas = foreign_grid_areas
-        .detect_missing_grid_areas()
-        .unwrap()
-        .unwrap();
-    let forged_regions = foreign_grid_areas
-        .forged_regions()
-        .iter()
-        .chain(missing_grid_areas.forged_regions());
-    let mut accumulated = String::new();
-    let red = Rgba([255, 0, 0, 255]);
-    let mut image_buffer = image.to_rgba8();
-    let mut forged_regions_count = 0;
-    for r in forged_regions {
-        forged_regions_count += 1;
-        accumulated.push_str(&format!("Forged region: from ({}, {}) to ({}, {})\n", r.start.0, r.start.1, r.end.0, r.end.1));
-        draw_hollow_rect(&mut image_buffer, &Region { start: Point { x: r.start.0, y: r.start.1 }, end: Point { x: r.end.0, y: r.end.1 } }, red);
-    }
-    info!("{}: found {} forged regions", job_id, forged_regions_count);
-    if !accumulated.is_empty() {
-        let mut result = String::from("edited");
-        if foreign_grid_areas.is_cropped() {
-            result = String::from("editcrop");
+    let max_entries = worker_config.result_cache_max_entries;
+    let result_cache = (max_entries > 0).then(|| {
+        let dir = worker_config.result_cache_dir;
+        ResultCache::open(max_entries, dir, &config.detectors).expect("Failed to create result cache dir")
+    });
+
+    let signer = worker_config.result_signing.map(|signing| {
+        let signer = ResultSigner::load(signing, worker_config.s3.clone())
+            .unwrap_or_else(|err| panic!("Failed to load result signing key: {}", err));
+        match signer.public_key() {
+            Some(public_key) => info!("Signing results with key {}, Ed25519 public key {}", signer.key_id(), public_key),
+            None => info!("Signing results with key {}", signer.key_id()),
         }
-        let mut buf = Cursor::new(Vec::new());
-        image_buffer.write_to(&mut buf, image::ImageOutputFormat::Png)?;
-        let enc_img_out = general_purpose::STANDARD.encode(buf.into_inner());
-        info!("{}: Finished processing image, result: {}", job_id, result);
-        return Ok(QueryResult { enc_img_out, text: accumulated, result });
-    }
+        signer
+    });
+
+    let verdicts = Arc::new(VerdictCounts::default());
+    tokio::spawn(stats::log_summaries(verdicts.clone(), worker_config.verdict_summary_interval));
 
-    if foreign_grid_areas.is_cropped() {
-        let result = String::from("cropped");
-        info!("{}: Finished processing image, result: {}", job_id, result);
-        return Ok(QueryResult {enc_img_out: query.enc_img_in, text: String::from(""), result });
+    let drain_timeout = worker_config.shutdown_drain_timeout;
+    let drained = worker::run(Worker {
+        client,
+        jobs,
+        results,
+        post_result_retries: worker_config.post_result_retries,
+        max_job_failures: worker_config.max_job_failures,
+        deliveries: Deliveries::default(),
+        result_queue: ResultQueue::open(worker_config.result_queue_dir).expect("Failed to create result queue dir"),
+        result_queue_interval: worker_config.result_queue_interval,
+        poll_interval: worker_config.poll_interval,
+        poll_max_interval: worker_config.poll_max_interval,
+        module_auth_token,
+        concurrency: worker_config.worker_concurrency,
+        prefetch: worker_config.job_prefetch,
+        batch_concurrency: worker_config.batch_concurrency,
+        drain_timeout,
+        job_timeout: worker_config.job_timeout,
+        cancel_job_uri: worker_config.cancel_job_uri,
+        cancel_poll_interval: worker_config.cancel_poll_interval,
+        progress_uri: worker_config.progress_uri,
+        progress_interval: worker_config.progress_interval,
+        fetch_limits: FetchLimits { max_bytes: worker_config.fetch_max_bytes, timeout: worker_config.fetch_timeout },
+        s3: worker_config.s3,
+        tracer: tracer.clone(),
+        stage_timings: worker_config.stage_timings,
+        audit_log,
+        artifact_dir,
+        result_cache,
+        signer,
+        verdicts,
+        status,
+        pipeline: config.pipeline().unwrap_or_else(|err| exit_on_config_error(err)),
+        handlers: Handlers::default(),
+    })
+    .await;
+    if let Some(tracer) = tracer {
+        tracer.flush().await;
     }
 
-    let result = String::from("clean");
-    info!("{}: Finished processing image, result: {}", job_id, result);
-    return Ok(QueryResult { enc_img_out: query.enc_img_in, text: String::from(""), result });
+    // Blocking detection tasks would keep the runtime alive, exit explicitly
+    if drained.is_err() {
+        error!("In-flight jobs did not finish within {:?}, exiting anyway", drain_timeout);
+        process::exit(1);
+    }
 }
 
+async fn run_server(overrides: Overrides) {
+    let (config, server_config) = config::load(&overrides, |settings| (Config::read(settings), ServerConfig::read(settings)))
+        .unwrap_or_else(|err| exit_on_config_error(err));
 
-fn post_result(client: &Client, post_result_uri: &str, job_id: &str, result: &QueryResult, module_auth_token: &str) {
-    let response = client.post(&format!("{}/{}", post_result_uri, job_id))
-        .header("Module-Auth-Token", module_auth_token)
-        .header(CONTENT_TYPE, "application/octet-stream")
-        .body(serde_json::to_string(result).expect("Failed to serialize results"))
-        .send();
-    
-    match response {
-        Ok(res) => {
-            if res.status() != 204 {
-                error!("Failed to post result: {}", res.status());
-            } else {
-                info!("{}: Posted result", job_id);
-            }
-        }
-        Err(err) => {
-            error!("Error posting result: {}", err);
-        }
+    let server = Server {
+        listen_addr: server_config.listen_addr,
+        max_body_bytes: server_config.max_body_bytes,
+        pipeline: config.pipeline().unwrap_or_else(|err| exit_on_config_error(err)),
+    };
+    if server::run(server).await.is_err() {
+        process::exit(1);
     }
 }
 
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("Failed to start tokio runtime")
+}
+
 fn main() {
-    env_logger::init();
+    logging::init();
 
-    let cert_path = env::var("DEFAULT_CA_PATH").expect("DEFAULT_CA_PATH env var not set");
-    let module_auth_token = fs::read_to_string(env::var("MODULE_AUTH_TOKEN").expect("MODULE_AUTH_TOKEN env var not set"))
-        .expect("Failed to read module auth token");
-    
-    let get_job_uri = env::var("GET_JOB_URI").expect("GET_JOB_URI env var not set");
-    let post_result_uri = env::var("POST_RESULT_URI").expect("POST_RESULT_URL env var not set");
-    let cert_data = fs::read(cert_path.clone()).expect("Failed to read cert path");
-    let cert = Certificate::from_pem(&cert_data).expect("Failed to load cert");
-
-    let client = Client::builder()
-        .add_root_certificate(cert)
-        .use_rustls_tls()
-        .build()
-        .expect("Failed to build client");
-
-    loop {
-        match get_job_blocking(&client, &get_job_uri, &module_auth_token) {
-            Ok(job) => {
-                let v1 = job.compute_module_job_v1;
-                let job_id = &v1.job_id;
-
-                info!("Got job: {}", job_id);
-
-                match detect_fraud(job_id, v1.query) {
-                    Ok(res) => post_result(&client, &post_result_uri, job_id, &res, &module_auth_token),
-                    Err(err) => post_result(
-                        &client, 
-                        &post_result_uri, 
-                        job_id, 
-                        &QueryResult { 
-                            enc_img_out: String::new(), 
-                            text: err.to_string(), 
-                            result: String::from("Failed"),
-                        }, 
-                        &module_auth_token),
-                }
+    let cli = Cli::parse();
+    let mut overrides = Overrides::default();
+    overrides.config_path = cli.config;
+    overrides.set("detectors", cli.detectors);
+
+    let command = cli.command.unwrap_or(Command::Worker { concurrency: None, drain_timeout_secs: None });
+    match command {
+        Command::Worker { concurrency, drain_timeout_secs } => {
+            overrides.set("worker_concurrency", concurrency);
+            overrides.set("shutdown_drain_timeout_secs", drain_timeout_secs);
+            runtime().block_on(run_worker(overrides));
+        }
+        Command::Serve { listen } => {
+            overrides.set("server.listen_addr", listen);
+            runtime().block_on(run_server(overrides));
+        }
+        Command::Scan { path, output } => {
+            let config = config::load(&overrides, Config::read).unwrap_or_else(|err| exit_on_config_error(err));
+            if let Err(err) = scan::run(&config, &path, output) {
+                error!("Failed to scan {}: {}", path.display(), err);
+                process::exit(1);
             }
-            Err(err) => {
-                error!("Something failed: {}", err);
-                sleep(Duration::from_secs(1));
+        }
+        Command::Bench { path, iterations } => {
+            let config = config::load(&overrides, Config::read).unwrap_or_else(|err| exit_on_config_error(err));
+            if let Err(err) = bench::run(&config, &path, iterations) {
+                error!("Failed to benchmark {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+        Command::Fingerprint { images, camera, output_dir } => {
+            if let Err(err) = fingerprint::run(&images, camera, &output_dir) {
+                error!("Failed to estimate fingerprint: {}", err);
+                process::exit(1);
             }
         }
     }
 }
-
@@ -0,0 +1,125 @@
+use crate::transport::{PostError, ResultBody, ResultSink};
+use fraud_core::hex;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::task;
+
+const EXTENSION: &str = "result";
+
+/// Results that could not be posted, kept on disk until they can be so
+/// they survive restarts. Every file is a JSON header line followed by the
+/// result document.
+pub struct ResultQueue {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    job_id: String,
+    idempotency_key: String,
+    // Missing from results queued before it was kept
+    #[serde(default)]
+    correlation_id: Option<String>,
+}
+
+fn read_header(path: &Path) -> io::Result<Header> {
+    let mut line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+// Streams the document after the header, the file is opened again for every post
+fn document(path: PathBuf) -> ResultBody {
+    ResultBody::new(move |writer| {
+        let mut reader = BufReader::new(File::open(&path)?);
+        reader.read_line(&mut String::new())?;
+        io::copy(&mut reader, writer).map(|_| ())
+    })
+}
+
+impl ResultQueue {
+    pub fn open(dir: PathBuf) -> io::Result<ResultQueue> {
+        fs::create_dir_all(&dir)?;
+        Ok(ResultQueue { dir })
+    }
+
+    /// Writes `result` out to be posted later, replacing an earlier copy with the same key.
+    pub async fn push(
+        &self,
+        job_id: &str,
+        idempotency_key: &str,
+        correlation_id: Option<&str>,
+        result: &ResultBody,
+    ) -> io::Result<()> {
+        let name = hex(&Sha256::digest(idempotency_key));
+        let path = self.dir.join(name).with_extension(EXTENSION);
+        let header = Header {
+            job_id: job_id.to_string(),
+            idempotency_key: idempotency_key.to_string(),
+            correlation_id: correlation_id.map(str::to_string),
+        };
+        let result = result.clone();
+        // Renamed into place once complete, so a crash never leaves half a result to post
+        task::spawn_blocking(move || {
+            let partial = path.with_extension("partial");
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            serde_json::to_writer(&mut writer, &header)?;
+            writer.write_all(b"\n")?;
+            result.write(&mut writer)?;
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+            fs::rename(&partial, &path)
+        })
+        .await?
+    }
+
+    fn queued(&self) -> io::Result<Vec<PathBuf>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|extension| extension == EXTENSION) {
+                entries.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        entries.sort();
+        Ok(entries.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Posts queued results oldest first, stopping at the first that fails
+    /// for a reason that may pass. Refused results are dropped.
+    pub async fn deliver(&self, results: &dyn ResultSink) {
+        let queued = match self.queued() {
+            Ok(queued) => queued,
+            Err(err) => {
+                error!("Failed to list queued results in {}: {}", self.dir.display(), err);
+                return;
+            }
+        };
+        for path in queued {
+            let header = match read_header(&path) {
+                Ok(header) => header,
+                Err(err) => {
+                    error!("Dropping unreadable queued result {}: {}", path.display(), err);
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+            };
+            let job_id = &header.job_id;
+            let correlation_id = header.correlation_id.as_deref();
+            match results.post_result(job_id, &header.idempotency_key, correlation_id, &document(path.clone())).await {
+                Ok(()) => info!("{}: Posted queued result", job_id),
+                Err(PostError::Transient(err)) => {
+                    error!("{}: {}, keeping queued results for later", job_id, err);
+                    return;
+                }
+                Err(PostError::Rejected(err)) => error!("{}: {}, dropping the queued result", job_id, err),
+            }
+            if let Err(err) = fs::remove_file(&path) {
+                error!("{}: Failed to remove queued result {}: {}", job_id, path.display(), err);
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use fraud_core::{hex, timestamp};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, RequestBuilder, Url};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::time::SystemTime;
+
+// Payloads are sent over TLS, S3 accepts them without hashing the body up front
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Credentials and endpoint for `s3://bucket/key` references, requests are
+/// signed with AWS Signature Version 4 and sent path-style.
+#[derive(Clone)]
+pub struct S3 {
+    pub region: String,
+    /// Defaults to AWS, set for S3 compatible stores.
+    pub endpoint: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encodes everything but unreserved characters, and `/` when encoding a path
+fn uri_encode(value: &str, path: bool) -> String {
+    value.bytes().fold(String::new(), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if path => encoded.push('/'),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+        encoded
+    })
+}
+
+// The `YYYYMMDD'T'HHMMSS'Z'` timestamp requests are signed for, the RFC 3339 one without separators and milliseconds
+fn amz_date(now: SystemTime) -> String {
+    format!("{}Z", timestamp(now)[..19].replace(['-', ':'], ""))
+}
+
+/// Splits `s3://bucket/key` into its bucket and key.
+pub fn parse_s3_url(url: &str) -> Option<(&str, &str)> {
+    let (bucket, key) = url.strip_prefix("s3://")?.split_once('/')?;
+    (!bucket.is_empty() && !key.is_empty()).then_some((bucket, key))
+}
+
+impl S3 {
+    fn object_url(&self, bucket: &str, key: &str) -> Result<Url, String> {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://s3.{}.amazonaws.com", self.region),
+        };
+        let url = format!("{}/{}/{}", endpoint, uri_encode(bucket, false), uri_encode(key, true));
+        Url::parse(&url).map_err(|err| format!("Invalid S3 endpoint {}: {}", endpoint, err))
+    }
+
+    /// A request for an object with the signature headers set, the body is left to the caller.
+    pub fn request(&self, client: &Client, method: Method, bucket: &str, key: &str) -> Result<RequestBuilder, String> {
+        let url = self.object_url(bucket, key)?;
+        Ok(self.signed(client, method, url, "s3", UNSIGNED_PAYLOAD, &[]))
+    }
+
+    /// A request to `service`, like `kms`, signed for the body of SHA-256
+    /// `payload_sha256` along with `extra_headers`, whose names are lowercase.
+    pub fn signed(
+        &self,
+        client: &Client,
+        method: Method,
+        url: Url,
+        service: &str,
+        payload_sha256: &str,
+        extra_headers: &[(&str, &str)],
+    ) -> RequestBuilder {
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let timestamp = amz_date(SystemTime::now());
+        let date = &timestamp[..8];
+
+        let mut headers = vec![
+            ("host", host.as_str()),
+            ("x-amz-content-sha256", payload_sha256),
+            ("x-amz-date", timestamp.as_str()),
+        ];
+        headers.extend_from_slice(extra_headers);
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        // Signed in order of their names
+        headers.sort_by_key(|(name, _)| *name);
+        let canonical_headers: String =
+            headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, url.path(), canonical_headers, signed_headers, payload_sha256
+        );
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp, scope, hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        for part in [self.region.as_str(), service, "aws4_request"] {
+            key = hmac(&key, part);
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, hex(&hmac(&key, &string_to_sign))
+        );
+
+        let mut request = client.request(method, url).header("Authorization", authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, *value);
+        }
+        request
+    }
+}
@@ -0,0 +1,57 @@
+use crate::config::Config;
+use fraud_core::{decode_image, DetectorSummary, MetadataFinding, RegionReport};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct ScanReport<'a> {
+    path: &'a Path,
+    result: &'a str,
+    confidence: f64,
+    severity: u8,
+    regions: &'a [RegionReport],
+    findings: &'a [String],
+    metadata_findings: &'a [MetadataFinding],
+    scores: &'a BTreeMap<String, f64>,
+    measurements: &'a BTreeMap<String, f64>,
+    detectors: &'a [DetectorSummary],
+    annotated_path: PathBuf,
+}
+
+fn annotated_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("image");
+    path.with_file_name(format!("{}.annotated.png", stem))
+}
+
+/// Runs the detection pipeline on a local file, writes the annotated image
+/// to `output` (or next to the input) and prints the verdict and regions as JSON.
+pub fn run(config: &Config, path: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let image_data = fs::read(path)?;
+    let job_id = path.display().to_string();
+    let analysis = config.pipeline()?.analyze(&job_id, &image_data)?;
+
+    let annotated_path = output.unwrap_or_else(|| annotated_path(path));
+    match &analysis.annotated {
+        Some(image_buffer) => image_buffer.save(&annotated_path)?,
+        None => decode_image(&image_data)?.save(&annotated_path)?,
+    }
+
+    let report = ScanReport {
+        path,
+        result: &analysis.result,
+        confidence: analysis.confidence,
+        severity: analysis.severity,
+        regions: &analysis.regions,
+        findings: &analysis.findings,
+        metadata_findings: &analysis.metadata,
+        scores: &analysis.scores,
+        measurements: &analysis.measurements,
+        detectors: &analysis.detectors,
+        annotated_path,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
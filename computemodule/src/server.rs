@@ -0,0 +1,169 @@
+use axum::body::Bytes;
+use axum::extract::multipart::Field;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use crate::handlers::{self, Base64Image, JobOverrides};
+use fraud_core::{encode_base64, output_mime_type, CancelToken, JobError, JobSettings, Pipeline, QueryResult, BUFFERS};
+use log::{error, info};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::task;
+
+pub struct Server {
+    pub listen_addr: SocketAddr,
+    pub max_body_bytes: usize,
+    pub pipeline: Pipeline,
+}
+
+struct AppState {
+    pipeline: Pipeline,
+    requests: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct DetectRequest {
+    enc_img_in: Base64Image,
+    #[serde(flatten)]
+    overrides: JobOverrides,
+}
+
+fn failure(status: StatusCode, err: &JobError) -> (StatusCode, Json<QueryResult>) {
+    (status, Json(QueryResult::failed(err)))
+}
+
+fn content_type(headers: &HeaderMap) -> &str {
+    headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default()
+}
+
+fn accepts_multipart(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("multipart/mixed"))
+}
+
+async fn json_field<T: DeserializeOwned>(field: Field<'_>, name: &str) -> Result<T, String> {
+    let value = field.bytes().await.map_err(|err| err.body_text())?;
+    serde_json::from_slice(&value).map_err(|err| format!("Invalid {}: {}", name, err))
+}
+
+// Accepts `{"enc_img_in": "<base64>"}` when sent as JSON, a form with an `image` file when sent as
+// multipart, or else the raw image. JSON and forms may carry a `sensitivity`, `output`, `annotation` and
+// `locale` overriding the configured ones.
+async fn read_request(state: &Arc<AppState>, request: Request) -> Result<(Vec<u8>, JobSettings), String> {
+    let content_type = content_type(request.headers());
+    let (image_data, overrides) = if content_type.starts_with("application/json") {
+        let body = Bytes::from_request(request, state).await.map_err(|err| err.body_text())?;
+        let request: DetectRequest =
+            serde_json::from_slice(&body).map_err(|err| format!("Invalid request body: {}", err))?;
+        // The body is only needed until the image is decoded from it
+        drop(body);
+        (request.enc_img_in.decoded()?, request.overrides)
+    } else if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(request, state).await.map_err(|err| err.body_text())?;
+        let mut image_data = None;
+        let mut overrides = JobOverrides::default();
+        while let Some(field) = multipart.next_field().await.map_err(|err| err.body_text())? {
+            match field.name() {
+                Some("image") => image_data = Some(field.bytes().await.map_err(|err| err.body_text())?.to_vec()),
+                Some("sensitivity") => overrides.sensitivity = json_field(field, "sensitivity").await?,
+                Some("output") => overrides.output = json_field(field, "output").await?,
+                Some("annotation") => overrides.annotation = json_field(field, "annotation").await?,
+                Some("locale") => {
+                    let locale = field.text().await.map_err(|err| err.body_text())?;
+                    overrides.locale = Some(locale.parse().map_err(|err| format!("Invalid locale: {}", err))?);
+                }
+                _ => {}
+            }
+        }
+        (image_data.ok_or("Form has no image field")?, overrides)
+    } else {
+        let body = Bytes::from_request(request, state).await.map_err(|err| err.body_text())?;
+        (body.to_vec(), JobOverrides::default())
+    };
+    Ok((image_data, overrides.apply(&state.pipeline)?))
+}
+
+// The result as JSON followed by the output image as a raw part of its own, instead of base64 in the JSON.
+// The image part is left out when the image is skipped.
+fn multipart_response(result: &QueryResult, image_out: Vec<u8>) -> Response {
+    let json = match serde_json::to_vec(result) {
+        Ok(json) => json,
+        Err(err) => {
+            return failure(StatusCode::INTERNAL_SERVER_ERROR, &JobError::Internal(err.to_string())).into_response()
+        }
+    };
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+    let boundary = format!("fraud-result-{:x}", nanos);
+    let image_type = output_mime_type(&image_out);
+    let mut body = Vec::with_capacity(json.len() + image_out.len() + 256);
+    body.extend_from_slice(format!("--{}\r\nContent-Type: application/json\r\n\r\n", boundary).as_bytes());
+    body.extend_from_slice(&json);
+    if !image_out.is_empty() {
+        body.extend_from_slice(format!("\r\n--{}\r\nContent-Type: {}\r\n\r\n", boundary, image_type).as_bytes());
+        body.extend_from_slice(&image_out);
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type = format!("multipart/mixed; boundary={}", boundary);
+    (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+// Stops the job when the request is dropped, as it is once the client disconnects
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Responds with JSON, or with multipart/mixed when the client accepts it
+async fn detect(State(state): State<Arc<AppState>>, request: Request) -> Response {
+    let request_id = format!("request-{}", state.requests.fetch_add(1, Ordering::Relaxed));
+    let multipart = accepts_multipart(request.headers());
+    let (image_data, settings) = match read_request(&state, request).await {
+        Ok(input) => input,
+        Err(err) => return failure(StatusCode::BAD_REQUEST, &JobError::Invalid(err)).into_response(),
+    };
+
+    info!("{}: Received {} byte image", request_id, image_data.len());
+    let _cancel_on_drop = CancelOnDrop(settings.cancel.clone());
+    let res = task::spawn_blocking(move || {
+        let res = state.pipeline.detect_raw(&request_id, &image_data, &settings);
+        BUFFERS.give(image_data);
+        res
+    })
+    .await;
+    let res = res.unwrap_or_else(|err| Err(handlers::join_error(err)));
+
+    match res {
+        Ok((result, image_out)) if multipart => multipart_response(&result, image_out),
+        Ok((result, image_out)) => {
+            let enc_img_out = encode_base64(&image_out);
+            BUFFERS.give(image_out);
+            (StatusCode::OK, Json(QueryResult { enc_img_out, ..result })).into_response()
+        }
+        Err(err @ JobError::Internal(_)) => failure(StatusCode::INTERNAL_SERVER_ERROR, &err).into_response(),
+        Err(err) => failure(StatusCode::UNPROCESSABLE_ENTITY, &err).into_response(),
+    }
+}
+
+pub async fn run(server: Server) -> std::io::Result<()> {
+    let state = Arc::new(AppState { pipeline: server.pipeline, requests: AtomicU64::new(0) });
+    let app = Router::new()
+        .route("/detect", post(detect))
+        .layer(DefaultBodyLimit::max(server.max_body_bytes))
+        .with_state(state);
+
+    let listener = TcpListener::bind(server.listen_addr).await?;
+    info!("Listening on {}", server.listen_addr);
+    axum::serve(listener, app).await.inspect_err(|err| error!("Server failed: {}", err))
+}
@@ -0,0 +1,235 @@
+use crate::audit;
+use crate::s3::S3;
+use base64::engine::general_purpose;
+use base64::Engine as _;
+use fraud_core::{QueryResult, RegionReport, ResultSignature};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How results are signed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Ed25519,
+    HmacSha256,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Ed25519 => "ed25519",
+            Algorithm::HmacSha256 => "hmac-sha256",
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "ed25519" => Ok(Algorithm::Ed25519),
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            _ => Err(String::from("expected ed25519 or hmac-sha256")),
+        }
+    }
+}
+
+/// Where the key results are signed with is kept.
+pub enum KeySource {
+    /// A PKCS#8 Ed25519 private key, PEM or DER, or the whole file as the HMAC secret.
+    File(PathBuf),
+    /// An AWS KMS key, used through the KMS API with the `s3` credentials. The
+    /// endpoint defaults to the one of their region.
+    Kms { key_id: String, endpoint: Option<String> },
+}
+
+pub struct SigningConfig {
+    /// Put into every signature, for consumers to pick the key to verify it with.
+    pub key_id: String,
+    pub algorithm: Algorithm,
+    pub key: KeySource,
+}
+
+enum Key {
+    Ed25519(Ed25519KeyPair),
+    Hmac(Vec<u8>),
+    Kms { key_id: String, url: Url, aws: S3 },
+}
+
+/// Signs the verdicts of jobs before they are posted.
+pub struct ResultSigner {
+    key_id: String,
+    algorithm: Algorithm,
+    key: Key,
+}
+
+// What is signed, the verdict and hashes of what is too large to repeat
+#[derive(Serialize)]
+struct Claims<'a> {
+    key_id: &'a str,
+    algorithm: &'static str,
+    job_id: &'a str,
+    input_sha256: Option<&'a str>,
+    result: &'a str,
+    confidence: f64,
+    severity: u8,
+    regions: &'a [RegionReport],
+    // Of the base64 as sent
+    enc_img_out_sha256: String,
+    img_out_url: Option<&'a str>,
+    batch: Vec<Verdict<'a>>,
+    pages: Vec<Verdict<'a>>,
+    files: Vec<Verdict<'a>>,
+}
+
+#[derive(Serialize)]
+struct Verdict<'a> {
+    result: &'a str,
+    confidence: f64,
+    severity: u8,
+    regions: &'a [RegionReport],
+}
+
+// The DER of a PEM file, or the file itself
+fn der(file: Vec<u8>) -> Result<Vec<u8>, String> {
+    let Some(pem) = std::str::from_utf8(&file).ok().filter(|text| text.trim_start().starts_with("-----BEGIN")) else {
+        return Ok(file);
+    };
+    let body: String = pem.lines().filter(|line| !line.starts_with("-----")).map(str::trim).collect();
+    general_purpose::STANDARD.decode(body).map_err(|err| format!("invalid PEM: {}", err))
+}
+
+impl ResultSigner {
+    /// KMS keys need `aws` credentials.
+    pub fn load(config: SigningConfig, aws: Option<S3>) -> Result<ResultSigner, String> {
+        let SigningConfig { key_id, algorithm, key } = config;
+        let key = match key {
+            KeySource::File(path) => {
+                let file = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                match algorithm {
+                    // OpenSSL writes keys without their public half, which ring checks when it is there
+                    Algorithm::Ed25519 => Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der(file)?)
+                        .map(Key::Ed25519)
+                        .map_err(|err| format!("{}: invalid Ed25519 key: {}", path.display(), err))?,
+                    Algorithm::HmacSha256 => Key::Hmac(file),
+                }
+            }
+            KeySource::Kms { key_id, endpoint } => {
+                let aws = aws.ok_or("KMS keys need AWS credentials")?;
+                let endpoint = endpoint.unwrap_or_else(|| format!("https://kms.{}.amazonaws.com", aws.region));
+                let url = Url::parse(&endpoint).map_err(|err| format!("Invalid KMS endpoint {}: {}", endpoint, err))?;
+                Key::Kms { key_id, url, aws }
+            }
+        };
+        Ok(ResultSigner { key_id, algorithm, key })
+    }
+
+    /// The base64 Ed25519 public key consumers verify with, none for HMAC and KMS keys.
+    pub fn public_key(&self) -> Option<String> {
+        match &self.key {
+            Key::Ed25519(pair) => Some(general_purpose::STANDARD.encode(pair.public_key())),
+            _ => None,
+        }
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Signs the same result the same way every time, so posting it again
+    /// for a redelivered job still matches the first post.
+    pub async fn sign(
+        &self,
+        client: &Client,
+        job_id: &str,
+        input_sha256: Option<&str>,
+        result: &QueryResult,
+    ) -> Result<ResultSignature, String> {
+        let verdict = |result, confidence, severity, regions| Verdict { result, confidence, severity, regions };
+        let claims = Claims {
+            key_id: &self.key_id,
+            algorithm: self.algorithm.name(),
+            job_id,
+            input_sha256,
+            result: &result.result,
+            confidence: result.confidence,
+            severity: result.severity,
+            regions: &result.regions,
+            enc_img_out_sha256: audit::sha256(result.enc_img_out.as_bytes()),
+            img_out_url: result.img_out_url.as_deref(),
+            batch: result
+                .batch
+                .iter()
+                .map(|image| verdict(&image.result, image.confidence, image.severity, &image.regions))
+                .collect(),
+            pages: result
+                .pages
+                .iter()
+                .map(|page| verdict(&page.result, page.confidence, page.severity, &page.regions))
+                .collect(),
+            files: result
+                .files
+                .iter()
+                .map(|file| verdict(&file.result, file.confidence, file.severity, &file.regions))
+                .collect(),
+        };
+        let payload = serde_json::to_string(&claims).map_err(|err| err.to_string())?;
+        let signature = match &self.key {
+            Key::Ed25519(pair) => pair.sign(payload.as_bytes()).as_ref().to_vec(),
+            Key::Hmac(secret) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(payload.as_bytes());
+                mac.finalize().into_bytes().to_vec()
+            }
+            Key::Kms { key_id, url, aws } => kms_sign(client, aws, url, key_id, self.algorithm, &payload).await?,
+        };
+        Ok(ResultSignature {
+            key_id: self.key_id.clone(),
+            algorithm: self.algorithm.name().to_string(),
+            payload,
+            signature: general_purpose::STANDARD.encode(signature),
+        })
+    }
+}
+
+// Sign for Ed25519 keys and GenerateMac for HMAC ones, their messages and answers base64 encoded
+async fn kms_sign(
+    client: &Client,
+    aws: &S3,
+    url: &Url,
+    key_id: &str,
+    algorithm: Algorithm,
+    payload: &str,
+) -> Result<Vec<u8>, String> {
+    let message = general_purpose::STANDARD.encode(payload);
+    let (action, body, field) = match algorithm {
+        Algorithm::Ed25519 => (
+            "Sign",
+            json!({"KeyId": key_id, "Message": message, "MessageType": "RAW", "SigningAlgorithm": "ED25519_SHA_512"}),
+            "Signature",
+        ),
+        Algorithm::HmacSha256 => {
+            ("GenerateMac", json!({"KeyId": key_id, "Message": message, "MacAlgorithm": "HMAC_SHA_256"}), "Mac")
+        }
+    };
+    let body = body.to_string();
+    let target = format!("TrentService.{}", action);
+    let headers = [("content-type", "application/x-amz-json-1.1"), ("x-amz-target", target.as_str())];
+    let request = aws.signed(client, Method::POST, url.clone(), "kms", &audit::sha256(body.as_bytes()), &headers);
+    let response = request.body(body).send().await.map_err(|err| format!("KMS {} failed: {}", action, err))?;
+    let status = response.status();
+    let text = response.text().await.map_err(|err| format!("KMS {} failed: {}", action, err))?;
+    if !status.is_success() {
+        return Err(format!("KMS {} answered {}: {}", action, status, text));
+    }
+    let answer: Value = serde_json::from_str(&text).map_err(|err| format!("Invalid KMS {} answer: {}", action, err))?;
+    let encoded = answer[field].as_str().ok_or_else(|| format!("KMS {} answered without {}", action, field))?;
+    general_purpose::STANDARD.decode(encoded).map_err(|err| format!("Invalid KMS {} answer: {}", action, err))
+}
@@ -0,0 +1,60 @@
+use log::info;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+// Counted by the minute, the summary covers the last hour
+const WINDOW_MINUTES: u64 = 60;
+
+/// How many jobs finished with each result over the last hour, e.g. `clean`,
+/// `edited`, `cropped` or `failed`.
+#[derive(Default)]
+pub struct VerdictCounts(Mutex<VecDeque<(u64, BTreeMap<String, u64>)>>);
+
+fn minute() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 60
+}
+
+impl VerdictCounts {
+    pub fn record(&self, result: &str) {
+        let now = minute();
+        let mut minutes = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while minutes.front().is_some_and(|(minute, _)| minute + WINDOW_MINUTES <= now) {
+            minutes.pop_front();
+        }
+        if minutes.back().map(|(minute, _)| *minute) != Some(now) {
+            minutes.push_back((now, BTreeMap::new()));
+        }
+        let (_, counts) = minutes.back_mut().expect("The current minute was just added");
+        *counts.entry(result.to_lowercase()).or_default() += 1;
+    }
+
+    pub fn last_hour(&self) -> BTreeMap<String, u64> {
+        let now = minute();
+        let minutes = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut total = BTreeMap::new();
+        for (_, counts) in minutes.iter().filter(|(minute, _)| minute + WINDOW_MINUTES > now) {
+            for (result, count) in counts {
+                *total.entry(result.clone()).or_default() += count;
+            }
+        }
+        total
+    }
+}
+
+/// Logs the results of the last hour every `interval`, until aborted.
+pub async fn log_summaries(counts: Arc<VerdictCounts>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let counts = counts.last_hour();
+        let jobs: u64 = counts.values().sum();
+        let get = |result: &str| counts.get(result).copied().unwrap_or(0);
+        let summary: Vec<String> = counts.iter().map(|(result, count)| format!("{} {}", result, count)).collect();
+        let summary = if summary.is_empty() { String::from("none") } else { summary.join(", ") };
+        info!(
+            jobs, clean = get("clean"), edited = get("edited"), cropped = get("cropped"), failed = get("failed");
+            "Finished {} jobs in the last hour: {}", jobs, summary
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::ca::ReloadingClient;
+use log::error;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+// Spans kept while the collector is unreachable, newer ones are dropped beyond that
+const MAX_BUFFERED_SPANS: usize = 4096;
+
+/// Spans of finished jobs, exported to an OpenTelemetry collector over
+/// OTLP/HTTP in its JSON encoding.
+pub struct Tracer {
+    client: ReloadingClient,
+    /// Base URL of the collector, e.g. `http://otel-collector:4318`.
+    endpoint: String,
+    service_name: String,
+    spans: Mutex<Vec<Value>>,
+}
+
+// Trace and span IDs only need to be unique, which the randomly keyed std hasher is enough for
+fn random_id(words: usize) -> String {
+    (0..words).map(|_| format!("{:016x}", RandomState::new().build_hasher().finish())).collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+fn attributes(attributes: &[(&str, &str)]) -> Value {
+    let attributes = attributes.iter().map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }));
+    Value::Array(attributes.collect())
+}
+
+/// The steps of a job in the order they happen.
+pub const STEPS: [&str; 6] = ["fetch", "decode", "detect", "annotate", "encode", "upload"];
+
+// The step a progress stage of the pipeline or worker belongs to, detector names are the detect step
+fn step(stage: &str) -> &'static str {
+    match stage {
+        "fetching" => "fetch",
+        "decoding" => "decode",
+        "annotating" => "annotate",
+        "encoding" => "encode",
+        "uploading" => "upload",
+        _ => "detect",
+    }
+}
+
+// Every stage with when it started and ended, which is when the next one starts and `until` for the last. Detection
+// of jobs that timed out may go on after that, those stages are left out.
+fn stage_spans(
+    stages: &[(String, SystemTime)],
+    until: SystemTime,
+) -> impl Iterator<Item = (&str, SystemTime, SystemTime)> {
+    let stages = &stages[..stages.partition_point(|(_, start)| *start <= until)];
+    let ends = stages.iter().skip(1).map(|(_, start)| *start).chain([until]);
+    stages.iter().zip(ends).map(|((stage, start), end)| (stage.as_str(), *start, end))
+}
+
+/// Milliseconds spent in each step of `stages` up to `until`, in the order of
+/// [`STEPS`] and summed over the images of multi-image inputs.
+pub fn step_timings(stages: &[(String, SystemTime)], until: SystemTime) -> Vec<(&'static str, f64)> {
+    let mut timings = STEPS.map(|step| (step, None));
+    for (stage, start, end) in stage_spans(stages, until) {
+        let elapsed = end.duration_since(start).unwrap_or_default().as_secs_f64() * 1000.0;
+        let index = STEPS.iter().position(|&known| known == step(stage)).expect("Every stage maps to a step");
+        *timings[index].1.get_or_insert(0.0) += elapsed;
+    }
+    timings.into_iter().filter_map(|(step, elapsed)| Some((step, elapsed?))).collect()
+}
+
+impl Tracer {
+    pub fn new(client: ReloadingClient, endpoint: String, service_name: String) -> Tracer {
+        Tracer { client, endpoint, service_name, spans: Mutex::default() }
+    }
+
+    /// Records the trace of a job that started at `started`, went through
+    /// `stages` as reported by its progress and was posted from `posting` on.
+    pub fn record_job(
+        &self,
+        job_id: &str,
+        started: SystemTime,
+        stages: &[(String, SystemTime)],
+        posting: SystemTime,
+        result: &str,
+    ) {
+        let trace_id = random_id(2);
+        let job_span_id = random_id(1);
+        let span = |name: &str, span_id: &str, parent: &str, start, end, attrs: &[(&str, &str)]| {
+            json!({
+                "traceId": trace_id,
+                "spanId": span_id,
+                "parentSpanId": parent,
+                "name": name,
+                // INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(start),
+                "endTimeUnixNano": unix_nanos(end),
+                "attributes": attributes(attrs),
+            })
+        };
+
+        let ended = SystemTime::now();
+        let mut job_span = span("job", &job_span_id, "", started, ended, &[("job_id", job_id), ("result", result)]);
+        if matches!(result, "Failed" | "timeout" | "dead_letter") {
+            job_span["status"] = json!({ "code": 2, "message": result });
+        }
+        let mut spans = vec![job_span];
+        for (stage, start, end) in stage_spans(stages, posting) {
+            let attrs = [("job_id", job_id), ("stage", stage)];
+            spans.push(span(step(stage), &random_id(1), &job_span_id, start, end, &attrs));
+        }
+        spans.push(span("post", &random_id(1), &job_span_id, posting, ended, &[("job_id", job_id)]));
+
+        let mut buffered = self.spans.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let room = MAX_BUFFERED_SPANS.saturating_sub(buffered.len());
+        if room < spans.len() {
+            error!("{}: Dropping trace, {} spans are waiting to be exported", job_id, buffered.len());
+            return;
+        }
+        buffered.extend(spans);
+    }
+
+    /// Exports the spans recorded so far, dropping them when the collector fails.
+    pub async fn flush(&self) {
+        let spans = mem::take(&mut *self.spans.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        if spans.is_empty() {
+            return;
+        }
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": attributes(&[("service.name", &self.service_name)]) },
+                "scopeSpans": [{ "scope": { "name": "computemodule" }, "spans": spans }],
+            }],
+        });
+        let response = self.client.current().post(format!("{}/v1/traces", self.endpoint))
+            .timeout(Duration::from_secs(10))
+            .json(&body)
+            .send()
+            .await;
+        match response {
+            Ok(res) if !res.status().is_success() => error!("Failed to export spans: {}", res.status()),
+            Ok(_) => {}
+            Err(err) => error!("Error exporting spans: {}", err),
+        }
+    }
+}
+
+/// Exports the spans of `tracer` every `interval`, until aborted.
+pub async fn export(tracer: Arc<Tracer>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        tracer.flush().await;
+    }
+}
@@ -0,0 +1,347 @@
+use crate::auth::AuthToken;
+use crate::ca::ReloadingClient;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use fraud_core::hex;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::body::{Bytes, Sender};
+use log::error;
+use reqwest::header::{HeaderName, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Body, RequestBuilder};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub const CHUNK_BYTES: usize = 64 * 1024;
+/// Largest job document read, decompressed. Jobs inline their images in
+/// base64, this allows for the largest fetch.max_bytes anyone sets.
+pub const MAX_JOB_BYTES: u64 = 256 * 1024 * 1024;
+
+/// How the worker talks to the job API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Rest,
+    /// The `computemodule.v1.Jobs` service of `proto/jobs.proto`.
+    Grpc,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "rest" => Ok(Transport::Rest),
+            "grpc" => Ok(Transport::Grpc),
+            _ => Err(String::from("expected rest or grpc")),
+        }
+    }
+}
+
+/// Header the correlation ID of a job may come with, and is sent back with its result.
+pub const CORRELATION_ID: &str = "x-correlation-id";
+
+/// A job as it was fetched.
+pub struct FetchedJob {
+    /// The JSON envelope of the job.
+    pub document: Vec<u8>,
+    /// From the `X-Correlation-ID` header the job came with, if it had one.
+    pub correlation_id: Option<String>,
+}
+
+impl FetchedJob {
+    /// A job that came without a correlation ID header.
+    pub fn new(document: Vec<u8>) -> FetchedJob {
+        FetchedJob { document, correlation_id: None }
+    }
+}
+
+/// Where the worker gets jobs from.
+pub trait JobSource: Send + Sync {
+    /// The next job, none when there was none to be had for now.
+    fn next_job(&self) -> BoxFuture<'_, Result<Option<FetchedJob>, String>>;
+
+    /// Whether jobs are waited for to be pushed right now rather than polled
+    /// for, so no job for a long time is no sign of trouble.
+    fn is_pushed(&self) -> bool {
+        false
+    }
+}
+
+/// Where the worker posts results to.
+pub trait ResultSink: Send + Sync {
+    /// Posts `result` of `job_id`, posts with the same `idempotency_key` are
+    /// retries of one another. The `correlation_id` of the job is sent along
+    /// as a header.
+    fn post_result<'a>(
+        &'a self,
+        job_id: &'a str,
+        idempotency_key: &'a str,
+        correlation_id: Option<&'a str>,
+        result: &'a ResultBody,
+    ) -> BoxFuture<'a, Result<(), PostError>>;
+}
+
+#[derive(Debug)]
+pub enum PostError {
+    /// The request failed or the server could not take the result, posting again may succeed.
+    Transient(String),
+    /// The server refused the result.
+    Rejected(String),
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PostError::Transient(message) | PostError::Rejected(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Timeouts of the requests to one endpoint, connecting is bounded by the client.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Longest the server may take to answer once the body was sent, or to send the next chunk of the answer.
+    pub read: Duration,
+    /// Bounds the whole request, sending the body and reading the answer included.
+    pub request: Duration,
+}
+
+/// `future` unless the server takes longer than `timeout` to get it done.
+pub async fn within<T, E: fmt::Display>(
+    timeout: Duration,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    match time::timeout(timeout, future).await {
+        Ok(res) => res.map_err(|err| err.to_string()),
+        Err(_) => Err(format!("No answer from the server within {:?}", timeout)),
+    }
+}
+
+/// `response` unless the server takes longer than `timeout` to answer once
+/// `uploaded`, the upload being bounded by the request timeout only.
+pub async fn after_upload<T, E: fmt::Display>(
+    timeout: Duration,
+    uploaded: impl Future,
+    response: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    tokio::pin!(response);
+    // Servers may answer early, e.g. refusing the token
+    tokio::select! {
+        res = &mut response => return res.map_err(|err| err.to_string()),
+        _ = uploaded => {}
+    }
+    within(timeout, response).await
+}
+
+type WriteResult = Arc<dyn Fn(&mut dyn Write) -> io::Result<()> + Send + Sync>;
+
+/// A result document, serialized every time it is sent so results with large
+/// images are not held in memory a second time as one string.
+#[derive(Clone)]
+pub struct ResultBody(WriteResult);
+
+// Hands the document on in chunks, failing once the request reading them is gone
+struct ChunkWriter {
+    chunk: Vec<u8>,
+    sender: Sender,
+    runtime: Handle,
+}
+
+impl Write for ChunkWriter {
+    // Takes at most what fits the chunk, base64 images are written in one go otherwise
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_BYTES - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        if self.chunk.len() == CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_BYTES)));
+        let sent = self.runtime.block_on(self.sender.send_data(chunk));
+        sent.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Upload stopped"))
+    }
+}
+
+impl ResultBody {
+    pub fn new(write: impl Fn(&mut dyn Write) -> io::Result<()> + Send + Sync + 'static) -> ResultBody {
+        ResultBody(Arc::new(write))
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (self.0)(writer)
+    }
+
+    /// The whole document, for transports that need its length up front.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Hex SHA-256 of the document.
+    pub fn sha256(&self) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        (self.0)(&mut hasher)?;
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// A chunked body, serialized on a blocking thread at most a chunk ahead of
+    /// the upload and gzipped if `gzip`, along with that thread, done once the
+    /// last chunk was handed on.
+    pub fn to_stream(&self, gzip: bool) -> (Body, JoinHandle<()>) {
+        let (sender, body) = hyper::Body::channel();
+        let runtime = Handle::current();
+        let document = self.clone();
+        let writing = task::spawn_blocking(move || {
+            let mut writer = ChunkWriter { chunk: Vec::with_capacity(CHUNK_BYTES), sender, runtime };
+            let written = if gzip {
+                let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+                (document.0)(&mut encoder).and_then(|()| encoder.finish().map(|_| ()))
+            } else {
+                (document.0)(&mut writer)
+            };
+            // Fails the request instead of ending it with a truncated document
+            if let Err(err) = written.and_then(|()| writer.flush()) {
+                error!("Stopped writing result: {}", err);
+                writer.sender.abort();
+            }
+        });
+        (Body::from(body), writing)
+    }
+}
+
+// Job documents are decoded whatever was asked for, `deflate` being zlib in HTTP. Up to MAX_JOB_BYTES, so small
+// compressed answers can't exhaust memory.
+fn decode(content_encoding: Option<&str>, body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    let read = match content_encoding {
+        None | Some("identity") => return Ok(body.to_vec()),
+        Some("gzip") => GzDecoder::new(body).take(MAX_JOB_BYTES + 1).read_to_end(&mut decoded),
+        Some("deflate") => ZlibDecoder::new(body).take(MAX_JOB_BYTES + 1).read_to_end(&mut decoded),
+        Some(other) => return Err(format!("Unsupported Content-Encoding: {}", other)),
+    };
+    read.map_err(|err| format!("Failed to decompress job: {}", err))?;
+    if decoded.len() as u64 > MAX_JOB_BYTES {
+        return Err(format!("Job is larger than {} bytes decompressed", MAX_JOB_BYTES));
+    }
+    Ok(decoded)
+}
+
+/// Jobs are polled with GET, `204 No Content` when there is none, and results
+/// POSTed with the job ID appended.
+pub struct Rest {
+    pub client: ReloadingClient,
+    pub get_job_uri: String,
+    pub post_result_uri: String,
+    pub module_auth_token: AuthToken,
+    /// Ask for gzipped jobs and gzip results.
+    pub compression: bool,
+    pub get_job_timeouts: Timeouts,
+    pub post_result_timeouts: Timeouts,
+}
+
+impl Rest {
+    // Sets `header` only with compression enabled
+    fn compressed(&self, request: RequestBuilder, header: HeaderName, value: &'static str) -> RequestBuilder {
+        if self.compression {
+            request.header(header, value)
+        } else {
+            request
+        }
+    }
+
+    /// Asks for a job once, none when there is none yet.
+    pub async fn poll(&self) -> Result<Option<FetchedJob>, String> {
+        let Timeouts { read, request: timeout } = self.get_job_timeouts;
+        let token = self.module_auth_token.current();
+        let request = self.client.current().get(&self.get_job_uri)
+            .timeout(timeout)
+            .header("Module-Auth-Token", &token);
+        let mut response = within(read, self.compressed(request, ACCEPT_ENCODING, "gzip, deflate").send()).await?;
+
+        match response.status().as_u16() {
+            200 => {
+                let header = response.headers().get(CONTENT_ENCODING).map(|value| value.to_str().map(str::to_string));
+                let content_encoding = header.transpose().map_err(|err| format!("Invalid Content-Encoding: {}", err))?;
+                let correlation_id = response.headers().get(CORRELATION_ID).and_then(|value| value.to_str().ok());
+                let correlation_id = correlation_id.map(str::to_string);
+                let mut body = Vec::new();
+                while let Some(chunk) = within(read, response.chunk()).await? {
+                    if (body.len() + chunk.len()) as u64 > MAX_JOB_BYTES {
+                        return Err(format!("Job is larger than {} bytes", MAX_JOB_BYTES));
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                let document = decode(content_encoding.as_deref(), &body)?;
+                Ok(Some(FetchedJob { document, correlation_id }))
+            }
+            204 => Ok(None),
+            401 | 403 if self.module_auth_token.refresh(&token) => {
+                Err(format!("Token refused with {}, retrying with the reloaded one", response.status()))
+            }
+            _ => Err(format!("Unexpected status code: {}", response.status())),
+        }
+    }
+}
+
+impl JobSource for Rest {
+    fn next_job(&self) -> BoxFuture<'_, Result<Option<FetchedJob>, String>> {
+        Box::pin(self.poll())
+    }
+}
+
+impl ResultSink for Rest {
+    fn post_result<'a>(
+        &'a self,
+        job_id: &'a str,
+        idempotency_key: &'a str,
+        correlation_id: Option<&'a str>,
+        result: &'a ResultBody,
+    ) -> BoxFuture<'a, Result<(), PostError>> {
+        Box::pin(async move {
+            let Timeouts { read, request: timeout } = self.post_result_timeouts;
+            let token = self.module_auth_token.current();
+            let request = self.client.current().post(format!("{}/{}", self.post_result_uri, job_id))
+                .timeout(timeout)
+                .header("Module-Auth-Token", &token)
+                .header("Idempotency-Key", idempotency_key)
+                .header(CONTENT_TYPE, "application/octet-stream");
+            let request = match correlation_id {
+                Some(correlation_id) => request.header(CORRELATION_ID, correlation_id),
+                None => request,
+            };
+            let (body, uploaded) = result.to_stream(self.compression);
+            let request = self.compressed(request, CONTENT_ENCODING, "gzip").body(body);
+            let response = after_upload(read, uploaded, request.send())
+                .await
+                .map_err(|err| PostError::Transient(format!("Error posting result: {}", err)))?;
+
+            let status = response.status();
+            match status.as_u16() {
+                204 => Ok(()),
+                401 | 403 if self.module_auth_token.refresh(&token) => {
+                    Err(PostError::Transient(format!("Token refused with {}, retrying with the reloaded one", status)))
+                }
+                408 | 429 | 500..=599 => Err(PostError::Transient(format!("Failed to post result: {}", status))),
+                _ => Err(PostError::Rejected(format!("Failed to post result: {}", status))),
+            }
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use base64::engine::general_purpose;
+use base64::Engine as _;
+use crate::auth::AuthToken;
+use crate::ca::ReloadingClient;
+use crate::transport::{BoxFuture, FetchedJob, JobSource, Rest, MAX_JOB_BYTES};
+use log::{error, info};
+use reqwest::header::{CONNECTION, UPGRADE};
+use reqwest::{StatusCode, Upgraded};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+// Appended to the handshake key by servers, see RFC 6455 section 1.3
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Jobs pushed by the server over a WebSocket, one job document per message.
+/// While the socket is down jobs are polled from `polling` instead, and
+/// reconnecting is tried again every `reconnect_interval`.
+pub struct PushedJobs {
+    client: ReloadingClient,
+    uri: String,
+    module_auth_token: AuthToken,
+    reconnect_interval: Duration,
+    polling: Arc<Rest>,
+    socket: Mutex<Socket>,
+    // Whether `socket` has a connection, which can't be told while it is locked waiting for a job
+    connected: AtomicBool,
+}
+
+struct Socket {
+    connection: Option<Upgraded>,
+    retry_at: Instant,
+}
+
+// Clears `connected` when dropped, unless defused once the read it guards finished
+struct Disconnect<'a>(Option<&'a AtomicBool>);
+
+impl Drop for Disconnect<'_> {
+    fn drop(&mut self) {
+        if let Some(connected) = self.0 {
+            connected.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+// Only for checking the handshake answer of the server, which is all WebSockets use SHA-1 for
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+// Keys and masks only need to be unpredictable to proxies, the randomly keyed std hasher is enough for that
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_be_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+// Frames from clients are always masked, see RFC 6455 section 5.3
+async fn write_frame(connection: &mut Upgraded, opcode: u8, payload: &[u8]) -> Result<(), String> {
+    let mask: [u8; 4] = random_bytes();
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(byte, mask)| byte ^ mask));
+    connection.write_all(&frame).await.map_err(|err| err.to_string())
+}
+
+// The next text or binary message, answering pings on the way
+async fn read_message(connection: &mut Upgraded) -> Result<Vec<u8>, String> {
+    let mut message = Vec::new();
+    loop {
+        let mut head = [0; 2];
+        connection.read_exact(&mut head).await.map_err(|err| err.to_string())?;
+        let (fin, opcode) = (head[0] & 0x80 != 0, head[0] & 0x0f);
+        // Server frames are never masked, see RFC 6455 section 5.1
+        if head[1] & 0x80 != 0 {
+            return Err(String::from("Server sent a masked frame"));
+        }
+        let len = match head[1] & 0x7f {
+            126 => u64::from(connection.read_u16().await.map_err(|err| err.to_string())?),
+            127 => connection.read_u64().await.map_err(|err| err.to_string())?,
+            len => u64::from(len),
+        };
+        if message.len() as u64 + len > MAX_JOB_BYTES {
+            return Err(format!("Message is larger than {} bytes", MAX_JOB_BYTES));
+        }
+        let mut payload = vec![0; len as usize];
+        connection.read_exact(&mut payload).await.map_err(|err| err.to_string())?;
+
+        match opcode {
+            TEXT | BINARY | CONTINUATION => {
+                message.extend_from_slice(&payload);
+                if fin {
+                    return Ok(message);
+                }
+            }
+            PING => write_frame(connection, PONG, &payload).await?,
+            PONG => {}
+            CLOSE => {
+                // Echoing the status code, the connection is dropped either way
+                let _ = write_frame(connection, CLOSE, &payload[..payload.len().min(2)]).await;
+                return Err(String::from("Closed by the server"));
+            }
+            opcode => return Err(format!("Unsupported opcode {:#x}", opcode)),
+        }
+    }
+}
+
+impl PushedJobs {
+    /// `uri` is a `ws://` or `wss://` URL, `client` must only speak HTTP/1.1 so
+    /// the connection can be upgraded.
+    pub fn new(
+        client: ReloadingClient,
+        uri: String,
+        module_auth_token: AuthToken,
+        reconnect_interval: Duration,
+        polling: Arc<Rest>,
+    ) -> PushedJobs {
+        let socket = Mutex::new(Socket { connection: None, retry_at: Instant::now() });
+        let connected = AtomicBool::new(false);
+        PushedJobs { client, uri, module_auth_token, reconnect_interval, polling, socket, connected }
+    }
+
+    async fn connect(&self) -> Result<Upgraded, String> {
+        let url = match self.uri.split_once("://") {
+            Some(("ws", rest)) => format!("http://{}", rest),
+            Some(("wss", rest)) => format!("https://{}", rest),
+            _ => return Err(format!("Not a ws:// or wss:// URL: {}", self.uri)),
+        };
+        let key = general_purpose::STANDARD.encode(random_bytes::<16>());
+        let token = self.module_auth_token.current();
+        let response = self.client.current().get(url)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", &key)
+            .header("Module-Auth-Token", &token)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        if matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            // Connecting waits for the reconnect interval, polling meanwhile uses the reloaded token
+            self.module_auth_token.refresh(&token);
+        }
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(format!("Unexpected status code: {}", response.status()));
+        }
+        let accept = general_purpose::STANDARD.encode(sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()));
+        if response.headers().get("Sec-WebSocket-Accept").and_then(|value| value.to_str().ok()) != Some(&accept) {
+            return Err(String::from("Server answered the handshake with the wrong Sec-WebSocket-Accept"));
+        }
+        response.upgrade().await.map_err(|err| err.to_string())
+    }
+}
+
+impl JobSource for PushedJobs {
+    fn next_job(&self) -> BoxFuture<'_, Result<Option<FetchedJob>, String>> {
+        Box::pin(async move {
+            let mut socket = self.socket.lock().await;
+            loop {
+                if socket.connection.is_none() && Instant::now() >= socket.retry_at {
+                    match self.connect().await {
+                        Ok(connection) => {
+                            info!("Connected to job socket {}", self.uri);
+                            socket.connection = Some(connection);
+                            self.connected.store(true, Ordering::Relaxed);
+                        }
+                        Err(err) => {
+                            error!("Failed to connect to job socket, polling instead: {}", err);
+                            socket.retry_at = Instant::now() + self.reconnect_interval;
+                        }
+                    }
+                }
+                // Out of the socket while reading, so a read cancelled midway, e.g. at shutdown, drops the
+                // connection instead of leaving the rest of its frame to be read as the next one
+                let Some(mut connection) = socket.connection.take() else {
+                    return self.polling.poll().await;
+                };
+                let mut disconnect = Disconnect(Some(&self.connected));
+                match read_message(&mut connection).await {
+                    Ok(job) => {
+                        disconnect.0 = None;
+                        socket.connection = Some(connection);
+                        return Ok(Some(FetchedJob::new(job)));
+                    }
+                    Err(err) => {
+                        error!("Job socket dropped, polling instead: {}", err);
+                        socket.retry_at = Instant::now() + self.reconnect_interval;
+                    }
+                }
+            }
+        })
+    }
+
+    fn is_pushed(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
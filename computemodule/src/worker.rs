@@ -0,0 +1,789 @@
+use crate::artifacts::ArtifactDir;
+use crate::audit::{self, AuditLog};
+use crate::auth::AuthToken;
+use crate::cache::ResultCache;
+use crate::ca::ReloadingClient;
+use crate::fetch::{fetch, upload, FetchLimits};
+use crate::handlers::{self, Base64Image, Handlers, JobOverrides};
+use crate::health::WorkerStatus;
+use crate::logging::{self, Correlated};
+use crate::queue::ResultQueue;
+use crate::s3::S3;
+use crate::signing::ResultSigner;
+use crate::stats::VerdictCounts;
+use crate::telemetry::{self, Tracer};
+use crate::transport::{FetchedJob, JobSource, PostError, ResultBody, ResultSink};
+use fraud_core::{
+    batch_result, decode_base64_into, Artifacts, CancelToken, ErrorReport, JobError, JobSettings, Pipeline, Progress, ProgressUpdate,
+    QueryResult, BUFFERS,
+};
+use log::{debug, error, info};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::panic;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::task;
+use tokio::time::error::Elapsed;
+use tokio::time::{sleep, timeout};
+
+// Keyed by the schema version, both carry the same fields
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Job {
+    ComputeModuleJobV1(ComputeModuleJob),
+    ComputeModuleJobV2(ComputeModuleJob),
+}
+
+impl Job {
+    fn into_parts(self) -> (Schema, ComputeModuleJob) {
+        match self {
+            Job::ComputeModuleJobV1(job) => (Schema::V1, job),
+            Job::ComputeModuleJobV2(job) => (Schema::V2, job),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ComputeModuleJob {
+    job_id: String,
+    query_type: String,
+    query: Query,
+    /// Traces the job across services, logged with its lines and sent back with its result. Taken from the
+    /// `X-Correlation-ID` header the job came with when not set.
+    #[serde(default)]
+    correlation_id: Option<String>,
+}
+
+/// The job schema version, results are posted in the one their job came in.
+#[derive(Debug, Clone, Copy)]
+enum Schema {
+    /// The result as is.
+    V1,
+    /// The result wrapped with the job ID and whether it succeeded.
+    V2,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ComputeModuleResultV2<'a> {
+    job_id: &'a str,
+    status: &'static str,
+    result: &'a QueryResult,
+}
+
+// Jobs remembered by `Deliveries`, enough to cover redeliveries of the jobs in flight
+const REMEMBERED_JOBS: usize = 1024;
+
+/// The jobs the worker got lately, how often it got and failed each and the
+/// hash of the last result it posted.
+#[derive(Default)]
+pub struct Deliveries(std::sync::Mutex<VecDeque<Delivery>>);
+
+struct Delivery {
+    job_id: String,
+    attempt: u32,
+    posted: Option<String>,
+    failures: u32,
+    last_error: Option<ErrorReport>,
+}
+
+impl Deliveries {
+    // Counts another delivery of `job_id` and returns its number, from 1
+    fn start(&self, job_id: &str) -> u32 {
+        let mut deliveries = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = deliveries.iter().position(|delivery| delivery.job_id == job_id);
+        let delivery = match index.and_then(|index| deliveries.remove(index)) {
+            Some(delivery) => Delivery { attempt: delivery.attempt + 1, ..delivery },
+            None => Delivery { job_id: job_id.to_string(), attempt: 1, posted: None, failures: 0, last_error: None },
+        };
+        let attempt = delivery.attempt;
+        if deliveries.len() == REMEMBERED_JOBS {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(delivery);
+        attempt
+    }
+
+    fn posted(&self, job_id: &str) -> Option<String> {
+        let deliveries = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        deliveries.iter().find(|delivery| delivery.job_id == job_id).and_then(|delivery| delivery.posted.clone())
+    }
+
+    fn set_posted(&self, job_id: &str, hash: String) {
+        let mut deliveries = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(delivery) = deliveries.iter_mut().find(|delivery| delivery.job_id == job_id) {
+            delivery.posted = Some(hash);
+        }
+    }
+
+    fn set_failed(&self, job_id: &str, err: &JobError) {
+        let mut deliveries = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(delivery) = deliveries.iter_mut().find(|delivery| delivery.job_id == job_id) {
+            delivery.failures += 1;
+            delivery.last_error = Some(ErrorReport::from(err));
+        }
+    }
+
+    // How often `job_id` failed and why it failed last, once that is at least `max_failures` times
+    fn dead_letter(&self, job_id: &str, max_failures: u32) -> Option<(u32, ErrorReport)> {
+        let deliveries = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let delivery = deliveries.iter().find(|delivery| delivery.job_id == job_id)?;
+        let last_error = delivery.last_error.clone().filter(|_| delivery.failures >= max_failures)?;
+        Some((delivery.failures, last_error))
+    }
+}
+
+#[derive(Deserialize)]
+struct Query {
+    #[serde(flatten)]
+    image: ImageInput,
+    /// Presigned or `s3://` URL the annotated image is uploaded to instead of returned inline.
+    #[serde(default)]
+    img_out_url: Option<String>,
+    #[serde(flatten)]
+    overrides: JobOverrides,
+}
+
+// Images are either inlined as base64 or downloaded by the module, from presigned or `s3://` URLs. Batches
+// of inlined images are analyzed one by one and answered with a result per image.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImageInput {
+    Encoded { enc_img_in: Base64Image },
+    Url { img_url: String },
+    Batch { enc_imgs_in: Vec<String> },
+}
+
+pub struct Worker {
+    /// Fetches images, uploads annotated ones and polls for cancellation and progress.
+    pub client: ReloadingClient,
+    pub jobs: Arc<dyn JobSource>,
+    pub results: Arc<dyn ResultSink>,
+    /// Times a result is posted again, with the same idempotency key, when posting it failed.
+    pub post_result_retries: u32,
+    /// Failed attempts at a job after which it is answered with a dead-letter result instead of run again.
+    pub max_job_failures: u32,
+    pub deliveries: Deliveries,
+    /// Where results are kept that failed to post after every retry.
+    pub result_queue: ResultQueue,
+    pub result_queue_interval: Duration,
+    /// Wait before polling again when there was no job, doubled up to `poll_max_interval` while there is none.
+    pub poll_interval: Duration,
+    pub poll_max_interval: Duration,
+    pub module_auth_token: AuthToken,
+    pub concurrency: usize,
+    /// Jobs fetched ahead of the detection workers, 0 to only fetch when one is idle.
+    pub prefetch: usize,
+    /// Images of a batch job analyzed at once.
+    pub batch_concurrency: usize,
+    pub drain_timeout: Duration,
+    /// How long a job may run before it is failed with a "timeout" result.
+    pub job_timeout: Duration,
+    /// Polled with the job ID appended for whether the caller gave up on a job.
+    pub cancel_job_uri: Option<String>,
+    pub cancel_poll_interval: Duration,
+    /// Where the progress of running jobs is posted to with the job ID appended, if anywhere.
+    pub progress_uri: Option<String>,
+    pub progress_interval: Duration,
+    pub fetch_limits: FetchLimits,
+    pub s3: Option<S3>,
+    /// Where the spans of finished jobs are recorded, if anywhere.
+    pub tracer: Option<Arc<Tracer>>,
+    /// Add how long each step of a job took to its result.
+    pub stage_timings: bool,
+    /// Where the verdict of every job is appended to, if anywhere.
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Where the input, detector maps and annotated output of every job are saved to, if anywhere.
+    pub artifact_dir: Option<ArtifactDir>,
+    /// Verdicts of recent single image jobs, for answering resubmissions of the same image.
+    pub result_cache: Option<ResultCache>,
+    /// What results are signed with before they are recorded and posted, if anything.
+    pub signer: Option<ResultSigner>,
+    pub verdicts: Arc<VerdictCounts>,
+    pub status: Arc<WorkerStatus>,
+    pub pipeline: Pipeline,
+    pub handlers: Handlers,
+}
+
+// Polls again right away once there was a job, backing off while there are none
+async fn get_job(worker: &Worker) -> Result<(Schema, ComputeModuleJob), String> {
+    let mut delay = worker.poll_interval;
+    loop {
+        let job = worker.jobs.next_job().await?;
+        worker.status.polled();
+        if let Some(FetchedJob { document, correlation_id }) = job {
+            let job: Job = serde_json::from_slice(&document).map_err(|err| format!("Invalid job: {}", err))?;
+            let (schema, mut job) = job.into_parts();
+            job.correlation_id = job.correlation_id.or(correlation_id);
+            return Ok((schema, job));
+        }
+        debug!("No job found, trying again in {:?}", delay);
+        sleep(delay).await;
+        delay = (delay * 2).min(worker.poll_max_interval);
+    }
+}
+
+fn handle_query(
+    worker: &Worker,
+    job_id: &str,
+    query_type: &str,
+    settings: &JobSettings,
+    image_data: &[u8],
+) -> Result<QueryResult, JobError> {
+    let Some(handler) = worker.handlers.get(query_type) else {
+        return Ok(handlers::unsupported_query_type(query_type));
+    };
+    handlers::call(handler, &worker.pipeline, settings, job_id, image_data)
+}
+
+// Analyzes the images of a batch in up to `batch_concurrency` threads, images that fail don't fail the job
+fn handle_batch(
+    worker: &Worker,
+    job_id: &str,
+    query_type: &str,
+    settings: &JobSettings,
+    images: &[String],
+) -> Result<QueryResult, JobError> {
+    let Some(handler) = worker.handlers.get(query_type) else {
+        return Ok(handlers::unsupported_query_type(query_type));
+    };
+    let analyze = |index: usize| {
+        let image_job_id = format!("{} image {}", job_id, index + 1);
+        let result = decode_image_input(&images[index]).and_then(|image_data| {
+            let result = handlers::call(handler, &worker.pipeline, settings, &image_job_id, &image_data);
+            BUFFERS.give(image_data);
+            result
+        });
+        result.unwrap_or_else(|err| {
+            info!("{}: Failed to analyze: {}", image_job_id, err);
+            QueryResult { text: format!("{}\n", err), ..QueryResult::failed(&err) }
+        })
+    };
+    // Thread n takes images n, n + threads and so on, their results are put back in order after
+    let threads = worker.batch_concurrency.clamp(1, images.len().max(1));
+    let mut results: Vec<(usize, QueryResult)> = thread::scope(|scope| {
+        let analyze = &analyze;
+        let handles: Vec<_> = (0..threads)
+            .map(|first| {
+                scope.spawn(move || {
+                    let indices = (first..images.len()).step_by(threads);
+                    indices.map(|i| (i, analyze(i))).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let joined = handles.into_iter().map(|handle| handle.join().unwrap_or_else(|panic| panic::resume_unwind(panic)));
+        joined.flatten().collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    Ok(batch_result(results.into_iter().map(|(_, result)| result).collect(), settings.locale))
+}
+
+fn decode_image_input(enc_img_in: &str) -> Result<Vec<u8>, JobError> {
+    handlers::decode_base64(enc_img_in).map_err(JobError::Invalid)
+}
+
+// Failing to is only logged, the job goes on without
+fn save_artifacts(
+    artifact_dir: &ArtifactDir,
+    job_id: &str,
+    images: &Images,
+    artifacts: &Artifacts,
+    res: &Result<QueryResult, JobError>,
+) {
+    let inputs = match images {
+        Images::Single(image_data) => vec![(String::from("input"), image_data.clone())],
+        Images::Batch(images) => images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| Some((format!("input-{}", index + 1), decode_image_input(image).ok()?)))
+            .collect(),
+    };
+    let outputs = match res {
+        Ok(result) if result.batch.is_empty() => vec![(String::from("output"), &result.enc_img_out)],
+        Ok(result) => {
+            let outputs = result.batch.iter().enumerate();
+            outputs.map(|(index, result)| (format!("output-{}", index + 1), &result.enc_img_out)).collect()
+        }
+        Err(_) => Vec::new(),
+    };
+    let outputs = outputs.into_iter().filter(|(_, enc_img_out)| !enc_img_out.is_empty());
+    let outputs: Vec<(String, Vec<u8>)> =
+        outputs.filter_map(|(name, enc_img_out)| Some((name, decode_image_input(enc_img_out).ok()?))).collect();
+    let files: Vec<(String, &[u8])> =
+        inputs.iter().chain(&outputs).map(|(name, data)| (name.clone(), data.as_slice())).collect();
+    if let Err(err) = artifact_dir.save(job_id, &files, &artifacts.maps()) {
+        error!("{}: Failed to save debug artifacts: {}", job_id, err);
+    }
+}
+
+enum Images {
+    Single(Vec<u8>),
+    /// Still base64 encoded, decoding is left to the blocking detection task.
+    Batch(Vec<String>),
+}
+
+async fn image_data(worker: &Worker, job_id: &str, image: ImageInput) -> Result<Images, JobError> {
+    match image {
+        ImageInput::Encoded { enc_img_in } => enc_img_in.decoded().map(Images::Single).map_err(JobError::Invalid),
+        ImageInput::Url { img_url } => {
+            info!(job_id, stage = "fetch"; "{}: Fetching image from {}", job_id, img_url);
+            let fetched = fetch(&worker.client.current(), worker.s3.as_ref(), &img_url, worker.fetch_limits).await;
+            fetched.map(Images::Single).map_err(JobError::Transfer)
+        }
+        ImageInput::Batch { enc_imgs_in } if enc_imgs_in.is_empty() => {
+            Err(JobError::Invalid(String::from("Batch contains no images")))
+        }
+        ImageInput::Batch { enc_imgs_in } => Ok(Images::Batch(enc_imgs_in)),
+    }
+}
+
+// Moves the annotated image out of the result and into object storage
+async fn upload_output(worker: &Worker, job_id: &str, result: QueryResult, url: String) -> Result<QueryResult, JobError> {
+    // Dropped once decoded rather than kept through the upload
+    let enc_img_out = result.enc_img_out;
+    let mut image_data = Vec::with_capacity(enc_img_out.len() / 4 * 3);
+    let decoded = decode_base64_into(enc_img_out.as_bytes(), &mut image_data);
+    decoded.map_err(|err| JobError::Internal(err.to_string()))?;
+    drop(enc_img_out);
+    info!(job_id, stage = "upload"; "{}: Uploading {} byte annotated image to {}", job_id, image_data.len(), url);
+    let uploaded = upload(&worker.client.current(), worker.s3.as_ref(), &url, image_data, worker.fetch_limits).await;
+    uploaded.map_err(JobError::Transfer)?;
+    Ok(QueryResult { enc_img_out: String::new(), img_out_url: Some(url), ..result })
+}
+
+fn write_result(schema: Schema, job_id: &str, result: &QueryResult, writer: &mut dyn Write) -> serde_json::Result<()> {
+    match schema {
+        Schema::V1 => serde_json::to_writer(writer, result),
+        Schema::V2 => {
+            let status = match result.result.as_str() {
+                "Failed" | "unsupported" => "failed",
+                "cancelled" => "cancelled",
+                "timeout" => "timeout",
+                "dead_letter" => "dead_letter",
+                _ => "succeeded",
+            };
+            serde_json::to_writer(writer, &ComputeModuleResultV2 { job_id, status, result })
+        }
+    }
+}
+
+// Skipped when the worker already posted the same result for an earlier delivery of the job
+async fn post_result(
+    worker: &Worker,
+    schema: Schema,
+    job_id: &str,
+    attempt: u32,
+    correlation_id: Option<&str>,
+    result: QueryResult,
+) {
+    let id = job_id.to_string();
+    let body = ResultBody::new(move |writer| Ok(write_result(schema, &id, &result, writer)?));
+    // Serializes the whole result, images included
+    let hashed = body.clone();
+    let hash = match task::spawn_blocking(move || hashed.sha256()).await {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(err)) => {
+            error!("{}: Failed to serialize result: {}", job_id, err);
+            return;
+        }
+        Err(err) => {
+            error!("{}: Failed to serialize result: {}", job_id, err);
+            return;
+        }
+    };
+    if worker.deliveries.posted(job_id).as_ref() == Some(&hash) {
+        info!("{}: Already posted this result, skipping", job_id);
+        return;
+    }
+
+    let idempotency_key = format!("{}-{}-{}", job_id, attempt, hash);
+    let mut delay = Duration::from_secs(1);
+    for retry in 0..=worker.post_result_retries {
+        match worker.results.post_result(job_id, &idempotency_key, correlation_id, &body).await {
+            Ok(()) => {
+                info!(job_id, stage = "post"; "{}: Posted result", job_id);
+                worker.deliveries.set_posted(job_id, hash);
+                return;
+            }
+            Err(PostError::Transient(err)) if retry < worker.post_result_retries => {
+                let wait = jitter(delay);
+                error!(job_id, stage = "post"; "{}: {}, retrying in {:?}", job_id, err, wait);
+                sleep(wait).await;
+                delay *= 2;
+            }
+            // Kept for the queue delivery task rather than lost
+            Err(PostError::Transient(err)) => {
+                error!(job_id, stage = "post"; "{}: {}, queueing the result", job_id, err);
+                if let Err(err) = worker.result_queue.push(job_id, &idempotency_key, correlation_id, &body).await {
+                    error!("{}: Failed to queue result: {}", job_id, err);
+                }
+            }
+            Err(PostError::Rejected(err)) => {
+                error!(job_id, stage = "post"; "{}: {}", job_id, err);
+                return;
+            }
+        }
+    }
+}
+
+// Somewhere between half and one and a half of `delay`, so workers that failed together don't retry together
+fn jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    delay / 2 + delay.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+// Posts queued results every `result_queue_interval`, until aborted
+async fn deliver_queued(worker: Arc<Worker>) {
+    loop {
+        worker.result_queue.deliver(worker.results.as_ref()).await;
+        sleep(worker.result_queue_interval).await;
+    }
+}
+
+// Returns once `cancel_job_uri` answers 200 to say the caller gave up on the job, a 204 means it is still wanted
+async fn watch_cancellation(worker: &Worker, cancel_job_uri: &str, job_id: &str) {
+    let url = format!("{}/{}", cancel_job_uri, job_id);
+    loop {
+        sleep(worker.cancel_poll_interval).await;
+        let token = worker.module_auth_token.current();
+        let response = worker.client.current().get(&url).header("Module-Auth-Token", &token).send().await;
+        match response.map(|response| response.status()) {
+            Ok(StatusCode::OK) => {
+                info!("{}: Cancelled by the caller", job_id);
+                return;
+            }
+            Ok(StatusCode::NO_CONTENT) => {}
+            Ok(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) if worker.module_auth_token.refresh(&token) => {}
+            Ok(status) => error!("{}: Unexpected status code polling for cancellation: {}", job_id, status),
+            Err(err) => error!("{}: Failed to poll for cancellation: {}", job_id, err),
+        }
+    }
+}
+
+// With the job's `cancel` and `progress` in place of fresh ones. The hash of the input is set once it is read, for
+// the audit log.
+async fn run_job(
+    worker: Arc<Worker>,
+    job: ComputeModuleJob,
+    cancel: CancelToken,
+    progress: Progress,
+    input_sha256: Arc<OnceLock<String>>,
+) -> Result<QueryResult, JobError> {
+    let worker = &worker;
+    let ComputeModuleJob { job_id, query_type, query, .. } = job;
+    let settings = query.overrides.apply(&worker.pipeline).map_err(JobError::Invalid)?;
+    let artifacts = if worker.artifact_dir.is_some() { Artifacts::enabled() } else { Artifacts::default() };
+    let settings = JobSettings { cancel, progress: progress.clone(), artifacts, ..settings };
+    let detect_job_id = job_id.clone();
+    let detect_worker = worker.clone();
+
+    progress.set("fetching", 0);
+    let res = match image_data(worker, &job_id, query.image).await {
+        // Detection is CPU bound, keep it off the async executor threads
+        Ok(images) => task::spawn_blocking(move || {
+            let (worker, job_id) = (&detect_worker, &detect_job_id);
+            if worker.audit_log.is_some() || worker.result_cache.is_some() || worker.signer.is_some() {
+                // Batch images are hashed as received, they are only decoded one at a time while being analyzed
+                let hash = match &images {
+                    Images::Single(image_data) => audit::sha256(image_data),
+                    Images::Batch(images) => {
+                        let hashes: String = images.iter().map(|image| audit::sha256(image.as_bytes())).collect();
+                        audit::sha256(hashes.as_bytes())
+                    }
+                };
+                let _ = input_sha256.set(hash);
+            }
+            // Only single images are cached, batches rarely repeat as a whole
+            let cache = worker.result_cache.as_ref().filter(|_| matches!(images, Images::Single(_)));
+            let cache_key = cache.zip(input_sha256.get()).map(|(cache, hash)| cache.key(hash, &query_type, &settings));
+            let cached = cache.zip(cache_key.as_deref()).and_then(|(cache, key)| cache.get(key));
+            let res = match (&images, cached) {
+                (_, Some(result)) => {
+                    info!("{}: Answered from the result cache", job_id);
+                    Ok(result)
+                }
+                (Images::Single(image_data), None) => {
+                    let res = handle_query(worker, job_id, &query_type, &settings, image_data);
+                    // Only verdicts, failures may well not repeat
+                    let verdict = res.as_ref().ok();
+                    let verdict = verdict.filter(|result| !matches!(result.result.as_str(), "Failed" | "unsupported"));
+                    if let (Some(cache), Some(key), Some(result)) = (cache, &cache_key, verdict) {
+                        cache.put(key, result);
+                    }
+                    res
+                }
+                (Images::Batch(images), None) => handle_batch(worker, job_id, &query_type, &settings, images),
+            };
+            if let Some(artifact_dir) = &worker.artifact_dir {
+                save_artifacts(artifact_dir, job_id, &images, &settings.artifacts, &res);
+            }
+            if let Images::Single(image_data) = images {
+                BUFFERS.give(image_data);
+            }
+            res
+        })
+        .await
+        .unwrap_or_else(|err| Err(handlers::join_error(err))),
+        Err(err) => Err(err),
+    };
+    match (res, query.img_out_url) {
+        // Nothing to upload when the annotated image is skipped
+        (Ok(result), Some(img_out_url)) if !result.enc_img_out.is_empty() => {
+            progress.set("uploading", 100);
+            upload_output(worker, &job_id, result, img_out_url).await
+        }
+        (res, _) => res,
+    }
+}
+
+// Posts where the job got to every `progress_interval` until aborted, so slow jobs can be told from hung workers
+async fn report_progress(worker: Arc<Worker>, progress_uri: String, job_id: String, progress: Progress) {
+    let url = format!("{}/{}", progress_uri, job_id);
+    loop {
+        let update = progress.current();
+        debug!("{}: Progress {} {}%", job_id, update.stage, update.percent);
+        let token = worker.module_auth_token.current();
+        let response = worker.client.current().post(&url)
+            .header("Module-Auth-Token", &token)
+            .json(&update)
+            .send()
+            .await;
+        match response {
+            Ok(res) if matches!(res.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+                && worker.module_auth_token.refresh(&token) => {}
+            Ok(res) if !res.status().is_success() => error!("{}: Failed to post progress: {}", job_id, res.status()),
+            Ok(_) => {}
+            Err(err) => error!("{}: Error posting progress: {}", job_id, err),
+        }
+        sleep(worker.progress_interval).await;
+    }
+}
+
+// Posted unsigned when signing fails, which consumers checking signatures refuse like a tampered one
+async fn sign(worker: &Worker, job_id: &str, input_sha256: Option<&str>, result: QueryResult) -> QueryResult {
+    let Some(signer) = &worker.signer else {
+        return result;
+    };
+    match signer.sign(&worker.client.current(), job_id, input_sha256, &result).await {
+        Ok(signature) => QueryResult { signature: Some(signature), ..result },
+        Err(err) => {
+            error!(job_id, stage = "sign"; "{}: Failed to sign result: {}", job_id, err);
+            result
+        }
+    }
+}
+
+// Before the result is posted, so every verdict the caller may have seen is in the log
+async fn record_audit(worker: &Worker, job_id: &str, input_sha256: Option<String>, result: &QueryResult) {
+    let Some(audit_log) = worker.audit_log.clone() else {
+        return;
+    };
+    let regions = result.regions.len() + result.batch.iter().map(|image| image.regions.len()).sum::<usize>();
+    let (job_id, verdict) = (job_id.to_string(), result.result.clone());
+    let recorded = task::spawn_blocking(move || audit_log.record(&job_id, input_sha256.as_deref(), &verdict, regions));
+    match recorded.await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => error!("Failed to write audit log: {}", err),
+        Err(err) => error!("Failed to write audit log: {}", err),
+    }
+}
+
+async fn process_job(worker: &Arc<Worker>, schema: Schema, job: ComputeModuleJob) {
+    let (job_id, correlation_id) = (job.job_id.clone(), job.correlation_id.clone());
+    let correlation_id = correlation_id.as_deref();
+    let started = SystemTime::now();
+    let attempt = worker.deliveries.start(&job_id);
+    // Redelivered after failing every time, most likely failing again, so the caller is told to give up instead
+    if let Some((failures, last_error)) = worker.deliveries.dead_letter(&job_id, worker.max_job_failures) {
+        error!(
+            job_id = job_id.as_str(), verdict = "dead_letter";
+            "{}: Failed {} times, posting a dead-letter result", job_id, failures
+        );
+        let posting = SystemTime::now();
+        let result = sign(worker, &job_id, None, QueryResult::dead_letter(failures, last_error)).await;
+        worker.verdicts.record(&result.result);
+        record_audit(worker, &job_id, None, &result).await;
+        post_result(worker, schema, &job_id, attempt, correlation_id, result).await;
+        if let Some(tracer) = &worker.tracer {
+            tracer.record_job(&job_id, started, &[], posting, "dead_letter");
+        }
+        return;
+    }
+    let cancel = CancelToken::default();
+    let progress = Progress::default();
+    let input_sha256 = Arc::new(OnceLock::new());
+    let reporter = worker.progress_uri.clone().map(|progress_uri| {
+        tokio::spawn(report_progress(worker.clone(), progress_uri, job_id.clone(), progress.clone()))
+    });
+    // A task of its own, so a panic fails the job rather than the detection worker running it
+    let mut running = tokio::spawn(run_job(worker.clone(), job, cancel.clone(), progress.clone(), input_sha256.clone()));
+    let cancelled = async {
+        match &worker.cancel_job_uri {
+            Some(cancel_job_uri) => watch_cancellation(worker, cancel_job_uri, &job_id).await,
+            None => future::pending().await,
+        }
+    };
+    // Either way the caller is answered right away, detection stops before its next detector, page or file
+    let result = tokio::select! {
+        res = &mut running => res.unwrap_or_else(|err| {
+            let err = handlers::join_error(err);
+            error!(job_id = job_id.as_str(); "{}: {}", job_id, err);
+            Err(err)
+        }),
+        _ = cancelled => {
+            cancel.cancel();
+            Err(JobError::Cancelled)
+        }
+        _ = sleep(worker.job_timeout) => {
+            cancel.cancel();
+            let stage = progress.current().stage;
+            Err(JobError::TimedOut(format!("Timed out after {:?} in stage {}", worker.job_timeout, stage)))
+        }
+    };
+    running.abort();
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
+
+    let result = result.unwrap_or_else(|err| {
+        if !matches!(err, JobError::Cancelled) {
+            worker.deliveries.set_failed(&job_id, &err);
+        }
+        match err {
+            // With what the detectors that finished found
+            JobError::TimedOut(_) => {
+                error!(job_id = job_id.as_str(), verdict = "timeout"; "{}: {}", job_id, err);
+                let ProgressUpdate { detectors, scores, .. } = progress.current();
+                QueryResult { detectors, scores, ..QueryResult::failed(&err) }
+            }
+            _ => QueryResult::failed(&err),
+        }
+    });
+    let (posting, outcome) = (SystemTime::now(), result.result.clone());
+    let elapsed = started.elapsed().unwrap_or_default();
+    let stages = progress.current().stages;
+    let timings = telemetry::step_timings(&stages, posting);
+    let breakdown: Vec<String> = timings.iter().map(|(step, ms)| format!("{} {:.1}ms", step, ms)).collect();
+    info!(
+        job_id = job_id.as_str(), verdict = outcome.as_str(), duration_ms = elapsed.as_secs_f64() * 1000.0;
+        "{}: Finished as {} in {:?} ({})", job_id, outcome, elapsed, breakdown.join(", ")
+    );
+    let result = if worker.stage_timings {
+        QueryResult { timings: Some(timings.iter().map(|(step, ms)| (step.to_string(), *ms)).collect()), ..result }
+    } else {
+        result
+    };
+    let result = sign(worker, &job_id, input_sha256.get().map(String::as_str), result).await;
+    worker.verdicts.record(&result.result);
+    record_audit(worker, &job_id, input_sha256.get().cloned(), &result).await;
+    post_result(worker, schema, &job_id, attempt, correlation_id, result).await;
+    if let Some(tracer) = &worker.tracer {
+        tracer.record_job(&job_id, started, &stages, posting, &outcome);
+    }
+}
+
+// A job handed from the poll loop to a detection worker, with its log lines correlated and the slot it takes until it
+// is done
+type PickedUp = (Schema, ComputeModuleJob, Option<Correlated>, OwnedSemaphorePermit);
+
+async fn run_detection_worker(
+    id: usize,
+    worker: Arc<Worker>,
+    jobs: Arc<Mutex<mpsc::Receiver<PickedUp>>>,
+) {
+    loop {
+        // Only hold the lock while waiting for the next job, not while processing it
+        let next = jobs.lock().await.recv().await;
+        match next {
+            Some((schema, job, correlated, slot)) => {
+                debug!("Worker {} picked up {:?} job {}", id, schema, job.job_id);
+                worker.status.job_started();
+                process_job(&worker, schema, job).await;
+                worker.status.job_finished();
+                drop((correlated, slot));
+            }
+            None => return,
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
+}
+
+/// Polls for jobs until SIGTERM/SIGINT, then waits up to `drain_timeout` for
+/// jobs that were already picked up to finish and post their results.
+pub async fn run(worker: Worker) -> Result<(), Elapsed> {
+    let worker = Arc::new(worker);
+    let concurrency = worker.concurrency.max(1);
+    info!("Starting {} detection workers", concurrency);
+
+    // Every job takes a slot from being fetched until it is finished, so the poll loop fetches `prefetch` jobs
+    // ahead of busy workers, hiding the fetch behind detection, without hoarding jobs other instances could take.
+    let slots = Arc::new(Semaphore::new(concurrency + worker.prefetch));
+    let (sender, receiver) = mpsc::channel(concurrency + worker.prefetch);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let handles: Vec<_> = (0..concurrency)
+        .map(|id| tokio::spawn(run_detection_worker(id, worker.clone(), receiver.clone())))
+        .collect();
+    let queue_delivery = tokio::spawn(deliver_queued(worker.clone()));
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let slot = tokio::select! {
+            _ = &mut shutdown => break,
+            slot = slots.clone().acquire_owned() => slot.expect("The job slots are never closed"),
+        };
+        tokio::select! {
+            _ = &mut shutdown => break,
+            job = get_job(&worker) => match job {
+                Ok((schema, job)) => {
+                    let correlated = job.correlation_id.as_deref().map(|id| logging::correlate(&job.job_id, id));
+                    match &job.correlation_id {
+                        Some(id) => info!(job_id = job.job_id.as_str(); "Got {:?} job: {} ({})", schema, job.job_id, id),
+                        None => info!(job_id = job.job_id.as_str(); "Got {:?} job: {}", schema, job.job_id),
+                    }
+
+                    if sender.send((schema, job, correlated, slot)).await.is_err() {
+                        error!("All detection workers have stopped");
+                        break;
+                    }
+                }
+                Err(err) => {
+                    error!("Something failed: {}", err);
+                    sleep(Duration::from_secs(1)).await;
+                }
+            },
+        }
+    }
+
+    worker.status.drain();
+    info!("Stopped fetching jobs, draining in-flight jobs");
+    // Closing the channel lets workers finish queued jobs and then exit
+    drop(sender);
+    timeout(worker.drain_timeout, async {
+        for handle in handles {
+            let _ = handle.await;
+        }
+    })
+    .await?;
+    // Whatever is still queued is delivered after the next start
+    queue_delivery.abort();
+    info!("All in-flight jobs finished, shutting down");
+    Ok(())
+}
@@ -187,6 +187,13 @@ function translateDetectionResult(result: string | null) {
                 <IconAlertTriangle style={{color: "#f59f00"}}/>
             </div>
         );
+    } else if (result === "suspicious") {
+        return (
+            <div className={css.fraudResult}>
+                <span>Image may have been edited</span>
+                <IconAlertTriangle style={{color: "#f59f00"}}/>
+            </div>
+        );
     } else if (result === "cropped") {
         return (
             <div className={css.fraudResult}>